@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use common::{ default_resources::DEFAULT_RESOURCES};
+use crate::world::block_render::BlockRenderSettingsMap;
 use godot::{
     classes::{file_access::ModeFlags, DirAccess, FileAccess, Resource, ResourceLoader},
     obj::{Gd, Singleton},
@@ -12,6 +13,10 @@ pub struct LocalResourceManifest {
     pub slug: String,
     pub client_scripts: Option<Vec<String>>,
     pub media: Option<Vec<String>>,
+
+    /// Render settings of the blocks by slug
+    #[serde(default)]
+    pub blocks: BlockRenderSettingsMap,
 }
 
 #[derive(Default)]
@@ -19,6 +24,7 @@ pub(crate) struct LocalResource {
     pub slug: String,
     pub scripts: HashMap<String, String>,
     pub media: HashMap<String, Gd<Resource>>,
+    pub block_render_settings: BlockRenderSettingsMap,
 }
 
 pub(crate) fn get_local_resources() -> Result<Vec<LocalResource>, String> {
@@ -47,6 +53,7 @@ pub(crate) fn get_local_resources() -> Result<Vec<LocalResource>, String> {
 
         let mut resource = LocalResource {
             slug: manifest.slug.clone(),
+            block_render_settings: manifest.blocks,
             ..Default::default()
         };
 
@@ -8,7 +8,10 @@ use godot::{
 };
 use rhai::{serde::to_dynamic, Dynamic, Engine};
 
-use crate::utils::glb::glb_import;
+use crate::{
+    utils::glb::glb_import,
+    world::block_render::{is_block_render_settings_media, parse_block_render_settings, BlockRenderSettingsMap},
+};
 
 use super::{events::EmptyEvent, script_instance::ScriptInstance};
 use std::collections::HashMap;
@@ -33,6 +36,9 @@ pub struct ResourceInstance {
     scripts: Vec<ScriptInstance>,
     media: HashMap<String, MediaResource>,
 
+    // Render settings of the blocks by slug
+    block_render_settings: BlockRenderSettingsMap,
+
     #[allow(dead_code)]
     is_network: bool,
 }
@@ -69,6 +75,16 @@ impl ResourceInstance {
     }
 
     pub fn add_media_from_bytes(&mut self, media_slug: String, data: Vec<u8>) -> Result<(), String> {
+        if is_block_render_settings_media(&media_slug) {
+            let yaml = match String::from_utf8(data) {
+                Ok(y) => y,
+                Err(e) => return Err(format!("block render settings are not utf-8: {}", e)),
+            };
+            self.set_block_render_settings(parse_block_render_settings(&yaml)?);
+            log::debug!(target:"resources", "Resource \"{}\" block render settings \"{}\" loaded", self.slug, media_slug);
+            return Ok(());
+        }
+
         let resource = if media_slug.ends_with(".png") {
             let mut pba = PackedByteArray::new();
             pba.extend(data);
@@ -126,6 +142,14 @@ impl ResourceInstance {
         &self.slug
     }
 
+    pub fn set_block_render_settings(&mut self, block_render_settings: BlockRenderSettingsMap) {
+        self.block_render_settings.extend(block_render_settings);
+    }
+
+    pub fn get_block_render_settings(&self) -> &BlockRenderSettingsMap {
+        &self.block_render_settings
+    }
+
     pub fn has_media(&self, slug: &String) -> bool {
         self.media.contains_key(slug)
     }
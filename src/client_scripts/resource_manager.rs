@@ -24,6 +24,7 @@ use super::modules::main_api;
 use super::resource_instance::MediaResource;
 use super::resource_instance::ResourceInstance;
 use super::texture_image::TextureImage;
+use crate::world::block_render::BlockRenderSettingsMap;
 
 pub struct ResourceStorage {
     resources: HashMap<String, ResourceInstance>,
@@ -110,6 +111,26 @@ impl ResourceStorage {
         return None;
     }
 
+    /// Block render settings of all resource packs
+    ///
+    /// The same block can't be described by two packs
+    pub fn get_block_render_settings(&self) -> Result<BlockRenderSettingsMap, String> {
+        let mut result = BlockRenderSettingsMap::default();
+        let mut sources: HashMap<&String, &String> = Default::default();
+        for (resource_slug, resource) in self.resources.iter() {
+            for (block_slug, settings) in resource.get_block_render_settings().iter() {
+                if let Some(other) = sources.insert(block_slug, resource_slug) {
+                    return Err(format!(
+                        "&cblock &4\"{}\" &crender settings are defined by resources &4\"{}\" &cand &4\"{}\"",
+                        block_slug, other, resource_slug
+                    ));
+                }
+                result.insert(block_slug.clone(), settings.clone());
+            }
+        }
+        Ok(result)
+    }
+
     pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, ResourceInstance> {
         self.resources.iter()
     }
@@ -128,6 +149,9 @@ pub struct ResourceManager {
     resources_scheme: Option<Vec<ResurceScheme>>,
     archive_data: Option<Vec<u8>>,
     archive_hash: Option<u64>,
+
+    // Hash of the archive which resources are already loaded
+    loaded_archive_hash: Option<u64>,
 }
 
 enum ResourceType {
@@ -149,6 +173,7 @@ impl Default for ResourceManager {
             resources_scheme: Default::default(),
             archive_hash: Default::default(),
             archive_data: Default::default(),
+            loaded_archive_hash: Default::default(),
         }
     }
 }
@@ -175,6 +200,12 @@ impl ResourceManager {
         self.archive_hash.as_ref()
     }
 
+    /// Resources of this archive are already loaded
+    /// (the same session is restored after reconnect)
+    pub fn is_archive_loaded(&self, archive_hash: &u64) -> bool {
+        self.loaded_archive_hash.as_ref() == Some(archive_hash)
+    }
+
     pub fn get_resource_scheme_count(&mut self) -> (usize, usize) {
         let mut scripts_count: usize = 0;
         let mut media_count: usize = 0;
@@ -260,7 +291,9 @@ impl ResourceManager {
 
     pub fn load_local_archive(&mut self, archive_hash: &u64) -> Result<u32, String> {
         let path = ResourceManager::get_saved_resource_path(archive_hash).unwrap();
-        self.load_archive(File::open(path).unwrap())
+        let count = self.load_archive(File::open(path).unwrap())?;
+        self.loaded_archive_hash = Some(archive_hash.clone());
+        Ok(count)
     }
 
     fn load_archive<R: Read + Seek>(&mut self, reader: R) -> Result<u32, String> {
@@ -339,6 +372,7 @@ impl ResourceManager {
         let mut resource_names: Vec<String> = Default::default();
         for mut local_resource in local_resources {
             let mut resource_instance = ResourceInstance::new(local_resource.slug.clone(), false);
+            resource_instance.set_block_render_settings(std::mem::take(&mut local_resource.block_render_settings));
 
             for (script_slug, script_code) in local_resource.scripts.drain() {
                 resource_instance.add_script(&mut self.rhai_engine.borrow_mut(), script_slug, script_code)?;
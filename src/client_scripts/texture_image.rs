@@ -1,7 +1,9 @@
-use common::blocks::block_type::BlockColor;
-use godot::builtin::PackedByteArray;
-use image::{DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Pixel, Rgba, RgbaImage};
-use std::io::Cursor;
+use godot::{
+    builtin::{Array, PackedByteArray},
+    classes::{image::Format, Image, ImageTexture, Texture2DArray},
+    obj::{Gd, NewGd},
+};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgba, RgbaImage};
 
 pub struct TextureImage {
     image: DynamicImage,
@@ -22,7 +24,7 @@ impl TextureImage {
         &self.image
     }
 
-    pub fn change_color_balance(&mut self, color: &BlockColor) -> Self {
+    pub fn change_color_balance(&self, color: [u8; 3]) -> Self {
         let tint_color = Rgba([color[0], color[1], color[2], 255]);
 
         let (width, height) = self.image.dimensions();
@@ -42,6 +44,47 @@ impl TextureImage {
         Self { image: output.into() }
     }
 
+    /// Frames of the vertical strip from the top
+    pub fn split_frames(&self, frames: u32) -> Vec<Self> {
+        let (width, height) = self.image.dimensions();
+        let frame_height = (height / frames.max(1)).max(1);
+        (0..height / frame_height)
+            .map(|i| Self {
+                image: self.image.crop_imm(0, i * frame_height, width, frame_height),
+            })
+            .collect()
+    }
+
+    /// Hides the borders from the mask: left, right, top, bottom;
+    /// they are replaced by the middle of the texture, so the same blocks look like one
+    pub fn connect_borders(&self, mask: u8) -> Self {
+        let mut image = self.image.to_rgba8();
+        let (width, height) = image.dimensions();
+        let border = (width / 16).max(1);
+        for side in 0..4 {
+            if mask & (1 << side) == 0 {
+                continue;
+            }
+            let length = match side < 2 {
+                true => height,
+                false => width,
+            };
+            for a in 0..border {
+                for b in 0..length {
+                    let (x, y, source) = match side {
+                        0 => (a, b, (width / 2, b)),
+                        1 => (width - 1 - a, b, (width / 2, b)),
+                        2 => (b, a, (b, height / 2)),
+                        _ => (b, height - 1 - a, (b, height / 2)),
+                    };
+                    let pixel = *image.get_pixel(source.0, source.1);
+                    image.put_pixel(x, y, pixel);
+                }
+            }
+        }
+        Self { image: image.into() }
+    }
+
     pub fn overlay_on_top(&mut self, image: &TextureImage) -> Self {
         let mut background = self.image.clone();
 
@@ -51,28 +94,247 @@ impl TextureImage {
     }
 }
 
-/// Generates one big texture, which is contains all block textures
+/// Pages of the godot texture array can't be more on most of the GPUs
+const MAX_TEXTURE_LAYERS: usize = 2048;
+
+/// Block textures on the gpu
+pub struct TerrainTextures {
+    pub albedo: Gd<Texture2DArray>,
+
+    /// Two pixels per layer: frames count, frame time and page of the layer in the first row,
+    /// rect of the layer inside the page in the second one
+    pub layers: Gd<ImageTexture>,
+}
+
+/// Collects all block textures as the layers of the texture array pages
+///
+/// Textures are grouped by their size: every page is filled with the textures of one size,
+/// so the textures keep their resolution and are never scaled. The page is as big as the largest texture.
 pub(crate) struct TexturePack {
-    img: RgbaImage,
+    layers: Vec<RgbaImage>,
+
+    // First layer of the animation, frames count and frame time
+    animations: Vec<(usize, u32, f32)>,
+}
+
+/// Place of the layer: page and position with the size in pixels
+struct LayerPlace {
+    page: usize,
+    rect: [u32; 4],
 }
 
 impl TexturePack {
     pub(crate) fn create() -> Self {
-        let size = 16 * 32;
-        let img: RgbaImage = ImageBuffer::new(size, size);
-        Self { img }
+        Self {
+            layers: Default::default(),
+            animations: Default::default(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Returns the layer of the image
+    pub(crate) fn add_subimage(&mut self, image: &TextureImage) -> usize {
+        self.layers.push(image.get_source().to_rgba8());
+        self.layers.len() - 1
+    }
+
+    /// Frames are placed in the layers one after another; returns the first layer
+    pub(crate) fn add_animation(&mut self, frames: &[TextureImage], frame_time: f32) -> usize {
+        let first = self.layers.len();
+        for frame in frames.iter() {
+            self.add_subimage(frame);
+        }
+        self.animations.push((first, frames.len() as u32, frame_time));
+        first
     }
 
-    pub(crate) fn add_subimage(&mut self, image: &TextureImage, index: i64) {
-        let offset_x = 16 * (index % 32_i64);
-        let offset_y = 16 * (index as f64 / 32_f64).floor() as i64;
+    pub(crate) fn generate(&self) -> Result<TerrainTextures, String> {
+        let page_size = self.get_page_size();
+        let places = self.place_layers(page_size);
+        let pages_count = get_pages_count(&places);
+        if pages_count > MAX_TEXTURE_LAYERS {
+            return Err(format!(
+                "texture pages count {} is more than the limit {}",
+                pages_count, MAX_TEXTURE_LAYERS
+            ));
+        }
+        Ok(TerrainTextures {
+            albedo: self.generate_albedo(page_size, &places)?,
+            layers: self.generate_layers(page_size, &places)?,
+        })
+    }
+
+    fn get_page_size(&self) -> u32 {
+        self.layers
+            .iter()
+            .map(|layer| layer.width().max(layer.height()))
+            .max()
+            .unwrap_or(1)
+    }
 
-        image::imageops::overlay(&mut self.img, image.get_source(), offset_x, offset_y);
+    /// Layers of the same size are placed in rows on the shared pages
+    fn place_layers(&self, page_size: u32) -> Vec<LayerPlace> {
+        let mut sizes: Vec<(u32, u32)> = self.layers.iter().map(|layer| layer.dimensions()).collect();
+        sizes.sort_unstable();
+        sizes.dedup();
+
+        let mut places: Vec<Option<LayerPlace>> = self.layers.iter().map(|_| None).collect();
+        let mut pages = 0;
+        for (width, height) in sizes {
+            let columns = page_size / width;
+            let per_page = columns * (page_size / height);
+            let mut index = 0;
+            for (i, layer) in self.layers.iter().enumerate() {
+                if layer.dimensions() != (width, height) {
+                    continue;
+                }
+                if index == per_page {
+                    index = 0;
+                    pages += 1;
+                }
+                let (x, y) = (index % columns * width, index / columns * height);
+                places[i] = Some(LayerPlace {
+                    page: pages,
+                    rect: [x, y, width, height],
+                });
+                index += 1;
+            }
+            pages += 1;
+        }
+        places.into_iter().flatten().collect()
     }
 
-    pub(crate) fn generate(&self) -> Vec<u8> {
-        let mut b: Vec<u8> = Vec::new();
-        self.img.write_to(&mut Cursor::new(&mut b), ImageFormat::Png).unwrap();
-        b.to_vec()
+    fn generate_albedo(&self, page_size: u32, places: &[LayerPlace]) -> Result<Gd<Texture2DArray>, String> {
+        let pages = generate_pages(&self.layers, page_size, places);
+
+        let mut images: Array<Gd<Image>> = Array::new();
+        for levels in pages.iter() {
+            let bytes: Vec<u8> = levels.iter().flat_map(|level| level.as_raw().iter().copied()).collect();
+            let data = PackedByteArray::from(bytes.as_slice());
+            let Some(image) = Image::create_from_data(page_size as i32, page_size as i32, true, Format::RGBA8, &data)
+            else {
+                return Err("texture page image can't be created".to_string());
+            };
+            images.push(&image);
+        }
+
+        let mut texture = Texture2DArray::new_gd();
+        if pages.is_empty() {
+            return Ok(texture);
+        }
+        let result = texture.create_from_images(&images);
+        if result != godot::global::Error::OK {
+            return Err(format!("texture array creation error: {:?}", result));
+        }
+        Ok(texture)
+    }
+
+    /// Static layers are one frame animations
+    fn generate_layers(&self, page_size: u32, places: &[LayerPlace]) -> Result<Gd<ImageTexture>, String> {
+        let width = self.layers.len().max(1);
+        let mut pixels: Vec<[f32; 4]> = vec![[1.0, 0.0, 0.0, 0.0]; width * 2];
+        for (i, place) in places.iter().enumerate() {
+            pixels[i][2] = place.page as f32;
+            pixels[width + i] = place.rect.map(|v| v as f32 / page_size as f32);
+        }
+        for (first, frames, frame_time) in self.animations.iter() {
+            pixels[*first][0] = *frames as f32;
+            pixels[*first][1] = *frame_time;
+        }
+        let bytes: Vec<u8> = pixels
+            .iter()
+            .flat_map(|p| p.iter().flat_map(|v| v.to_le_bytes()))
+            .collect();
+
+        let data = PackedByteArray::from(bytes.as_slice());
+        let Some(image) = Image::create_from_data(width as i32, 2, false, Format::RGBAF, &data) else {
+            return Err("texture layers image can't be created".to_string());
+        };
+        match ImageTexture::create_from_image(&image) {
+            Some(texture) => Ok(texture),
+            None => Err("texture layers can't be created".to_string()),
+        }
+    }
+}
+
+fn get_pages_count(places: &[LayerPlace]) -> usize {
+    places.iter().map(|place| place.page + 1).max().unwrap_or(0)
+}
+
+/// Sizes of the mipmap levels in the same order as godot expects them
+fn get_mipmap_sizes(size: u32) -> Vec<u32> {
+    let mut sizes = vec![size];
+    let mut size = size;
+    while size > 1 {
+        size = (size / 2).max(1);
+        sizes.push(size);
+    }
+    sizes
+}
+
+/// Mipmap levels of every page
+///
+/// Mipmaps are generated for every layer separately and placed on the page like the layer itself;
+/// mipmaps of the whole page would mix the colors of the neighbour layers.
+fn generate_pages(layers: &[RgbaImage], page_size: u32, places: &[LayerPlace]) -> Vec<Vec<RgbaImage>> {
+    let sizes = get_mipmap_sizes(page_size);
+    let mut pages: Vec<Vec<RgbaImage>> = (0..get_pages_count(places))
+        .map(|_| sizes.iter().map(|size| RgbaImage::new(*size, *size)).collect())
+        .collect();
+
+    for (layer, place) in layers.iter().zip(places.iter()) {
+        let mut level_image = layer.clone();
+        for level in 0..sizes.len() {
+            let [x, y, width, height] = place.rect.map(|v| v >> level);
+            if level > 0 {
+                level_image = image::imageops::resize(&level_image, width.max(1), height.max(1), FilterType::Triangle);
+            }
+            image::imageops::replace(&mut pages[place.page][level], &level_image, x as i64, y as i64);
+        }
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{generate_pages, get_mipmap_sizes, LayerPlace};
+
+    #[test]
+    fn mipmap_sizes() {
+        assert_eq!(get_mipmap_sizes(16), vec![16, 8, 4, 2, 1]);
+        assert_eq!(get_mipmap_sizes(1), vec![1]);
+    }
+
+    #[test]
+    fn mipmaps_keep_layer_colors() {
+        let red = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]));
+        let places = vec![
+            LayerPlace {
+                page: 0,
+                rect: [0, 0, 4, 4],
+            },
+            LayerPlace {
+                page: 0,
+                rect: [4, 0, 4, 4],
+            },
+        ];
+        let pages = generate_pages(&[red, blue], 8, &places);
+        assert_eq!(pages.len(), 1);
+
+        // Second level: every layer is 2x2
+        let level = &pages[0][1];
+        assert_eq!(level.dimensions(), (4, 4));
+        for y in 0..2 {
+            assert_eq!(*level.get_pixel(0, y), Rgba([255, 0, 0, 255]));
+            assert_eq!(*level.get_pixel(1, y), Rgba([255, 0, 0, 255]));
+            assert_eq!(*level.get_pixel(2, y), Rgba([0, 0, 255, 255]));
+            assert_eq!(*level.get_pixel(3, y), Rgba([0, 0, 255, 255]));
+        }
     }
 }
@@ -127,6 +127,11 @@ impl PlayerController {
         }
     }
 
+    /// Collider belongs to the world physics, so it must be removed with the player
+    pub fn remove_collider(&mut self) {
+        self.collider.remove();
+    }
+
     pub fn set_frozen(&self, state: bool) {
         self.frozen.store(state, Ordering::Relaxed);
     }
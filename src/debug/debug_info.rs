@@ -11,6 +11,7 @@ use std::sync::{
     Arc,
 };
 
+use crate::network::transport::NetworkQueueStats;
 use crate::world::{chunks::chunks_map::LIMIT_CHUNK_LOADING_AT_A_TIME, worlds_manager::WorldsManager};
 use common::chunks::block_position::{BlockPosition, BlockPositionTrait};
 use network::client::NetworkInfo;
@@ -50,7 +51,7 @@ macro_rules! debug_world_string {
         "[b]World: [color=#6FA8FF]{world_slug}[/color][/b]
 [color=#B3B3B3]Position:[/color] {controller_positioin}
 [color=#B3B3B3]Character state:[/color] {current_animation}
-[color=#B3B3B3]Chunks:[/color] {chunks_count} [color=#B3B3B3]loading: [/color]{chunks_loading}/{loading_limit} [color=#B3B3B3]waiting: [/color]{chunks_waiting}
+[color=#B3B3B3]Chunks:[/color] {chunks_count} [color=#B3B3B3]loading: [/color]{chunks_loading}/{loading_limit} [color=#B3B3B3]waiting: [/color]{chunks_waiting} [color=#B3B3B3]remesh: [/color]{chunks_remesh}
 [color=#B3B3B3]Chunk position:[/color] {chunk_pos}
 [color=#B3B3B3]Chunk info:[/color] {chunk_info}
 [color=#B3B3B3]Look at:[/color] {look_at_message}
@@ -62,7 +63,16 @@ macro_rules! debug_network_string {
         "[b]Network connected: {is_connected}[/b]
 [color=#B3B3B3]Received:[/color] {received_per_sec:.1} KB/sec
 [color=#B3B3B3]Sent:[/color] {sent_per_sec:.1} KB/sec
-[color=#B3B3B3]Packet loss:[/color] {packet_loss:.1}"
+[color=#B3B3B3]Packet loss:[/color] {packet_loss:.1}
+[color=#B3B3B3]Pending messages:[/color] {pending_messages}"
+    };
+}
+macro_rules! debug_network_queue_string {
+    () => {
+        "
+[color=#B3B3B3]Tick rate:[/color] {tick_rate}
+[color=#B3B3B3]Incoming queue:[/color] {incoming}/{incoming_capacity} [color=#B3B3B3]peak: [/color]{incoming_peak}
+[color=#B3B3B3]Outgoing queue:[/color] {outgoing}/{outgoing_capacity} [color=#B3B3B3]dropped: [/color]{outgoing_dropped}"
     };
 }
 
@@ -77,6 +87,8 @@ pub struct DebugInfo {
     pub(crate) worlds_manager: Option<Gd<WorldsManager>>,
 
     network_info: Option<NetworkInfo>,
+    queue_stats: Option<NetworkQueueStats>,
+    pending_messages: usize,
 
     accum_time: f64,
 }
@@ -104,6 +116,14 @@ impl DebugInfo {
     pub fn set_network_info(&mut self, network_info: NetworkInfo) {
         self.network_info = Some(network_info)
     }
+
+    pub fn set_pending_messages(&mut self, pending_messages: usize) {
+        self.pending_messages = pending_messages
+    }
+
+    pub fn set_queue_stats(&mut self, queue_stats: Option<NetworkQueueStats>) {
+        self.queue_stats = queue_stats
+    }
 }
 
 #[godot_api]
@@ -116,6 +136,8 @@ impl IMarginContainer for DebugInfo {
             network_row: DebugInfo::load_row(),
             worlds_manager: None,
             network_info: None,
+            queue_stats: None,
+            pending_messages: 0,
             accum_time: 0.0,
         }
     }
@@ -207,6 +229,7 @@ impl IMarginContainer for DebugInfo {
                     chunks_loading = chunk_map.get_loading_chunks_count(),
                     loading_limit = LIMIT_CHUNK_LOADING_AT_A_TIME,
                     chunks_waiting = chunk_map.get_waiting_chunks_count(),
+                    chunks_remesh = chunk_map.get_remesh_queue_len(),
                     chunk_pos = chunk_pos,
                     chunk_info = chunk_info,
                     look_at_message = player_controller.get_look_at_message(),
@@ -217,13 +240,26 @@ impl IMarginContainer for DebugInfo {
         DebugInfo::change_text(&self.world_row, world_text);
 
         if let Some(network_info) = self.network_info.as_ref() {
-            let network_text = format!(
+            let mut network_text = format!(
                 debug_network_string!(),
                 is_connected = !network_info.is_disconnected,
                 received_per_sec = network_info.bytes_received_per_sec / 1024.0,
                 sent_per_sec = network_info.bytes_sent_per_sec / 1024.0,
                 packet_loss = network_info.packet_loss / 1024.0,
+                pending_messages = self.pending_messages,
             );
+            if let Some(stats) = self.queue_stats.as_ref() {
+                network_text += &format!(
+                    debug_network_queue_string!(),
+                    tick_rate = stats.tick_rate,
+                    incoming = stats.incoming,
+                    incoming_capacity = stats.incoming_capacity,
+                    incoming_peak = stats.incoming_peak,
+                    outgoing = stats.outgoing,
+                    outgoing_capacity = stats.outgoing_capacity,
+                    outgoing_dropped = stats.outgoing_dropped,
+                );
+            }
             DebugInfo::change_text(&self.network_row, network_text);
         }
     }
@@ -6,7 +6,8 @@ use network::{
     messages::{NetworkEntitySkin, NetworkEntityTag},
 };
 
-use super::entity::Entity;
+use super::{entity::Entity, interpolation::SnapshotBuffer};
+use std::time::{Duration, Instant};
 
 #[derive(GodotClass)]
 #[class(no_init, base=Node3D)]
@@ -14,6 +15,9 @@ pub struct EntitiesManager {
     pub base: Base<Node3D>,
 
     entities: AHashMap<u32, Gd<Entity>>,
+
+    // Server snapshots of each entity for the interpolation
+    snapshots: AHashMap<u32, SnapshotBuffer>,
 }
 
 impl EntitiesManager {
@@ -21,6 +25,7 @@ impl EntitiesManager {
         Self {
             base,
             entities: Default::default(),
+            snapshots: Default::default(),
         }
     }
 
@@ -28,9 +33,12 @@ impl EntitiesManager {
         self.entities.get(&entity_id)
     }
 
+    /// `tick_interval` is the shortest time between the entity moves of the transport
     pub fn create_entity(
         &mut self,
         id: u32,
+        time: Instant,
+        tick_interval: Duration,
         position: Vector3,
         rotation: Rotation,
         components: Vec<EntityNetworkComponent>,
@@ -45,8 +53,12 @@ impl EntitiesManager {
 
         self.entities.insert(id, entity.clone());
 
+        let mut snapshots = SnapshotBuffer::new(tick_interval);
+        snapshots.push(time, position, rotation);
+        self.snapshots.insert(id, snapshots);
+
         let mut e = entity.bind_mut();
-        e.change_position(position);
+        e.base_mut().set_position(position);
         e.rotate(rotation);
 
         log::info!(target: "entities", "SPAWN id:{}", id);
@@ -72,15 +84,20 @@ impl EntitiesManager {
         e.change_skin(skin);
     }
 
-    pub fn move_entity(&mut self, id: u32, position: Vector3, rotation: Rotation) {
-        let Some(entity) = self.entities.get_mut(&id) else {
+    /// Position is applied in process with the interpolation delay
+    ///
+    /// `time` is when the transport recieved the move
+    pub fn move_entity(&mut self, id: u32, time: Instant, position: Vector3, rotation: Rotation) {
+        let Some(snapshots) = self.snapshots.get_mut(&id) else {
             log::error!(target:"entities", "Tried to move non-existent entity id:{}", id);
             return;
         };
+        snapshots.push(time, position, rotation);
+    }
 
-        let mut e = entity.bind_mut();
-        e.change_position(position);
-        e.rotate(rotation);
+    pub fn despawn_all(&mut self) {
+        let ids: Vec<u32> = self.entities.keys().cloned().collect();
+        self.despawn(ids);
     }
 
     pub fn despawn(&mut self, ids: Vec<u32>) {
@@ -89,8 +106,32 @@ impl EntitiesManager {
                 log::error!(target:"entities", "Tried to despawn non-exitent entity id:{}", id);
                 continue;
             };
+            self.snapshots.remove(id);
             log::info!(target: "entities", "despawn id: {}", id);
             e.bind_mut().base_mut().queue_free();
         }
     }
 }
+
+#[godot_api]
+impl INode3D for EntitiesManager {
+    fn process(&mut self, _delta: f64) {
+        #[cfg(feature = "trace")]
+        let _span = tracy_client::span!("entities_manager.process");
+
+        let _span = crate::span!("entities_manager.process");
+
+        let now = Instant::now();
+        for (id, snapshots) in self.snapshots.iter_mut() {
+            let Some(state) = snapshots.sample(now) else {
+                continue;
+            };
+            let Some(entity) = self.entities.get_mut(id) else {
+                continue;
+            };
+            let mut e = entity.bind_mut();
+            e.change_position(state.position);
+            e.rotate(state.rotation);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use common::chunks::rotation::Rotation;
-use godot::{global::lerp, prelude::*};
+use godot::prelude::*;
 use network::{
     entities::EntityNetworkComponent,
     messages::{NetworkEntitySkin, NetworkEntityTag},
@@ -20,7 +20,6 @@ pub struct Entity {
 
     skin: EntitySkinContainer,
     tag: Option<Gd<EntityTag>>,
-    target_position: Option<Vector3>,
 }
 
 impl Entity {
@@ -52,7 +51,6 @@ impl Entity {
             pitch: 0.0,
             skin: skin_container,
             tag,
-            target_position: Default::default(),
         }
     }
 
@@ -101,8 +99,11 @@ impl Entity {
         unimplemented!("change_skin is not implemented");
     }
 
+    /// Position from the network sync; moves the skin animation
     pub fn change_position(&mut self, position: Vector3) {
-        self.target_position = Some(position);
+        let old_position = self.base().get_position();
+        self.handle_movement(position - old_position);
+        self.base_mut().set_position(position);
     }
 
     pub fn rotate(&mut self, rotation: Rotation) {
@@ -150,35 +151,4 @@ impl INode3D for Entity {
             base.add_child(tag);
         }
     }
-
-    fn process(&mut self, _delta: f64) {
-        #[cfg(feature = "trace")]
-        let _span = tracy_client::span!("entity.process");
-
-        let _span = crate::span!("entity.process");
-
-        // target_position is onlt for network sync
-        if let Some(target_position) = self.target_position {
-            let current_position = self.base().get_position();
-
-            if current_position.distance_to(target_position) >= 10.0 {
-                self.base_mut().set_position(target_position);
-                self.target_position = None;
-            }
-
-            let l = lerp(
-                &current_position.to_variant(),
-                &target_position.to_variant(),
-                &(0.5).to_variant(),
-            );
-            let new_position = Vector3::from_variant(&l);
-            let old_position = self.base_mut().get_position();
-            self.handle_movement(new_position - old_position);
-            self.base_mut().set_position(new_position);
-
-            if new_position == target_position {
-                self.target_position = None;
-            }
-        }
-    }
 }
@@ -0,0 +1,230 @@
+use common::chunks::rotation::Rotation;
+use godot::prelude::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Entities are rendered this much in the past
+/// so there are two server snapshots to interpolate between
+pub const INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+
+/// How long the movement continues when snapshots are late
+pub const MAX_EXTRAPOLATION: Duration = Duration::from_millis(200);
+
+/// After the extrapolation the entity returns to the last snapshot during this time
+pub const EXTRAPOLATION_RECOVERY: Duration = Duration::from_millis(200);
+
+/// Snapshots farther than this are teleports and are not interpolated
+pub const TELEPORT_DISTANCE: f32 = 10.0;
+
+const SNAPSHOTS_LIMIT: usize = 32;
+
+#[derive(Clone, Copy)]
+struct EntitySnapshot {
+    time: Instant,
+    position: Vector3,
+    yaw: f32,
+    pitch: f32,
+}
+
+pub struct InterpolatedState {
+    pub position: Vector3,
+    pub rotation: Rotation,
+}
+
+/// Shortest way between two angles in degrees
+fn lerp_degrees(from: f32, to: f32, weight: f32) -> f32 {
+    let difference = (to - from + 180.0).rem_euclid(360.0) - 180.0;
+    from + difference * weight
+}
+
+/// Timestamped server snapshots of the remote entity
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<EntitySnapshot>,
+
+    // Snapshots recieved closer than the transport tick came in one burst;
+    // their velocity is not trusted
+    min_interval: Duration,
+}
+
+impl SnapshotBuffer {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            snapshots: Default::default(),
+            min_interval,
+        }
+    }
+
+    /// `time` is when the transport recieved the snapshot
+    pub fn push(&mut self, time: Instant, position: Vector3, rotation: Rotation) {
+        if let Some(last) = self.snapshots.back() {
+            if last.position.distance_to(position) >= TELEPORT_DISTANCE {
+                self.snapshots.clear();
+            }
+        }
+
+        // Messages are handled in order, but the time must never go back
+        let time = match self.snapshots.back() {
+            Some(last) => time.max(last.time),
+            None => time,
+        };
+        self.snapshots.push_back(EntitySnapshot {
+            time,
+            position,
+            yaw: rotation.yaw,
+            pitch: rotation.pitch,
+        });
+        if self.snapshots.len() > SNAPSHOTS_LIMIT {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// State of the entity at `now - INTERPOLATION_DELAY`
+    pub fn sample(&mut self, now: Instant) -> Option<InterpolatedState> {
+        let render_time = now.checked_sub(INTERPOLATION_DELAY).unwrap_or(now);
+
+        // Keep the last snapshot before the render time and all after it
+        while self.snapshots.len() > 2 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+
+        let (from, to) = match self.snapshots.len() {
+            0 => return None,
+            1 => {
+                let s = &self.snapshots[0];
+                return Some(InterpolatedState {
+                    position: s.position,
+                    rotation: Rotation::new(s.yaw, s.pitch),
+                });
+            }
+            _ => (self.snapshots[0], self.snapshots[1]),
+        };
+
+        if render_time <= from.time {
+            return Some(InterpolatedState {
+                position: from.position,
+                rotation: Rotation::new(from.yaw, from.pitch),
+            });
+        }
+
+        let interval = to.time.duration_since(from.time);
+        if render_time < to.time {
+            let weight = render_time.duration_since(from.time).as_secs_f32() / interval.as_secs_f32();
+            return Some(InterpolatedState {
+                position: from.position.lerp(to.position, weight),
+                rotation: Rotation::new(
+                    lerp_degrees(from.yaw, to.yaw, weight),
+                    lerp_degrees(from.pitch, to.pitch, weight),
+                ),
+            });
+        }
+
+        // Snapshots are late: continue the last known velocity for a while
+        let velocity = match interval > Duration::ZERO && interval >= self.min_interval {
+            true => (to.position - from.position) / interval.as_secs_f32(),
+            false => Vector3::ZERO,
+        };
+        let overtime = render_time.duration_since(to.time);
+        let overshoot = velocity * overtime.min(MAX_EXTRAPOLATION).as_secs_f32();
+
+        // Moves stopped: ease back instead of staying overshot
+        let recovery = overtime.saturating_sub(MAX_EXTRAPOLATION).as_secs_f32() / EXTRAPOLATION_RECOVERY.as_secs_f32();
+        Some(InterpolatedState {
+            position: to.position + overshoot * (1.0 - recovery.min(1.0)),
+            rotation: Rotation::new(to.yaw, to.pitch),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn moved_buffer(start: Instant) -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::new(TICK);
+        buffer.push(start, Vector3::ZERO, Rotation::new(0.0, 0.0));
+        buffer.push(start + TICK, Vector3::new(1.0, 0.0, 0.0), Rotation::new(90.0, 0.0));
+        buffer
+    }
+
+    /// Position x when the render time is `after` from the start
+    fn sample_x(buffer: &mut SnapshotBuffer, start: Instant, after: Duration) -> f32 {
+        buffer.sample(start + INTERPOLATION_DELAY + after).unwrap().position.x
+    }
+
+    fn assert_near(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 0.001, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn interpolation() {
+        let start = Instant::now();
+        let mut buffer = moved_buffer(start);
+
+        assert_near(sample_x(&mut buffer, start, Duration::ZERO), 0.0);
+
+        let state = buffer.sample(start + INTERPOLATION_DELAY + TICK / 2).unwrap();
+        assert_near(state.position.x, 0.5);
+        assert_near(state.rotation.yaw, 45.0);
+
+        assert_near(sample_x(&mut buffer, start, TICK), 1.0);
+    }
+
+    #[test]
+    fn extrapolation_is_clamped() {
+        let start = Instant::now();
+        let mut buffer = moved_buffer(start);
+
+        // Velocity is 1 per tick
+        let velocity = 1.0 / TICK.as_secs_f32();
+        assert_near(
+            sample_x(&mut buffer, start, TICK * 2),
+            1.0 + velocity * TICK.as_secs_f32(),
+        );
+
+        let max_overshoot = velocity * MAX_EXTRAPOLATION.as_secs_f32();
+        assert_near(
+            sample_x(&mut buffer, start, TICK + MAX_EXTRAPOLATION),
+            1.0 + max_overshoot,
+        );
+    }
+
+    #[test]
+    fn recovery_after_extrapolation() {
+        let start = Instant::now();
+        let mut buffer = moved_buffer(start);
+        let max_overshoot = MAX_EXTRAPOLATION.as_secs_f32() / TICK.as_secs_f32();
+
+        let half_recovery = TICK + MAX_EXTRAPOLATION + EXTRAPOLATION_RECOVERY / 2;
+        assert_near(sample_x(&mut buffer, start, half_recovery), 1.0 + max_overshoot / 2.0);
+
+        let recovered = TICK + MAX_EXTRAPOLATION + EXTRAPOLATION_RECOVERY;
+        assert_near(sample_x(&mut buffer, start, recovered), 1.0);
+        assert_near(sample_x(&mut buffer, start, recovered * 10), 1.0);
+    }
+
+    #[test]
+    fn burst_is_not_extrapolated() {
+        let start = Instant::now();
+        let mut buffer = SnapshotBuffer::new(TICK);
+        buffer.push(start, Vector3::ZERO, Rotation::new(0.0, 0.0));
+        buffer.push(start + TICK / 10, Vector3::new(1.0, 0.0, 0.0), Rotation::new(0.0, 0.0));
+
+        assert_near(sample_x(&mut buffer, start, TICK), 1.0);
+    }
+
+    #[test]
+    fn teleport() {
+        let start = Instant::now();
+        let mut buffer = SnapshotBuffer::new(TICK);
+        buffer.push(start, Vector3::ZERO, Rotation::new(0.0, 0.0));
+        buffer.push(
+            start + TICK,
+            Vector3::new(TELEPORT_DISTANCE, 0.0, 0.0),
+            Rotation::new(0.0, 0.0),
+        );
+
+        assert_near(sample_x(&mut buffer, start, Duration::ZERO), TELEPORT_DISTANCE);
+    }
+}
@@ -1,5 +1,6 @@
 pub mod entity;
 pub mod entities_manager;
+pub mod interpolation;
 pub mod enums;
 pub mod generic_skin;
 pub mod entity_tag;
@@ -1,123 +1,101 @@
-use network::client::IClientNetwork;
+use super::loopback::LoopbackTransport;
+use super::recording::NetworkReplay;
+use super::socket::SocketTransport;
+use super::transport::{NetworkQueueStats, NetworkTransport, TimedMessage};
+use network::client::NetworkInfo;
 use network::messages::{ClientMessages, NetworkMessageType};
-use network::NetworkClient;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use std::time::Duration;
 
-#[derive(Clone)]
-pub struct NetworkContainer {
-    client_network: Arc<RwLock<NetworkClient>>,
-
-    network_lock: Arc<AtomicBool>,
-    timer: Arc<RwLock<Instant>>,
+/// Where the main scene takes server messages from
+pub enum ConnectionTarget {
+    Server(String),
+    Replay(PathBuf),
 
-    runtime: Arc<std::sync::Mutex<Option<tokio::runtime::Runtime>>>,
+    /// In-process server; see `LoopbackServer`
+    Loopback(LoopbackTransport),
 }
 
-/// Корректно завершает Tokio runtime при уничтожении контейнера.
-///
-/// Tokio runtime нельзя дропать из асинхронного контекста — это вызывает панику
-/// "Cannot drop a runtime in a context where blocking is not allowed".
-/// Godot может уничтожать объекты в async контексте, поэтому используется
-/// `shutdown_background()` который не блокирует.
-impl Drop for NetworkContainer {
-    fn drop(&mut self) {
-        if Arc::strong_count(&self.runtime) == 1 {
-            if let Ok(mut guard) = self.runtime.lock() {
-                if let Some(rt) = guard.take() {
-                    rt.shutdown_background();
-                }
-            }
+impl ConnectionTarget {
+    pub fn get_description(&self) -> String {
+        match self {
+            ConnectionTarget::Server(ip_port) => ip_port.clone(),
+            ConnectionTarget::Replay(path) => format!("replay {}", path.display()),
+            ConnectionTarget::Loopback(_) => "loopback server".to_string(),
         }
     }
 }
 
+#[derive(Clone)]
+pub struct NetworkContainer {
+    transport: Arc<dyn NetworkTransport>,
+}
+
 impl NetworkContainer {
     pub fn new(ip_port: String) -> Result<Self, String> {
-        log::info!(target: "network", "Connecting to the server at &e{}", ip_port);
-
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        let result = runtime.block_on(async { NetworkClient::new(ip_port).await });
-
-        let network = match result {
-            Ok(n) => n,
-            Err(e) => return Err(e),
-        };
-        Ok(Self {
-            runtime: Arc::new(std::sync::Mutex::new(Some(runtime))),
-            client_network: Arc::new(RwLock::new(network)),
-            timer: Arc::new(RwLock::new(Instant::now())),
-            network_lock: Arc::new(AtomicBool::new(false)),
-        })
+        Ok(Self::from_transport(Box::new(SocketTransport::create(ip_port)?)))
     }
 
-    fn with_runtime<F, R>(&self, f: F) -> R
-    where
-        F: FnOnce(&tokio::runtime::Runtime) -> R,
-    {
-        let guard = self.runtime.lock().unwrap();
-        f(guard.as_ref().expect("Runtime already shutdown"))
+    /// Offline transport which plays a recorded session
+    pub fn replay(path: PathBuf) -> Result<Self, String> {
+        log::info!(target: "network", "Starting replay &e{}", path.display());
+        Ok(Self::from_transport(Box::new(NetworkReplay::open(path)?)))
     }
 
-    pub(crate) fn get_client(&self) -> tokio::sync::RwLockReadGuard<'_, NetworkClient> {
-        self.with_runtime(|rt| rt.block_on(async { self.client_network.read().await }))
+    pub fn from_transport(transport: Box<dyn NetworkTransport>) -> Self {
+        Self {
+            transport: Arc::from(transport),
+        }
     }
 
-    pub fn send_message(&self, message_type: NetworkMessageType, message: &ClientMessages) {
-        self.get_client().send_message(message_type, message);
+    pub fn connect(target: ConnectionTarget) -> Result<Self, String> {
+        match target {
+            ConnectionTarget::Server(ip_port) => Self::new(ip_port),
+            ConnectionTarget::Replay(path) => Self::replay(path),
+            ConnectionTarget::Loopback(transport) => Ok(Self::from_transport(Box::new(transport))),
+        }
+    }
+
+    /// Opens a new connection to the same server
+    pub fn reconnect(&self) -> Result<Self, String> {
+        Ok(Self::from_transport(self.transport.reconnect()?))
     }
 
-    pub fn is_network_locked(&self) -> bool {
-        self.network_lock.load(Ordering::Relaxed)
+    pub fn can_reconnect(&self) -> bool {
+        self.transport.can_reconnect()
     }
 
-    pub fn set_network_lock(&self, state: bool) {
-        self.network_lock.store(state, Ordering::Relaxed);
+    pub fn is_replay(&self) -> bool {
+        self.transport.is_replay()
     }
 
-    async fn get_delta_time(&self) -> Duration {
-        let mut t = self.timer.write().await;
-        let delta_time = t.elapsed();
-        *t = Instant::now();
-        delta_time
+    /// Collects all server messages recieved since the previous frame
+    pub fn poll(&self) -> Result<Vec<TimedMessage>, String> {
+        self.transport.poll()
+    }
+
+    pub fn get_tick_interval(&self) -> Duration {
+        self.transport.get_tick_interval()
+    }
+
+    pub fn get_network_info(&self) -> Option<NetworkInfo> {
+        self.transport.get_network_info()
+    }
+
+    pub fn get_queue_stats(&self) -> Option<NetworkQueueStats> {
+        self.transport.get_queue_stats()
+    }
+
+    pub fn send_message(&self, message_type: NetworkMessageType, message: &ClientMessages) {
+        self.transport.send_message(message_type, message);
     }
 
     pub fn spawn_network_thread(&self) {
-        let container = self.clone();
-        log::info!(target: "network", "Spawning network thread...");
-
-        std::thread::spawn(move || {
-            let io_loop = tokio::runtime::Runtime::new().unwrap();
-            io_loop.block_on(async move {
-                let network = container.client_network.read().await;
-                loop {
-                    // Network will be processed only when there is no lock
-                    if container.is_network_locked() {
-                        std::thread::sleep(Duration::from_millis(10));
-                        continue;
-                    }
-                    container.set_network_lock(true);
-
-                    let success = network.step(container.get_delta_time().await).await;
-                    if !success {
-                        log::info!(target: "network", "Network thread exited;");
-                        break;
-                    }
-                    std::thread::sleep(Duration::from_millis(10));
-                }
-            });
-        });
-        log::info!(target: "network", "Network thread spawned");
+        self.transport.start();
     }
 
     pub fn disconnect(&self) {
-        let network = self.get_client();
-
-        if network.is_connected() {
-            log::info!(target: "network", "Disconnected from the server");
-            network.disconnect();
-        }
+        self.transport.disconnect();
     }
 }
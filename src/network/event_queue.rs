@@ -0,0 +1,300 @@
+use common::chunks::block_position::BlockPositionTrait;
+use common::chunks::chunk_position::ChunkPosition;
+use network::messages::ServerMessages;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::transport::TimedMessage;
+
+/// How much of each frame can be spent on server messages
+///
+/// Control messages are always handled in the frame they were recieved;
+/// the budget limits only chunk and entity streaming.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct NetworkBudgetSettings {
+    pub frame_time_ms: f32,
+    pub max_messages: usize,
+}
+
+impl Default for NetworkBudgetSettings {
+    fn default() -> Self {
+        Self {
+            frame_time_ms: 4.0,
+            max_messages: 256,
+        }
+    }
+}
+
+/// Frame budget for the bulk messages
+pub struct NetworkBudget {
+    started: Instant,
+    max_time: Duration,
+    max_messages: usize,
+    handled: usize,
+}
+
+impl NetworkBudget {
+    pub fn start(settings: &NetworkBudgetSettings) -> Self {
+        Self {
+            started: Instant::now(),
+            max_time: Duration::from_secs_f32(settings.frame_time_ms.max(0.0) / 1000.0),
+            max_messages: settings.max_messages,
+            handled: 0,
+        }
+    }
+
+    pub fn consume(&mut self) {
+        self.handled += 1;
+    }
+
+    /// At least one bulk message is handled each frame,
+    /// so the queue always moves forward
+    pub fn is_exhausted(&self) -> bool {
+        if self.handled == 0 {
+            return false;
+        }
+        self.handled >= self.max_messages || self.started.elapsed() >= self.max_time
+    }
+}
+
+fn is_control_message(message: &ServerMessages) -> bool {
+    match message {
+        ServerMessages::ChunkSectionInfoEncoded { .. }
+        | ServerMessages::ChunkSectionInfo { .. }
+        | ServerMessages::UnloadChunks { .. }
+        | ServerMessages::StartStreamingEntity { .. }
+        | ServerMessages::UpdateEntityComponent { .. }
+        | ServerMessages::EntityMove { .. }
+        | ServerMessages::StopStreamingEntities { .. } => false,
+        _ => true,
+    }
+}
+
+/// Control messages which change the world;
+/// bulk messages recieved before them belong to the previous state and are handled first
+fn is_world_change(message: &ServerMessages) -> bool {
+    matches!(
+        message,
+        ServerMessages::SpawnWorld { .. } | ServerMessages::PlayerSpawn { .. }
+    )
+}
+
+/// Does the bulk message change the data of the edited chunk
+fn is_edited_chunk_data(message: &ServerMessages, world_slug: &str, edit_chunk: &ChunkPosition) -> bool {
+    match message {
+        ServerMessages::ChunkSectionInfo {
+            world_slug: chunk_world,
+            chunk_position,
+            ..
+        } => chunk_world == world_slug && chunk_position == edit_chunk,
+        ServerMessages::UnloadChunks {
+            world_slug: chunk_world,
+            chunks,
+        } => chunk_world == world_slug && chunks.contains(edit_chunk),
+        _ => false,
+    }
+}
+
+/// Server messages waiting to be handled
+///
+/// Messages keep their order inside each of the two queues.
+/// Control messages go ahead of the bulk ones only if they don't depend on them.
+#[derive(Default)]
+pub struct NetworkEventQueue {
+    control: VecDeque<(u64, TimedMessage)>,
+    bulk: VecDeque<(u64, TimedMessage)>,
+
+    // Order of the message recieving
+    next_index: u64,
+}
+
+impl NetworkEventQueue {
+    pub fn push(&mut self, message: TimedMessage) {
+        let index = self.next_index;
+        self.next_index += 1;
+        if is_control_message(&message.message) {
+            self.control.push_back((index, message));
+        } else {
+            self.bulk.push_back((index, message));
+        }
+    }
+
+    /// Takes the next control message
+    /// with the bulk messages recieved before it which must be handled first
+    pub fn pop_control(&mut self) -> Option<(Vec<TimedMessage>, TimedMessage)> {
+        let (index, message) = self.control.pop_front()?;
+        let dependencies = self.take_bulk_before(index, &message.message);
+        Some((dependencies, message))
+    }
+
+    pub fn pop_bulk(&mut self) -> Option<TimedMessage> {
+        self.bulk.pop_front().map(|(_, message)| message)
+    }
+
+    /// Block edit must be applied after the chunk data sent before it,
+    /// otherwise the edit will be overwritten or applied to the unloaded chunk.
+    /// World change must be applied after all streaming of the previous world.
+    fn take_bulk_before(&mut self, index: u64, message: &ServerMessages) -> Vec<TimedMessage> {
+        let is_dependency: Box<dyn Fn(&ServerMessages) -> bool> = match message {
+            ServerMessages::EditBlock {
+                world_slug, position, ..
+            } => {
+                let edit_chunk = position.get_chunk_position();
+                Box::new(move |queued| is_edited_chunk_data(queued, world_slug, &edit_chunk))
+            }
+            m if is_world_change(m) => Box::new(|_| true),
+            _ => return Default::default(),
+        };
+
+        let mut result: Vec<TimedMessage> = Default::default();
+        let mut rest: VecDeque<(u64, TimedMessage)> = VecDeque::with_capacity(self.bulk.len());
+        for (queued_index, queued) in self.bulk.drain(..) {
+            if queued_index < index && is_dependency(&queued.message) {
+                result.push(queued);
+            } else {
+                rest.push_back((queued_index, queued));
+            }
+        }
+        self.bulk = rest;
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.control.len() + self.bulk.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.control.is_empty() && self.bulk.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.control.clear();
+        self.bulk.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::chunks::block_position::BlockPosition;
+    use common::CHUNK_SIZE;
+
+    fn chunk(world_slug: &str, x: i64) -> TimedMessage {
+        TimedMessage::now(ServerMessages::ChunkSectionInfo {
+            world_slug: world_slug.to_string(),
+            chunk_position: ChunkPosition::new(x, 0),
+            sections: Default::default(),
+        })
+    }
+
+    fn unload(world_slug: &str, x: i64) -> TimedMessage {
+        TimedMessage::now(ServerMessages::UnloadChunks {
+            world_slug: world_slug.to_string(),
+            chunks: vec![ChunkPosition::new(x, 0)],
+        })
+    }
+
+    fn edit(world_slug: &str, x: i64) -> TimedMessage {
+        TimedMessage::now(ServerMessages::EditBlock {
+            world_slug: world_slug.to_string(),
+            position: BlockPosition::new(x * CHUNK_SIZE as i64 + 1, 10, 1),
+            new_block_info: None,
+        })
+    }
+
+    fn spawn_world(world_slug: &str) -> TimedMessage {
+        TimedMessage::now(ServerMessages::SpawnWorld {
+            world_slug: world_slug.to_string(),
+        })
+    }
+
+    /// Messages in the order they are handled without the budget
+    fn handle_all(queue: &mut NetworkEventQueue) -> Vec<String> {
+        let mut handled: Vec<TimedMessage> = Default::default();
+        while let Some((dependencies, message)) = queue.pop_control() {
+            handled.extend(dependencies);
+            handled.push(message);
+        }
+        while let Some(message) = queue.pop_bulk() {
+            handled.push(message);
+        }
+        handled.iter().map(|m| describe(&m.message)).collect()
+    }
+
+    fn describe(message: &ServerMessages) -> String {
+        match message {
+            ServerMessages::ChunkSectionInfo {
+                world_slug,
+                chunk_position,
+                ..
+            } => format!("chunk {} {}", world_slug, chunk_position.x),
+            ServerMessages::UnloadChunks { world_slug, chunks } => format!("unload {} {}", world_slug, chunks[0].x),
+            ServerMessages::EditBlock {
+                world_slug, position, ..
+            } => format!("edit {} {}", world_slug, position.get_chunk_position().x),
+            ServerMessages::SpawnWorld { world_slug } => format!("spawn {}", world_slug),
+            _ => "other".to_string(),
+        }
+    }
+
+    #[test]
+    fn control_goes_ahead_of_streaming() {
+        let mut queue = NetworkEventQueue::default();
+        queue.push(chunk("a", 0));
+        queue.push(chunk("a", 1));
+        queue.push(TimedMessage::now(ServerMessages::ConsoleOutput {
+            message: "-".to_string(),
+        }));
+        assert_eq!(handle_all(&mut queue), vec!["other", "chunk a 0", "chunk a 1"]);
+    }
+
+    #[test]
+    fn edit_after_chunk_data() {
+        let mut queue = NetworkEventQueue::default();
+        queue.push(chunk("a", 0));
+        queue.push(chunk("a", 1));
+        queue.push(chunk("b", 1));
+        queue.push(edit("a", 1));
+        assert_eq!(
+            handle_all(&mut queue),
+            vec!["chunk a 1", "edit a 1", "chunk a 0", "chunk b 1"]
+        );
+    }
+
+    #[test]
+    fn edit_after_unload() {
+        let mut queue = NetworkEventQueue::default();
+        queue.push(chunk("a", 1));
+        queue.push(unload("a", 1));
+        queue.push(unload("a", 2));
+        queue.push(edit("a", 1));
+        assert_eq!(
+            handle_all(&mut queue),
+            vec!["chunk a 1", "unload a 1", "edit a 1", "unload a 2"]
+        );
+    }
+
+    #[test]
+    fn edit_keeps_later_chunk_data() {
+        let mut queue = NetworkEventQueue::default();
+        queue.push(edit("a", 1));
+        queue.push(chunk("a", 1));
+        assert_eq!(handle_all(&mut queue), vec!["edit a 1", "chunk a 1"]);
+    }
+
+    #[test]
+    fn world_change_flushes_streaming() {
+        let mut queue = NetworkEventQueue::default();
+        queue.push(chunk("a", 0));
+        queue.push(unload("a", 1));
+        queue.push(spawn_world("b"));
+        queue.push(chunk("b", 0));
+        queue.push(edit("b", 0));
+        assert_eq!(
+            handle_all(&mut queue),
+            vec!["chunk a 0", "unload a 1", "spawn b", "chunk b 0", "edit b 0"]
+        );
+    }
+}
@@ -1,45 +1,105 @@
+use super::client::NetworkContainer;
+use super::event_queue::{NetworkBudget, NetworkBudgetSettings, NetworkEventQueue};
+use super::recording::NetworkRecorder;
+use super::transport::TimedMessage;
 use crate::client_scripts::resource_manager::ResourceManager;
+use crate::scenes::loading_progress::LoadingStage;
 use crate::scenes::main_scene::MainScene;
 use crate::utils::bridge::{IntoChunkPositionVector, IntoGodotVector};
 use crate::world::world_manager::WorldManager;
 use crate::world::worlds_manager::WorldsManager;
 use crate::VERSION;
+use common::blocks::block_type::BlockType;
+use common::chunks::block_position::BlockPosition;
+use common::chunks::chunk_data::{BlockDataInfo, BlockIndexType, ChunkData};
 use common::chunks::chunk_position::ChunkPosition;
+use common::chunks::position::Vector3 as NetworkVector3;
+use common::chunks::rotation::Rotation;
 use godot::classes::{Engine, RenderingServer};
 use godot::obj::{Gd, Singleton};
-use network::client::{IClientNetwork, NetworkInfo};
+use network::client::NetworkInfo;
 use network::entities::EntityNetworkComponent;
-use network::messages::{ClientMessages, NetworkMessageType, ServerMessages};
-use network::NetworkClient;
+use network::messages::{ClientMessages, NetworkMessageType, ResurceScheme, ServerMessages};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Client state which server messages are applied to
+///
+/// Implemented by `MainScene`; messages are handled the same way without godot in the tests.
+pub trait NetworkEventsHandler {
+    fn get_network(&self) -> Option<&NetworkContainer>;
+    fn get_event_queue_mut(&mut self) -> &mut NetworkEventQueue;
+    fn get_network_budget(&self) -> NetworkBudgetSettings;
+    fn get_recorder_mut(&mut self) -> Option<&mut NetworkRecorder>;
+    fn stop_recording(&mut self);
+
+    fn get_connection_info(&self) -> ClientMessages;
+    fn set_loading_stage(&mut self, stage: LoadingStage);
+    fn set_loading_progress(&mut self, text: String, progress: f32);
+
+    /// Closes the session without reconnect
+    fn disconnect(&mut self, message: String);
+
+    /// Returns true if the resources archive is already saved
+    fn load_resources_scheme(&mut self, list: Vec<ResurceScheme>, archive_hash: u64) -> Result<bool, String>;
+    fn load_resources_part(&mut self, data: Vec<u8>, is_last: bool) -> Result<(), String>;
+
+    /// Returns true if the block types are changed
+    fn load_settings(
+        &mut self,
+        block_types: Vec<BlockType>,
+        block_id_map: BTreeMap<BlockIndexType, String>,
+    ) -> Result<bool, String>;
+    fn on_server_connected(&mut self, blocks_changed: bool);
+
+    fn spawn_world(&mut self, world_slug: String);
+    fn spawn_player(
+        &mut self,
+        world_slug: String,
+        position: NetworkVector3,
+        rotation: Rotation,
+        components: Vec<EntityNetworkComponent>,
+    );
+    fn update_player_component(&mut self, component: EntityNetworkComponent);
+
+    /// Returns false if the world is not found
+    fn recieve_chunk(&mut self, world_slug: String, chunk_position: ChunkPosition, sections: ChunkData) -> bool;
+    fn unload_chunks(&mut self, world_slug: String, chunks: Vec<ChunkPosition>);
+    fn recieve_edit_block(
+        &mut self,
+        world_slug: String,
+        position: BlockPosition,
+        new_block_info: Option<BlockDataInfo>,
+    ) -> Result<(), String>;
+
+    fn start_streaming_entity(
+        &mut self,
+        world_slug: String,
+        id: u32,
+        time: Instant,
+        position: NetworkVector3,
+        rotation: Rotation,
+        components: Vec<EntityNetworkComponent>,
+    );
+    fn update_entity_component(&mut self, world_slug: String, id: u32, component: EntityNetworkComponent);
+    fn move_entity(&mut self, world_slug: String, id: u32, time: Instant, position: NetworkVector3, rotation: Rotation);
+    fn stop_streaming_entities(&mut self, world_slug: String, ids: Vec<u32>);
+}
 
 fn get_world(worlds_manager: &WorldsManager, world_slug: String) -> Option<&Gd<WorldManager>> {
-    let world = match worlds_manager.get_world() {
-        Some(w) => w,
-        None => {
-            log::error!(target: "network", "Network message for non existed world");
-            return None;
-        }
-    };
-    if world_slug != *world.bind().get_slug() {
-        log::error!(target: "network", "Network message for non wrong world {} != {}", world_slug, world.bind().get_slug());
-        return None;
+    let world = worlds_manager.get_world_by_slug(&world_slug);
+    if world.is_none() {
+        log::error!(target: "network", "Network message for non existed world {}", world_slug);
     }
-    Some(world)
+    world
 }
 
 fn get_world_mut(worlds_manager: &mut WorldsManager, world_slug: String) -> Option<&mut Gd<WorldManager>> {
-    let world = match worlds_manager.get_world_mut() {
-        Some(w) => w,
-        None => {
-            log::error!(target: "network", "Network message for non existed world");
-            return None;
-        }
-    };
-    if world_slug != *world.bind().get_slug() {
-        log::error!(target: "network", "Network message for wrong world {} != {}", world_slug, world.bind().get_slug());
-        return None;
+    let world = worlds_manager.get_world_by_slug_mut(&world_slug);
+    if world.is_none() {
+        log::error!(target: "network", "Network message for non existed world {}", world_slug);
     }
-    Some(world)
+    world
 }
 
 #[cfg(debug_assertions)]
@@ -65,61 +125,89 @@ fn span_name_for_event(event: &ServerMessages) -> &'static str {
     }
 }
 
-pub fn handle_network_events(main: &mut MainScene) -> Result<NetworkInfo, String> {
+pub fn handle_network_events<H: NetworkEventsHandler>(main: &mut H) -> Result<Option<NetworkInfo>, String> {
     #[cfg(feature = "trace")]
     let _span = tracy_client::span!("network.handle_network_events");
 
     let _span = crate::span!("network.handle_network_events");
 
     let container = main.get_network().expect("network is not set").clone();
-    container.set_network_lock(false);
 
-    let network = {
-        let _span = crate::span!("network.handle_network_events::get_client");
-        container.get_client()
+    let events = {
+        let _span = crate::span!("network.handle_network_events::poll");
+        container.poll()?
     };
 
-    // Recieve errors from network thread
-    for error in network.iter_errors() {
-        return Err(error);
+    if let Some(recorder) = main.get_recorder_mut() {
+        recorder.next_frame();
     }
 
-    // Recieve decoded server messages from network thread
-    for event in network.iter_server_messages() {
-        #[cfg(debug_assertions)]
-        let _span = crate::span!(span_name_for_event(&event));
+    for event in events {
+        if let Some(recorder) = main.get_recorder_mut() {
+            if let Err(e) = recorder.record(&event.message) {
+                log::error!(target: "network", "{}", e);
+                main.stop_recording();
+            }
+        }
+        main.get_event_queue_mut().push(event);
+    }
 
-        handle_event(&*network, main, event)?;
+    // Control messages are never delayed
+    while let Some((dependencies, event)) = main.get_event_queue_mut().pop_control() {
+        for bulk_event in dependencies {
+            handle_event_with_span(&container, main, bulk_event)?;
+        }
+        handle_event_with_span(&container, main, event)?;
+
+        // Session is closed by the server
+        if main.get_network().is_none() {
+            return Ok(None);
+        }
+    }
+
+    let mut budget = NetworkBudget::start(&main.get_network_budget());
+    while !budget.is_exhausted() {
+        let Some(event) = main.get_event_queue_mut().pop_bulk() else {
+            break;
+        };
+        handle_event_with_span(&container, main, event)?;
+        budget.consume();
     }
 
-    let network_info = network.get_network_info().clone();
-    Ok(network_info)
+    Ok(container.get_network_info())
 }
 
-fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMessages) -> Result<(), String> {
+fn handle_event_with_span<H: NetworkEventsHandler>(
+    network: &NetworkContainer,
+    main: &mut H,
+    event: TimedMessage,
+) -> Result<(), String> {
+    #[cfg(debug_assertions)]
+    let _span = crate::span!(span_name_for_event(&event.message));
+
+    handle_event(network, main, event)
+}
+
+fn handle_event<H: NetworkEventsHandler>(
+    network: &NetworkContainer,
+    main: &mut H,
+    event: TimedMessage,
+) -> Result<(), String> {
     let mut recieved_chunks: Vec<ChunkPosition> = Default::default();
 
-    match event {
+    let TimedMessage { time, message } = event;
+    match message {
         ServerMessages::AllowConnection => {
-            let device_name = match RenderingServer::singleton().get_rendering_device() {
-                Some(d) => d.get_device_name().to_string(),
-                None => String::from("-"),
-            };
-            let connection_info = ClientMessages::ConnectionInfo {
-                login: main.get_login().clone(),
-                version: VERSION.to_string(),
-                architecture: Engine::singleton().get_architecture_name().to_string(),
-                rendering_device: device_name,
-            };
             log::info!(target: "network", "Server allowed connection");
-            network.send_message(NetworkMessageType::ReliableOrdered, &connection_info);
+            main.set_loading_stage(LoadingStage::Resources);
+            network.send_message(NetworkMessageType::ReliableOrdered, &main.get_connection_info());
         }
         ServerMessages::Disconnect { message } => {
             let msg = match message {
                 Some(m) => m,
                 None => "-".to_string(),
             };
-            main.send_disconnect_event(format!("Disconnected by server: {}", msg));
+            main.disconnect(format!("Disconnected by server: {}", msg));
             return Ok(());
         }
 
@@ -128,120 +216,39 @@ fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMess
         }
 
         ServerMessages::ResourcesScheme { list, archive_hash } => {
-            let mut resource_manager = main.get_resource_manager_mut();
-            resource_manager.set_resource_scheme(list, archive_hash);
-            let (scripts_count, media_count) = resource_manager.get_resource_scheme_count();
-            log::info!(target: "network", "Network resources scheme loaded &e(scripts:{}, media:{}, archive_hash:{})", scripts_count, media_count, archive_hash);
-
-            let has_saved = if ResourceManager::has_local_saved_resource(&archive_hash).unwrap() {
-                match resource_manager.load_local_archive(&archive_hash) {
-                    Ok(count) => {
-                        let mut resource_names: Vec<String> = Default::default();
-                        for (resource_slug, resource) in resource_manager.get_resources_storage().iter() {
-                            if resource.is_network() {
-                                resource_names.push(resource_slug.clone());
-                            }
-                        }
-                        log::info!(target: "network", "Resources cache loaded: &e{}&r; media count:{}", resource_names.join(", "), count);
-                    }
-                    Err(e) => return Err(format!("Network resources cache load error: {}", e)),
-                }
-                true
-            } else {
-                false
-            };
+            let has_saved = main.load_resources_scheme(list, archive_hash)?;
             let msg = ClientMessages::ResourcesHasCache { exists: has_saved };
             network.send_message(NetworkMessageType::ReliableOrdered, &msg);
         }
-        ServerMessages::ResourcesPart { index, total, mut data } => {
-            {
-                let mut resource_manager = main.get_resource_manager_mut();
-                resource_manager.load_archive_chunk(&mut data);
-
-                let is_last = index + 1 >= total;
-                if is_last {
-                    log::info!(target: "network", "Resource pack downloaded!");
-                    resource_manager.check_archive_hash().unwrap();
-                    let archive_hash = resource_manager.get_archive_hash().unwrap().clone();
-                    let path = ResourceManager::get_saved_resource_path(&archive_hash).unwrap();
-                    match resource_manager.save_resource_to_local() {
-                        Ok(_) => {
-                            log::info!(target: "network", "Resources archive saved locally: &6{}", path.display().to_string())
-                        }
-                        Err(e) => return Err(format!("Network resources local save error: {}", e)),
-                    }
-                    match resource_manager.load_local_archive(&archive_hash) {
-                        Ok(_count) => {
-                            let mut resource_names: Vec<String> = Default::default();
-                            for (resource_slug, resource) in resource_manager.get_resources_storage().iter() {
-                                if resource.is_network() {
-                                    resource_names.push(resource_slug.clone());
-                                }
-                            }
-                            log::info!(target: "network", "Resources loaded from network: &e{}", resource_names.join(", "));
-                        }
-                        Err(e) => return Err(format!("Network resources cache load error: {}", e)),
-                    }
-                }
-            }
+        ServerMessages::ResourcesPart { index, total, data } => {
+            main.load_resources_part(data, index + 1 >= total)?;
 
             let msg = ClientMessages::ResourcesLoaded { last_index: index };
             network.send_message(NetworkMessageType::ReliableOrdered, &msg);
 
-            main.get_text_screen_mut()
-                .update_text(format!("Media downloading {}/{}", index + 1, total));
+            main.set_loading_progress(
+                format!("Media downloading {}/{}", index + 1, total),
+                (index + 1) as f32 / total.max(1) as f32,
+            );
         }
         ServerMessages::Settings {
             block_types,
             block_id_map,
         } => {
             log::info!(target: "network", "Recieved settings from the network");
+            main.set_loading_stage(LoadingStage::Settings);
 
-            {
-                let mut worlds_manager = main.get_wm().clone();
-                let resource_manager = main.get_resource_manager();
-
-                {
-                    let wm = worlds_manager.bind();
-                    let mut block_storage = wm.get_block_storage_mut();
-
-                    block_storage.set_block_id_map(block_id_map);
-                    log::info!(target: "network", "Block id map is set");
-
-                    if let Err(e) =
-                        block_storage.load_blocks_types(block_types, &*resource_manager.get_resources_storage())
-                    {
-                        return Err(e);
-                    }
-                }
-
-                if let Err(e) = worlds_manager
-                    .bind_mut()
-                    .build_textures(&*&resource_manager.get_resources_storage())
-                {
-                    return Err(e);
-                }
+            let blocks_changed = main.load_settings(block_types, block_id_map)?;
+            network.send_message(NetworkMessageType::ReliableOrdered, &ClientMessages::SettingsLoaded);
 
-                network.send_message(NetworkMessageType::ReliableOrdered, &ClientMessages::SettingsLoaded);
-            }
-
-            main.on_server_connected();
+            main.on_server_connected(blocks_changed);
         }
 
         ServerMessages::SpawnWorld { world_slug } => {
             main.spawn_world(world_slug);
-            main.get_text_screen_mut().toggle(false);
         }
         ServerMessages::UpdatePlayerComponent { component } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(player_controller) = worlds_manager.get_player_controller_mut() else {
-                log::error!(target: "network", "network tried to update skin with non existing world");
-                return Ok(());
-            };
-            match component {
-                EntityNetworkComponent::Tag(_tag) => (),
-                EntityNetworkComponent::Skin(skin) => player_controller.bind_mut().update_skin(skin),
-            }
+            main.update_player_component(component);
         }
         ServerMessages::PlayerSpawn {
             world_slug,
@@ -249,23 +256,7 @@ fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMess
             rotation,
             components,
         } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(_world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            let Some(player_controller) = worlds_manager.get_player_controller_mut().as_mut() else {
-                log::error!(target: "network", "network tried to teleport with non existing world");
-                return Ok(());
-            };
-            player_controller.bind_mut().set_position(position.to_godot());
-            player_controller.bind_mut().set_rotation(rotation);
-
-            for component in components {
-                match component {
-                    EntityNetworkComponent::Tag(_tag) => (),
-                    EntityNetworkComponent::Skin(skin) => player_controller.bind_mut().update_skin(skin),
-                }
-            }
+            main.spawn_player(world_slug, position, rotation, components);
         }
         ServerMessages::ChunkSectionInfoEncoded { .. } => {
             panic!("ChunkSectionInfoEncoded must be decoded");
@@ -275,27 +266,12 @@ fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMess
             chunk_position,
             sections,
         } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-
-            let center = match worlds_manager.get_player_controller() {
-                Some(c) => c.bind().get_position().to_chunk_position(),
-                None => ChunkPosition::zero(),
-            };
-
-            let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            world.bind_mut().recieve_chunk(center, chunk_position, sections);
-            recieved_chunks.push(chunk_position);
+            if main.recieve_chunk(world_slug, chunk_position, sections) {
+                recieved_chunks.push(chunk_position);
+            }
         }
         ServerMessages::UnloadChunks { chunks, world_slug } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            for chunk_position in chunks.iter() {
-                world.bind_mut().unload_chunk(*chunk_position);
-            }
+            main.unload_chunks(world_slug, chunks);
         }
         ServerMessages::StartStreamingEntity {
             id,
@@ -304,29 +280,14 @@ fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMess
             rotation,
             components,
         } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            let mut w = world.bind_mut();
-            let mut entities_manager = w.get_entities_manager_mut();
-            entities_manager.create_entity(id, position.to_godot(), rotation, components);
+            main.start_streaming_entity(world_slug, id, time, position, rotation, components);
         }
         ServerMessages::UpdateEntityComponent {
             world_slug,
             id,
             component,
         } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            let mut w = world.bind_mut();
-            let mut entities_manager = w.get_entities_manager_mut();
-            match component {
-                EntityNetworkComponent::Tag(c) => entities_manager.update_entity_tag(id, c),
-                EntityNetworkComponent::Skin(c) => entities_manager.update_entity_skin(id, c.unwrap()),
-            }
+            main.update_entity_component(world_slug, id, component);
         }
         ServerMessages::EntityMove {
             world_slug,
@@ -334,39 +295,17 @@ fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMess
             position,
             rotation,
         } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            let mut w = world.bind_mut();
-            let mut entities_manager = w.get_entities_manager_mut();
-            entities_manager.move_entity(id, position.to_godot(), rotation);
+            main.move_entity(world_slug, id, time, position, rotation);
         }
         ServerMessages::StopStreamingEntities { world_slug, ids } => {
-            let mut worlds_manager = main.get_worlds_manager_mut();
-            let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            let mut w = world.bind_mut();
-            let mut entities_manager = w.get_entities_manager_mut();
-            entities_manager.despawn(ids);
+            main.stop_streaming_entities(world_slug, ids);
         }
         ServerMessages::EditBlock {
             world_slug,
             position,
             new_block_info,
         } => {
-            let worlds_manager = main.get_wm().bind();
-            let Some(world) = get_world(&worlds_manager, world_slug) else {
-                return Ok(());
-            };
-            let block_storage = worlds_manager.get_block_storage();
-            let resource_manager = main.get_resource_manager();
-            let resources_storage = resource_manager.get_resources_storage();
-            world
-                .bind()
-                .edit_block(position, &block_storage, new_block_info, &*resources_storage)
-                .unwrap();
+            main.recieve_edit_block(world_slug, position, new_block_info)?;
         }
     }
 
@@ -379,3 +318,572 @@ fn handle_event(network: &NetworkClient, main: &mut MainScene, event: ServerMess
     }
     return Ok(());
 }
+
+impl NetworkEventsHandler for MainScene {
+    fn get_network(&self) -> Option<&NetworkContainer> {
+        MainScene::get_network(self)
+    }
+
+    fn get_event_queue_mut(&mut self) -> &mut NetworkEventQueue {
+        MainScene::get_event_queue_mut(self)
+    }
+
+    fn get_network_budget(&self) -> NetworkBudgetSettings {
+        MainScene::get_network_budget(self)
+    }
+
+    fn get_recorder_mut(&mut self) -> Option<&mut NetworkRecorder> {
+        MainScene::get_recorder_mut(self)
+    }
+
+    fn stop_recording(&mut self) {
+        MainScene::stop_recording(self)
+    }
+
+    fn get_connection_info(&self) -> ClientMessages {
+        let device_name = match RenderingServer::singleton().get_rendering_device() {
+            Some(d) => d.get_device_name().to_string(),
+            None => String::from("-"),
+        };
+        ClientMessages::ConnectionInfo {
+            login: self.get_login().clone(),
+            version: VERSION.to_string(),
+            architecture: Engine::singleton().get_architecture_name().to_string(),
+            rendering_device: device_name,
+        }
+    }
+
+    fn set_loading_stage(&mut self, stage: LoadingStage) {
+        MainScene::set_loading_stage(self, stage)
+    }
+
+    fn set_loading_progress(&mut self, text: String, progress: f32) {
+        self.get_text_screen_mut().update_text(text);
+        self.set_loading_stage_progress(progress);
+    }
+
+    fn disconnect(&mut self, message: String) {
+        MainScene::disconnect(self, message)
+    }
+
+    fn load_resources_scheme(&mut self, list: Vec<ResurceScheme>, archive_hash: u64) -> Result<bool, String> {
+        let mut resource_manager = self.get_resource_manager_mut();
+        resource_manager.set_resource_scheme(list, archive_hash);
+        let (scripts_count, media_count) = resource_manager.get_resource_scheme_count();
+        log::info!(target: "network", "Network resources scheme loaded &e(scripts:{}, media:{}, archive_hash:{})", scripts_count, media_count, archive_hash);
+
+        if resource_manager.is_archive_loaded(&archive_hash) {
+            log::info!(target: "network", "Resources archive is already loaded; skip loading");
+            return Ok(true);
+        }
+        if !ResourceManager::has_local_saved_resource(&archive_hash).unwrap() {
+            return Ok(false);
+        }
+        match resource_manager.load_local_archive(&archive_hash) {
+            Ok(count) => {
+                let mut resource_names: Vec<String> = Default::default();
+                for (resource_slug, resource) in resource_manager.get_resources_storage().iter() {
+                    if resource.is_network() {
+                        resource_names.push(resource_slug.clone());
+                    }
+                }
+                log::info!(target: "network", "Resources cache loaded: &e{}&r; media count:{}", resource_names.join(", "), count);
+            }
+            Err(e) => return Err(format!("Network resources cache load error: {}", e)),
+        }
+        Ok(true)
+    }
+
+    fn load_resources_part(&mut self, mut data: Vec<u8>, is_last: bool) -> Result<(), String> {
+        let mut resource_manager = self.get_resource_manager_mut();
+        resource_manager.load_archive_chunk(&mut data);
+        if !is_last {
+            return Ok(());
+        }
+
+        log::info!(target: "network", "Resource pack downloaded!");
+        resource_manager.check_archive_hash().unwrap();
+        let archive_hash = resource_manager.get_archive_hash().unwrap().clone();
+        let path = ResourceManager::get_saved_resource_path(&archive_hash).unwrap();
+        match resource_manager.save_resource_to_local() {
+            Ok(_) => {
+                log::info!(target: "network", "Resources archive saved locally: &6{}", path.display().to_string())
+            }
+            Err(e) => return Err(format!("Network resources local save error: {}", e)),
+        }
+        match resource_manager.load_local_archive(&archive_hash) {
+            Ok(_count) => {
+                let mut resource_names: Vec<String> = Default::default();
+                for (resource_slug, resource) in resource_manager.get_resources_storage().iter() {
+                    if resource.is_network() {
+                        resource_names.push(resource_slug.clone());
+                    }
+                }
+                log::info!(target: "network", "Resources loaded from network: &e{}", resource_names.join(", "));
+            }
+            Err(e) => return Err(format!("Network resources cache load error: {}", e)),
+        }
+        Ok(())
+    }
+
+    fn load_settings(
+        &mut self,
+        block_types: Vec<BlockType>,
+        block_id_map: BTreeMap<BlockIndexType, String>,
+    ) -> Result<bool, String> {
+        let blocks_changed = !self
+            .get_wm()
+            .bind()
+            .get_block_storage()
+            .is_same_scheme(&block_id_map, &block_types);
+
+        if !blocks_changed {
+            // Reconnect to the same server: textures and block meshes are kept
+            log::info!(target: "network", "Block types are not changed; textures are kept");
+            return Ok(false);
+        }
+
+        self.get_text_screen_mut()
+            .update_text("Building textures...".to_string());
+
+        let mut worlds_manager = self.get_wm().clone();
+        let resource_manager = self.get_resource_manager();
+
+        {
+            let wm = worlds_manager.bind();
+            let mut block_storage = wm.get_block_storage_mut();
+
+            block_storage.set_block_id_map(block_id_map);
+            log::info!(target: "network", "Block id map is set");
+
+            block_storage.load_blocks_types(block_types, &*resource_manager.get_resources_storage())?;
+        }
+
+        worlds_manager
+            .bind_mut()
+            .build_textures(&*&resource_manager.get_resources_storage())?;
+        Ok(true)
+    }
+
+    fn on_server_connected(&mut self, blocks_changed: bool) {
+        MainScene::on_server_connected(self, blocks_changed)
+    }
+
+    fn spawn_world(&mut self, world_slug: String) {
+        MainScene::spawn_world(self, world_slug);
+        self.start_world_loading();
+    }
+
+    fn spawn_player(
+        &mut self,
+        world_slug: String,
+        position: NetworkVector3,
+        rotation: Rotation,
+        components: Vec<EntityNetworkComponent>,
+    ) {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        if !worlds_manager.is_active_world(&world_slug) {
+            log::error!(target: "network", "Player spawn in inactive world {}", world_slug);
+            return;
+        }
+        let Some(player_controller) = worlds_manager.get_player_controller_mut().as_mut() else {
+            log::error!(target: "network", "network tried to teleport with non existing world");
+            return;
+        };
+        player_controller.bind_mut().set_position(position.to_godot());
+        player_controller.bind_mut().set_rotation(rotation);
+
+        for component in components {
+            match component {
+                EntityNetworkComponent::Tag(_tag) => (),
+                EntityNetworkComponent::Skin(skin) => player_controller.bind_mut().update_skin(skin),
+            }
+        }
+    }
+
+    fn update_player_component(&mut self, component: EntityNetworkComponent) {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        let Some(player_controller) = worlds_manager.get_player_controller_mut() else {
+            log::error!(target: "network", "network tried to update skin with non existing world");
+            return;
+        };
+        match component {
+            EntityNetworkComponent::Tag(_tag) => (),
+            EntityNetworkComponent::Skin(skin) => player_controller.bind_mut().update_skin(skin),
+        }
+    }
+
+    fn recieve_chunk(&mut self, world_slug: String, chunk_position: ChunkPosition, sections: ChunkData) -> bool {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+
+        let center = match worlds_manager.get_player_controller() {
+            Some(c) => c.bind().get_position().to_chunk_position(),
+            None => ChunkPosition::zero(),
+        };
+
+        let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
+            return false;
+        };
+        world.bind_mut().recieve_chunk(center, chunk_position, sections);
+        true
+    }
+
+    fn unload_chunks(&mut self, world_slug: String, chunks: Vec<ChunkPosition>) {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
+            return;
+        };
+        for chunk_position in chunks.iter() {
+            world.bind_mut().unload_chunk(*chunk_position);
+        }
+    }
+
+    fn recieve_edit_block(
+        &mut self,
+        world_slug: String,
+        position: BlockPosition,
+        new_block_info: Option<BlockDataInfo>,
+    ) -> Result<(), String> {
+        let worlds_manager = self.get_wm().bind();
+        let Some(world) = get_world(&worlds_manager, world_slug) else {
+            return Ok(());
+        };
+        let block_storage = worlds_manager.get_block_storage();
+        let resource_manager = self.get_resource_manager();
+        let resources_storage = resource_manager.get_resources_storage();
+        world
+            .clone()
+            .bind_mut()
+            .recieve_edit_block(position, &block_storage, new_block_info, &*resources_storage)
+    }
+
+    fn start_streaming_entity(
+        &mut self,
+        world_slug: String,
+        id: u32,
+        time: Instant,
+        position: NetworkVector3,
+        rotation: Rotation,
+        components: Vec<EntityNetworkComponent>,
+    ) {
+        let tick_interval = match MainScene::get_network(self) {
+            Some(network) => network.get_tick_interval(),
+            None => return,
+        };
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
+            return;
+        };
+        let mut w = world.bind_mut();
+        let mut entities_manager = w.get_entities_manager_mut();
+        entities_manager.create_entity(id, time, tick_interval, position.to_godot(), rotation, components);
+    }
+
+    fn update_entity_component(&mut self, world_slug: String, id: u32, component: EntityNetworkComponent) {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
+            return;
+        };
+        let mut w = world.bind_mut();
+        let mut entities_manager = w.get_entities_manager_mut();
+        match component {
+            EntityNetworkComponent::Tag(c) => entities_manager.update_entity_tag(id, c),
+            EntityNetworkComponent::Skin(c) => entities_manager.update_entity_skin(id, c.unwrap()),
+        }
+    }
+
+    fn move_entity(
+        &mut self,
+        world_slug: String,
+        id: u32,
+        time: Instant,
+        position: NetworkVector3,
+        rotation: Rotation,
+    ) {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
+            return;
+        };
+        let mut w = world.bind_mut();
+        let mut entities_manager = w.get_entities_manager_mut();
+        entities_manager.move_entity(id, time, position.to_godot(), rotation);
+    }
+
+    fn stop_streaming_entities(&mut self, world_slug: String, ids: Vec<u32>) {
+        let mut worlds_manager = self.get_worlds_manager_mut();
+        let Some(world) = get_world_mut(&mut worlds_manager, world_slug) else {
+            return;
+        };
+        let mut w = world.bind_mut();
+        let mut entities_manager = w.get_entities_manager_mut();
+        entities_manager.despawn(ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::loopback::LoopbackServer;
+    use common::chunks::block_position::BlockPositionTrait;
+
+    /// Client without godot; keeps the applied messages
+    #[derive(Default)]
+    struct TestClient {
+        network: Option<NetworkContainer>,
+        event_queue: NetworkEventQueue,
+        stage: Option<LoadingStage>,
+        connected: Option<bool>,
+        worlds: Vec<String>,
+        chunks: Vec<ChunkPosition>,
+        edits: Vec<(BlockPosition, Option<BlockDataInfo>)>,
+        disconnected: Option<String>,
+    }
+
+    impl TestClient {
+        fn create() -> (Self, LoopbackServer) {
+            let (server, transport) = LoopbackServer::create();
+            let client = Self {
+                network: Some(NetworkContainer::from_transport(Box::new(transport))),
+                ..Default::default()
+            };
+            (client, server)
+        }
+
+        fn is_world(&self, world_slug: &String) -> bool {
+            self.worlds.contains(world_slug)
+        }
+    }
+
+    impl NetworkEventsHandler for TestClient {
+        fn get_network(&self) -> Option<&NetworkContainer> {
+            self.network.as_ref()
+        }
+
+        fn get_event_queue_mut(&mut self) -> &mut NetworkEventQueue {
+            &mut self.event_queue
+        }
+
+        fn get_network_budget(&self) -> NetworkBudgetSettings {
+            Default::default()
+        }
+
+        fn get_recorder_mut(&mut self) -> Option<&mut NetworkRecorder> {
+            None
+        }
+
+        fn stop_recording(&mut self) {}
+
+        fn get_connection_info(&self) -> ClientMessages {
+            ClientMessages::ConnectionInfo {
+                login: "test".to_string(),
+                version: VERSION.to_string(),
+                architecture: "-".to_string(),
+                rendering_device: "-".to_string(),
+            }
+        }
+
+        fn set_loading_stage(&mut self, stage: LoadingStage) {
+            self.stage = Some(stage);
+        }
+
+        fn set_loading_progress(&mut self, _text: String, _progress: f32) {}
+
+        fn disconnect(&mut self, message: String) {
+            if let Some(network) = self.network.take() {
+                network.disconnect();
+            }
+            self.event_queue.clear();
+            self.disconnected = Some(message);
+        }
+
+        fn load_resources_scheme(&mut self, _list: Vec<ResurceScheme>, _archive_hash: u64) -> Result<bool, String> {
+            Ok(true)
+        }
+
+        fn load_resources_part(&mut self, _data: Vec<u8>, _is_last: bool) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn load_settings(
+            &mut self,
+            _block_types: Vec<BlockType>,
+            _block_id_map: BTreeMap<BlockIndexType, String>,
+        ) -> Result<bool, String> {
+            Ok(self.connected.is_none())
+        }
+
+        fn on_server_connected(&mut self, blocks_changed: bool) {
+            self.connected = Some(blocks_changed);
+        }
+
+        fn spawn_world(&mut self, world_slug: String) {
+            self.worlds.push(world_slug);
+        }
+
+        fn spawn_player(
+            &mut self,
+            _world_slug: String,
+            _position: NetworkVector3,
+            _rotation: Rotation,
+            _components: Vec<EntityNetworkComponent>,
+        ) {
+        }
+
+        fn update_player_component(&mut self, _component: EntityNetworkComponent) {}
+
+        fn recieve_chunk(&mut self, world_slug: String, chunk_position: ChunkPosition, _sections: ChunkData) -> bool {
+            if !self.is_world(&world_slug) {
+                return false;
+            }
+            self.chunks.push(chunk_position);
+            true
+        }
+
+        fn unload_chunks(&mut self, _world_slug: String, chunks: Vec<ChunkPosition>) {
+            self.chunks.retain(|c| !chunks.contains(c));
+        }
+
+        fn recieve_edit_block(
+            &mut self,
+            world_slug: String,
+            position: BlockPosition,
+            new_block_info: Option<BlockDataInfo>,
+        ) -> Result<(), String> {
+            if !self.is_world(&world_slug) {
+                return Err(format!("world {} is not found", world_slug));
+            }
+            if !self.chunks.contains(&position.get_chunk_position()) {
+                return Err(format!(
+                    "edit of the not recieved chunk {}",
+                    position.get_chunk_position()
+                ));
+            }
+            self.edits.push((position, new_block_info));
+            Ok(())
+        }
+
+        fn start_streaming_entity(
+            &mut self,
+            _world_slug: String,
+            _id: u32,
+            _time: Instant,
+            _position: NetworkVector3,
+            _rotation: Rotation,
+            _components: Vec<EntityNetworkComponent>,
+        ) {
+        }
+
+        fn update_entity_component(&mut self, _world_slug: String, _id: u32, _component: EntityNetworkComponent) {}
+
+        fn move_entity(
+            &mut self,
+            _world_slug: String,
+            _id: u32,
+            _time: Instant,
+            _position: NetworkVector3,
+            _rotation: Rotation,
+        ) {
+        }
+
+        fn stop_streaming_entities(&mut self, _world_slug: String, _ids: Vec<u32>) {}
+    }
+
+    fn send_chunk(server: &LoopbackServer, world_slug: &str, chunk_position: ChunkPosition) {
+        server.send(ServerMessages::ChunkSectionInfo {
+            world_slug: world_slug.to_string(),
+            chunk_position,
+            sections: Default::default(),
+        });
+    }
+
+    #[test]
+    fn session() {
+        let (mut client, server) = TestClient::create();
+
+        server.send(ServerMessages::AllowConnection);
+        handle_network_events(&mut client).unwrap();
+        assert_eq!(client.stage, Some(LoadingStage::Resources));
+        match server.try_recv() {
+            Some((NetworkMessageType::ReliableOrdered, ClientMessages::ConnectionInfo { login, .. })) => {
+                assert_eq!(login, "test")
+            }
+            _ => panic!("connection info is not sent"),
+        }
+
+        server.send(ServerMessages::Settings {
+            block_types: Default::default(),
+            block_id_map: Default::default(),
+        });
+        handle_network_events(&mut client).unwrap();
+        assert_eq!(client.stage, Some(LoadingStage::Settings));
+        assert_eq!(client.connected, Some(true));
+        assert!(matches!(server.try_recv(), Some((_, ClientMessages::SettingsLoaded))));
+
+        let chunk_position = ChunkPosition::new(0, 0);
+        server.send(ServerMessages::SpawnWorld {
+            world_slug: "default".to_string(),
+        });
+        send_chunk(&server, "default", chunk_position);
+        send_chunk(&server, "missing", ChunkPosition::new(1, 0));
+        handle_network_events(&mut client).unwrap();
+        assert_eq!(client.chunks, vec![chunk_position]);
+
+        // Chunks of the missing world are not confirmed
+        let confirmed: Vec<ChunkPosition> = server
+            .drain()
+            .into_iter()
+            .flat_map(|(message_type, message)| match message {
+                ClientMessages::ChunkRecieved { chunk_positions } => {
+                    assert!(matches!(message_type, NetworkMessageType::WorldInfo));
+                    chunk_positions
+                }
+                _ => panic!("unexpected client message"),
+            })
+            .collect();
+        assert_eq!(confirmed, vec![chunk_position]);
+
+        server.send(ServerMessages::EditBlock {
+            world_slug: "default".to_string(),
+            position: BlockPosition::new(1, 2, 3),
+            new_block_info: Some(BlockDataInfo::create(1, None)),
+        });
+        handle_network_events(&mut client).unwrap();
+        assert_eq!(client.edits.len(), 1);
+        assert!(client.edits[0].0 == BlockPosition::new(1, 2, 3));
+        assert!(client.edits[0].1.is_some());
+    }
+
+    #[test]
+    fn edit_after_chunk_in_one_frame() {
+        let (mut client, server) = TestClient::create();
+        client.worlds.push("default".to_string());
+
+        // Edit is a control message, the chunk data it changes is handled first
+        send_chunk(&server, "default", ChunkPosition::new(0, 0));
+        server.send(ServerMessages::EditBlock {
+            world_slug: "default".to_string(),
+            position: BlockPosition::new(1, 2, 3),
+            new_block_info: None,
+        });
+        handle_network_events(&mut client).unwrap();
+        assert_eq!(client.chunks.len(), 1);
+        assert_eq!(client.edits.len(), 1);
+    }
+
+    #[test]
+    fn server_disconnect() {
+        let (mut client, server) = TestClient::create();
+        client.worlds.push("default".to_string());
+
+        server.send(ServerMessages::Disconnect {
+            message: Some("bye".to_string()),
+        });
+        send_chunk(&server, "default", ChunkPosition::new(0, 0));
+        assert!(handle_network_events(&mut client).unwrap().is_none());
+
+        assert!(client.network.is_none());
+        assert_eq!(client.disconnected, Some("Disconnected by server: bye".to_string()));
+        assert!(client.chunks.is_empty());
+        assert!(!server.is_connected());
+    }
+}
@@ -0,0 +1,193 @@
+use super::transport::{NetworkTransport, TimedMessage};
+use flume::{Receiver, Sender};
+use network::messages::{ClientMessages, NetworkMessageType, ServerMessages};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Client side of the in-process server
+///
+/// Created together with `LoopbackServer` by `LoopbackServer::create`.
+/// Messages are passed through channels, so no ports are opened.
+pub struct LoopbackTransport {
+    server_messages: Receiver<TimedMessage>,
+    client_messages: Sender<(NetworkMessageType, ClientMessages)>,
+    errors: Receiver<String>,
+    connected: Arc<AtomicBool>,
+}
+
+/// Server side of the in-process connection
+///
+/// Can be driven from any thread
+#[derive(Clone)]
+pub struct LoopbackServer {
+    server_messages: Sender<TimedMessage>,
+    client_messages: Receiver<(NetworkMessageType, ClientMessages)>,
+    errors: Sender<String>,
+    connected: Arc<AtomicBool>,
+}
+
+impl LoopbackServer {
+    pub fn create() -> (Self, LoopbackTransport) {
+        let (server_tx, server_rx) = flume::unbounded();
+        let (client_tx, client_rx) = flume::unbounded();
+        let (errors_tx, errors_rx) = flume::unbounded();
+        let connected = Arc::new(AtomicBool::new(true));
+
+        let server = Self {
+            server_messages: server_tx,
+            client_messages: client_rx,
+            errors: errors_tx,
+            connected: connected.clone(),
+        };
+        let transport = LoopbackTransport {
+            server_messages: server_rx,
+            client_messages: client_tx,
+            errors: errors_rx,
+            connected,
+        };
+        (server, transport)
+    }
+
+    /// Returns false if the client is already disconnected
+    pub fn send(&self, message: ServerMessages) -> bool {
+        if !self.is_connected() {
+            return false;
+        }
+        self.server_messages.send(TimedMessage::now(message)).is_ok()
+    }
+
+    pub fn try_recv(&self) -> Option<(NetworkMessageType, ClientMessages)> {
+        self.client_messages.try_recv().ok()
+    }
+
+    /// Waits for the next client message
+    ///
+    /// Returns None if the client transport is dropped
+    pub fn recv(&self) -> Option<(NetworkMessageType, ClientMessages)> {
+        self.client_messages.recv().ok()
+    }
+
+    pub fn drain(&self) -> Vec<(NetworkMessageType, ClientMessages)> {
+        self.client_messages.drain().collect()
+    }
+
+    /// Breaks the connection with an error, as a socket transport would do
+    pub fn send_error(&self, error: String) {
+        let _ = self.errors.send(error);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
+
+impl NetworkTransport for LoopbackTransport {
+    fn poll(&self) -> Result<Vec<TimedMessage>, String> {
+        if let Ok(error) = self.errors.try_recv() {
+            return Err(error);
+        }
+        Ok(self.server_messages.drain().collect())
+    }
+
+    fn send_message(&self, message_type: NetworkMessageType, message: &ClientMessages) {
+        if !self.connected.load(Ordering::Relaxed) {
+            return;
+        }
+        if self.client_messages.send((message_type, message.clone())).is_err() {
+            log::error!(target: "network", "Loopback server is closed");
+        }
+    }
+
+    // Messages are delivered as soon as the server sends them
+    fn get_tick_interval(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn disconnect(&self) {
+        if self.connected.swap(false, Ordering::Relaxed) {
+            log::info!(target: "network", "Disconnected from the loopback server");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake() {
+        let (server, transport) = LoopbackServer::create();
+        transport.start();
+        assert!(server.send(ServerMessages::AllowConnection));
+
+        let messages = transport.poll().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0].message, ServerMessages::AllowConnection));
+
+        let connection_info = ClientMessages::ConnectionInfo {
+            login: "test".to_string(),
+            version: "0".to_string(),
+            architecture: "-".to_string(),
+            rendering_device: "-".to_string(),
+        };
+        transport.send_message(NetworkMessageType::ReliableOrdered, &connection_info);
+        match server.try_recv() {
+            Some((_, ClientMessages::ConnectionInfo { login, .. })) => assert_eq!(login, "test"),
+            _ => panic!("connection info is not recieved"),
+        }
+    }
+
+    #[test]
+    fn message_delivery() {
+        let (server, transport) = LoopbackServer::create();
+        for i in 0..3 {
+            server.send(ServerMessages::ConsoleOutput { message: i.to_string() });
+        }
+
+        let messages: Vec<String> = transport
+            .poll()
+            .unwrap()
+            .into_iter()
+            .map(|m| match m.message {
+                ServerMessages::ConsoleOutput { message } => message,
+                _ => panic!("unexpected message"),
+            })
+            .collect();
+        assert_eq!(messages, vec!["0", "1", "2"]);
+        assert!(transport.poll().unwrap().is_empty());
+
+        for i in 0..2 {
+            let message = ClientMessages::ConsoleInput { command: i.to_string() };
+            transport.send_message(NetworkMessageType::ReliableOrdered, &message);
+        }
+        assert_eq!(server.drain().len(), 2);
+        assert!(server.try_recv().is_none());
+    }
+
+    #[test]
+    fn disconnect() {
+        let (server, transport) = LoopbackServer::create();
+        assert!(server.is_connected());
+
+        transport.disconnect();
+        assert!(!server.is_connected());
+        assert!(!server.send(ServerMessages::AllowConnection));
+
+        let message = ClientMessages::ConsoleInput {
+            command: "-".to_string(),
+        };
+        transport.send_message(NetworkMessageType::ReliableOrdered, &message);
+        assert!(server.try_recv().is_none());
+    }
+
+    #[test]
+    fn server_error() {
+        let (server, transport) = LoopbackServer::create();
+        server.send_error("closed".to_string());
+        assert_eq!(transport.poll().err(), Some("closed".to_string()));
+
+        drop(transport);
+        assert!(server.recv().is_none());
+    }
+}
@@ -1,2 +1,8 @@
 pub mod client;
+pub mod event_queue;
 pub mod events;
+pub mod loopback;
+pub mod reconnect;
+pub mod recording;
+pub mod socket;
+pub mod transport;
@@ -0,0 +1,121 @@
+use super::client::NetworkContainer;
+use flume::{Receiver, TryRecvError};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Automatic reconnect configuration
+///
+/// Delay between attempts grows from `initial_delay_ms`
+/// by `multiplier` until it reaches `max_delay_ms`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct ReconnectSettings {
+    pub enabled: bool,
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f32,
+}
+
+impl Default for ReconnectSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 8,
+            initial_delay_ms: 500,
+            max_delay_ms: 15_000,
+            multiplier: 2.0,
+        }
+    }
+}
+
+/// State of the reconnect loop after the connection was lost
+pub struct ReconnectState {
+    lost_network: NetworkContainer,
+    settings: ReconnectSettings,
+    reason: String,
+    attempt: u32,
+    next_attempt_at: Instant,
+
+    /// Result of the connection opened in the background
+    connecting: Option<Receiver<Result<NetworkContainer, String>>>,
+}
+
+impl ReconnectState {
+    pub fn create(lost_network: NetworkContainer, settings: ReconnectSettings, reason: String) -> Self {
+        let mut state = Self {
+            lost_network,
+            settings,
+            reason,
+            attempt: 0,
+            next_attempt_at: Instant::now(),
+            connecting: None,
+        };
+        state.schedule_next();
+        state
+    }
+
+    fn get_delay(&self) -> Duration {
+        let factor = self.settings.multiplier.max(1.0).powi(self.attempt.saturating_sub(1) as i32);
+        let delay = (self.settings.initial_delay_ms as f32 * factor) as u64;
+        Duration::from_millis(delay.min(self.settings.max_delay_ms))
+    }
+
+    /// Schedules the next attempt
+    ///
+    /// Returns false if all attempts are exhausted
+    pub fn schedule_next(&mut self) -> bool {
+        if self.attempt >= self.settings.max_attempts {
+            return false;
+        }
+        self.attempt += 1;
+        self.next_attempt_at = Instant::now() + self.get_delay();
+        true
+    }
+
+    /// Opens a new connection to the server of the lost one in a separate thread,
+    /// so the frame is not blocked while the server is unreachable
+    pub fn start_attempt(&mut self) {
+        let (result_tx, result_rx) = flume::bounded(1);
+        let lost_network = self.lost_network.clone();
+        std::thread::spawn(move || {
+            let _ = result_tx.send(lost_network.reconnect());
+        });
+        self.connecting = Some(result_rx);
+    }
+
+    pub fn is_connecting(&self) -> bool {
+        self.connecting.is_some()
+    }
+
+    /// Returns the result when the attempt is finished
+    pub fn poll_attempt(&mut self) -> Option<Result<NetworkContainer, String>> {
+        let result = match self.connecting.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err("reconnect thread is stopped".to_string()),
+        };
+        self.connecting = None;
+        Some(result)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        Instant::now() >= self.next_attempt_at
+    }
+
+    pub fn get_attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn get_max_attempts(&self) -> u32 {
+        self.settings.max_attempts
+    }
+
+    pub fn get_reason(&self) -> &String {
+        &self.reason
+    }
+
+    pub fn get_time_left(&self) -> Duration {
+        self.next_attempt_at.saturating_duration_since(Instant::now())
+    }
+}
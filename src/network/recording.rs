@@ -0,0 +1,261 @@
+use super::socket::NETWORK_TICK_RATE;
+use super::transport::{NetworkTransport, TimedMessage};
+use crate::utils::settings::GameSettings;
+use chrono::Local;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use network::messages::{ClientMessages, NetworkMessageType, ServerMessages};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{create_dir_all, File},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const RECORDINGS_DIR: &str = "recordings";
+const RECORDING_EXTENSION: &str = "jsonl.gz";
+
+#[derive(Serialize)]
+struct NetworkRecordRef<'a> {
+    frame: u64,
+    time_ms: u64,
+    message: &'a ServerMessages,
+}
+
+#[derive(Deserialize)]
+struct NetworkRecord {
+    frame: u64,
+    time_ms: u64,
+    message: ServerMessages,
+}
+
+fn get_recordings_path() -> Result<PathBuf, String> {
+    let mut path = GameSettings::get_game_data_path()?;
+    path.push(RECORDINGS_DIR);
+    if !path.exists() {
+        if let Err(e) = create_dir_all(&path) {
+            return Err(format!("Recordings directory \"{}\" create error: {}", path.display(), e));
+        }
+    }
+    Ok(path)
+}
+
+/// Writes every decoded server message with the frame timestamp
+///
+/// One gzipped json record per line
+pub struct NetworkRecorder {
+    path: PathBuf,
+    writer: GzEncoder<BufWriter<File>>,
+    started: Instant,
+    frame: u64,
+}
+
+impl NetworkRecorder {
+    pub fn create() -> Result<Self, String> {
+        let mut path = get_recordings_path()?;
+        path.push(format!("{}.{}", Local::now().format("%Y-%m-%d_%H-%M-%S"), RECORDING_EXTENSION));
+
+        let file = match File::create(&path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Recording file \"{}\" create error: {}", path.display(), e)),
+        };
+        log::info!(target: "network", "Network recording started: &6{}", path.display());
+        Ok(Self {
+            path,
+            writer: GzEncoder::new(BufWriter::new(file), Compression::fast()),
+            started: Instant::now(),
+            frame: 0,
+        })
+    }
+
+    /// Must be called once per frame before messages are recorded
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn record(&mut self, message: &ServerMessages) -> Result<(), String> {
+        let record = NetworkRecordRef {
+            frame: self.frame,
+            time_ms: self.started.elapsed().as_millis() as u64,
+            message,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(l) => l,
+            Err(e) => return Err(format!("Network record serialize error: {}", e)),
+        };
+        if let Err(e) = writeln!(self.writer, "{}", line) {
+            return Err(format!("Network record \"{}\" write error: {}", self.path.display(), e));
+        }
+        Ok(())
+    }
+
+    /// Returns the newest recording from the game data path
+    pub fn get_last_recording() -> Result<Option<PathBuf>, String> {
+        let path = get_recordings_path()?;
+        let entries = match std::fs::read_dir(&path) {
+            Ok(e) => e,
+            Err(e) => return Err(format!("Recordings directory \"{}\" read error: {}", path.display(), e)),
+        };
+        let mut recordings: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.to_string_lossy().ends_with(RECORDING_EXTENSION))
+            .collect();
+        // File names are timestamps, so the last one is the newest
+        recordings.sort();
+        Ok(recordings.pop())
+    }
+}
+
+/// The recording of the crashed game ends with a truncated line;
+/// it is skipped, but the broken line in the middle of the file is an error
+fn read_records(reader: impl BufRead, path: &Path) -> Result<VecDeque<NetworkRecord>, String> {
+    let mut records: VecDeque<NetworkRecord> = Default::default();
+    let mut lines = reader.lines().enumerate().peekable();
+    while let Some((i, line)) = lines.next() {
+        let error = match line {
+            Ok(line) => match serde_json::from_str::<NetworkRecord>(&line) {
+                Ok(r) => {
+                    records.push_back(r);
+                    continue;
+                }
+                Err(e) => format!("line {} error: {}", i + 1, e),
+            },
+            // Unfinished gzip stream, nothing can be read after it
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => format!("line {} read error: {}", i + 1, e),
+            Err(e) => return Err(format!("Replay file \"{}\" read error: {}", path.display(), e)),
+        };
+        if lines.peek().is_some_and(|(_, line)| line.is_ok()) {
+            return Err(format!("Replay file \"{}\" {}", path.display(), error));
+        }
+        log::warn!(target: "network", "Replay file &e{}&r is truncated, the rest is skipped: {}", path.display(), error);
+        break;
+    }
+    Ok(records)
+}
+
+/// Plays recorded server messages back at the original pacing
+pub struct NetworkReplay {
+    path: PathBuf,
+    records: Mutex<VecDeque<NetworkRecord>>,
+    started: Mutex<Option<Instant>>,
+}
+
+impl NetworkReplay {
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Replay file \"{}\" open error: {}", path.display(), e)),
+        };
+
+        let records = read_records(BufReader::new(GzDecoder::new(file)), &path)?;
+
+        let frames = records.back().map(|r| r.frame).unwrap_or(0);
+        let duration = Duration::from_millis(records.back().map(|r| r.time_ms).unwrap_or(0));
+        log::info!(target: "network", "Replay loaded: &6{}&r &7(messages:{} frames:{} duration:{:.1?})", path.display(), records.len(), frames, duration);
+        Ok(Self {
+            path,
+            records: Mutex::new(records),
+            started: Mutex::new(None),
+        })
+    }
+
+    /// Returns all messages whose time has come
+    ///
+    /// Messages get the time they were recieved in the recorded session
+    fn take_ready_messages(&self) -> Vec<TimedMessage> {
+        let started = *self.started.lock().unwrap().get_or_insert_with(Instant::now);
+        let elapsed = started.elapsed();
+
+        let mut records = self.records.lock().unwrap();
+        let mut result: Vec<TimedMessage> = Default::default();
+        while let Some(record) = records.front() {
+            let time = Duration::from_millis(record.time_ms);
+            if time > elapsed {
+                break;
+            }
+            result.push(TimedMessage {
+                time: started + time,
+                message: records.pop_front().unwrap().message,
+            });
+        }
+
+        if !result.is_empty() && records.is_empty() {
+            log::info!(target: "network", "Replay &6{}&r finished", self.path.display());
+        }
+        result
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.records.lock().unwrap().is_empty()
+    }
+}
+
+impl NetworkTransport for NetworkReplay {
+    fn poll(&self) -> Result<Vec<TimedMessage>, String> {
+        Ok(self.take_ready_messages())
+    }
+
+    // Replay has nobody to answer
+    fn send_message(&self, _message_type: NetworkMessageType, _message: &ClientMessages) {}
+
+    // Messages keep the time they were recieved by the socket
+    fn get_tick_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / NETWORK_TICK_RATE as f64)
+    }
+
+    fn is_replay(&self) -> bool {
+        true
+    }
+
+    fn disconnect(&self) {
+        if !self.is_finished() {
+            log::info!(target: "network", "Replay stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_line(frame: u64) -> String {
+        let message = ServerMessages::ConsoleOutput {
+            message: frame.to_string(),
+        };
+        let record = NetworkRecordRef {
+            frame,
+            time_ms: frame * 50,
+            message: &message,
+        };
+        serde_json::to_string(&record).unwrap()
+    }
+
+    #[test]
+    fn truncated_last_line() {
+        let last = get_line(3);
+        let data = format!("{}\n{}\n{}", get_line(1), get_line(2), &last[..last.len() / 2]);
+        let records = read_records(data.as_bytes(), Path::new("test")).unwrap();
+        assert_eq!(records.iter().map(|r| r.frame).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn broken_middle_line() {
+        let data = format!("{}\n{{\"frame\n{}\n", get_line(1), get_line(3));
+        assert!(read_records(data.as_bytes(), Path::new("test")).is_err());
+    }
+
+    #[test]
+    fn truncated_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        for frame in 0..100 {
+            writeln!(encoder, "{}", get_line(frame)).unwrap();
+        }
+        let data = encoder.finish().unwrap();
+
+        let reader = BufReader::new(GzDecoder::new(&data[..data.len() - 20]));
+        let records = read_records(reader, Path::new("test")).unwrap();
+        assert!(records.iter().enumerate().all(|(i, r)| r.frame == i as u64));
+    }
+}
@@ -0,0 +1,321 @@
+use super::transport::{NetworkQueueStats, NetworkTransport, TimedMessage};
+use flume::{Receiver, Sender, TryRecvError, TrySendError};
+use network::client::{IClientNetwork, NetworkInfo};
+use network::messages::{ClientMessages, NetworkMessageType};
+use network::NetworkClient;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+/// How many times per second the network thread steps the client
+pub const NETWORK_TICK_RATE: u32 = 60;
+
+/// When the main thread does not keep up, the network thread
+/// stops stepping the client until there is room in the queue
+const INCOMING_QUEUE_SIZE: usize = 4096;
+
+const OUTGOING_QUEUE_SIZE: usize = 1024;
+
+enum NetworkEvent {
+    Message(TimedMessage),
+    Error(String),
+}
+
+#[derive(Default)]
+struct SocketShared {
+    network_info: Mutex<Option<NetworkInfo>>,
+    incoming_peak: AtomicUsize,
+    outgoing_dropped: AtomicUsize,
+}
+
+/// Everything the network thread owns
+struct NetworkThread {
+    runtime: Runtime,
+    client: NetworkClient,
+    incoming: Sender<NetworkEvent>,
+    outgoing: Receiver<(NetworkMessageType, ClientMessages)>,
+    cancel: watch::Receiver<bool>,
+    shared: Arc<SocketShared>,
+}
+
+/// Transport to the real server over `network::NetworkClient`
+///
+/// The client is owned by a dedicated thread which steps it with a fixed tick rate;
+/// messages are passed through bounded channels.
+pub struct SocketTransport {
+    ip_port: String,
+
+    // Taken by the network thread on start
+    thread: Mutex<Option<NetworkThread>>,
+
+    incoming: Receiver<NetworkEvent>,
+    outgoing: Sender<(NetworkMessageType, ClientMessages)>,
+    cancel: watch::Sender<bool>,
+    shared: Arc<SocketShared>,
+
+    // Reliable messages which did not fit in the outgoing queue;
+    // the main thread never waits for the network thread
+    outgoing_overflow: Mutex<VecDeque<(NetworkMessageType, ClientMessages)>>,
+
+    // Error recieved after messages of the same poll; returned on the next one
+    delayed_error: Mutex<Option<String>>,
+}
+
+/// Tokio runtime нельзя дропать из асинхронного контекста — это вызывает панику
+/// "Cannot drop a runtime in a context where blocking is not allowed".
+/// Godot может уничтожать объекты в async контексте, поэтому если поток
+/// так и не был запущен, используется `shutdown_background()` который не блокирует.
+impl Drop for SocketTransport {
+    fn drop(&mut self) {
+        let _ = self.cancel.send(true);
+        if let Some(thread) = self.thread.lock().take() {
+            thread.runtime.shutdown_background();
+        }
+    }
+}
+
+impl SocketTransport {
+    pub fn create(ip_port: String) -> Result<Self, String> {
+        log::info!(target: "network", "Connecting to the server at &e{}", ip_port);
+
+        let runtime = Runtime::new().unwrap();
+        let result = runtime.block_on(async { NetworkClient::new(ip_port.clone()).await });
+
+        let client = match result {
+            Ok(n) => n,
+            Err(e) => {
+                runtime.shutdown_background();
+                return Err(e);
+            }
+        };
+
+        let (incoming_tx, incoming_rx) = flume::bounded(INCOMING_QUEUE_SIZE);
+        let (outgoing_tx, outgoing_rx) = flume::bounded(OUTGOING_QUEUE_SIZE);
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        let shared: Arc<SocketShared> = Default::default();
+
+        let thread = NetworkThread {
+            runtime,
+            client,
+            incoming: incoming_tx,
+            outgoing: outgoing_rx,
+            cancel: cancel_rx,
+            shared: shared.clone(),
+        };
+        Ok(Self {
+            ip_port,
+            thread: Mutex::new(Some(thread)),
+            incoming: incoming_rx,
+            outgoing: outgoing_tx,
+            cancel: cancel_tx,
+            shared,
+            outgoing_overflow: Default::default(),
+            delayed_error: Default::default(),
+        })
+    }
+
+    /// Moves overflowed messages to the queue keeping their order
+    fn flush_overflow(&self) {
+        let mut overflow = self.outgoing_overflow.lock();
+        while let Some(message) = overflow.pop_front() {
+            match self.outgoing.try_send(message) {
+                Ok(()) => (),
+                Err(TrySendError::Full(message)) => {
+                    overflow.push_front(message);
+                    return;
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    overflow.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl NetworkThread {
+    fn run(self) {
+        let NetworkThread {
+            runtime,
+            client,
+            incoming,
+            outgoing,
+            mut cancel,
+            shared,
+        } = self;
+
+        runtime.block_on(async {
+            network_loop(&client, &incoming, &outgoing, &mut cancel, &shared).await;
+
+            if client.is_connected() {
+                log::info!(target: "network", "Disconnected from the server");
+                client.disconnect();
+            }
+        });
+
+        // The main thread must know that no more messages will come;
+        // it's ignored if the transport is already dropped
+        let _ = incoming.try_send(NetworkEvent::Error("connection to the server is closed".to_string()));
+        log::info!(target: "network", "Network thread exited;");
+    }
+}
+
+/// Returns when the connection is closed or cancelled
+async fn network_loop(
+    client: &NetworkClient,
+    incoming: &Sender<NetworkEvent>,
+    outgoing: &Receiver<(NetworkMessageType, ClientMessages)>,
+    cancel: &mut watch::Receiver<bool>,
+    shared: &SocketShared,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / NETWORK_TICK_RATE as f64));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_tick = Instant::now();
+
+    loop {
+        tokio::select! {
+            // Sender is dropped together with the transport, so it's cancellation too
+            _ = cancel.changed() => return,
+            _ = interval.tick() => (),
+        }
+
+        for (message_type, message) in outgoing.drain() {
+            client.send_message(message_type, &message);
+        }
+
+        let delta = last_tick.elapsed();
+        last_tick = Instant::now();
+        let is_connected = client.step(delta).await;
+        *shared.network_info.lock() = Some(client.get_network_info().clone());
+
+        // Errors of the last step are forwarded before the loop exits
+        let events = client
+            .iter_errors()
+            .map(NetworkEvent::Error)
+            .chain(client.iter_server_messages().map(|m| NetworkEvent::Message(TimedMessage::now(m))));
+        for event in events {
+            // Waits for the main thread if the queue is full
+            tokio::select! {
+                result = incoming.send_async(event) => {
+                    if result.is_err() {
+                        return;
+                    }
+                }
+                _ = cancel.changed() => return,
+            }
+            shared.incoming_peak.fetch_max(incoming.len(), Ordering::Relaxed);
+        }
+
+        if !is_connected {
+            return;
+        }
+    }
+}
+
+impl NetworkTransport for SocketTransport {
+    fn start(&self) {
+        let Some(thread) = self.thread.lock().take() else {
+            log::error!(target: "network", "Network thread is already spawned");
+            return;
+        };
+        log::info!(target: "network", "Spawning network thread &7(tick rate: {})", NETWORK_TICK_RATE);
+
+        std::thread::Builder::new()
+            .name("network".to_string())
+            .spawn(move || thread.run())
+            .expect("network thread spawn error");
+    }
+
+    fn poll(&self) -> Result<Vec<TimedMessage>, String> {
+        self.flush_overflow();
+
+        if let Some(error) = self.delayed_error.lock().take() {
+            return Err(error);
+        }
+
+        let mut messages: Vec<TimedMessage> = Default::default();
+        loop {
+            let error = match self.incoming.try_recv() {
+                Ok(NetworkEvent::Message(message)) => {
+                    messages.push(message);
+                    continue;
+                }
+                Ok(NetworkEvent::Error(error)) => error,
+                Err(TryRecvError::Empty) => break,
+                // Network thread exited without the error event
+                Err(TryRecvError::Disconnected) => "network thread is stopped".to_string(),
+            };
+
+            // Messages recieved before the error must be handled first
+            if messages.is_empty() {
+                return Err(error);
+            }
+            *self.delayed_error.lock() = Some(error);
+            break;
+        }
+        Ok(messages)
+    }
+
+    fn send_message(&self, message_type: NetworkMessageType, message: &ClientMessages) {
+        let is_unreliable = matches!(message_type, NetworkMessageType::Unreliable);
+        if !self.outgoing_overflow.lock().is_empty() {
+            self.flush_overflow();
+        }
+
+        let mut overflow = self.outgoing_overflow.lock();
+        if !overflow.is_empty() && !is_unreliable {
+            overflow.push_back((message_type, message.clone()));
+            return;
+        }
+        match self.outgoing.try_send((message_type, message.clone())) {
+            Ok(()) => (),
+            Err(TrySendError::Full(message)) => {
+                if is_unreliable {
+                    self.shared.outgoing_dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                // Reliable messages can't be lost; they will be sent on the next ticks
+                overflow.push_back(message);
+            }
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+
+    fn get_tick_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / NETWORK_TICK_RATE as f64)
+    }
+
+    fn get_network_info(&self) -> Option<NetworkInfo> {
+        self.shared.network_info.lock().clone()
+    }
+
+    fn get_queue_stats(&self) -> Option<NetworkQueueStats> {
+        Some(NetworkQueueStats {
+            tick_rate: NETWORK_TICK_RATE,
+            incoming: self.incoming.len(),
+            incoming_capacity: INCOMING_QUEUE_SIZE,
+            incoming_peak: self.shared.incoming_peak.load(Ordering::Relaxed),
+            outgoing: self.outgoing.len() + self.outgoing_overflow.lock().len(),
+            outgoing_capacity: OUTGOING_QUEUE_SIZE,
+            outgoing_dropped: self.shared.outgoing_dropped.load(Ordering::Relaxed),
+        })
+    }
+
+    fn reconnect(&self) -> Result<Box<dyn NetworkTransport>, String> {
+        Ok(Box::new(Self::create(self.ip_port.clone())?))
+    }
+
+    fn can_reconnect(&self) -> bool {
+        true
+    }
+
+    fn disconnect(&self) {
+        // The network thread disconnects the client and exits
+        let _ = self.cancel.send(true);
+    }
+}
@@ -0,0 +1,80 @@
+use network::client::NetworkInfo;
+use network::messages::{ClientMessages, NetworkMessageType, ServerMessages};
+use std::time::{Duration, Instant};
+
+/// Server message with the time the transport recieved it
+///
+/// Messages can wait in the queues for several frames,
+/// so the time of the handling is not the time of the server state.
+pub struct TimedMessage {
+    pub time: Instant,
+    pub message: ServerMessages,
+}
+
+impl TimedMessage {
+    pub fn now(message: ServerMessages) -> Self {
+        Self {
+            time: Instant::now(),
+            message,
+        }
+    }
+}
+
+/// Source of server messages and sink of client messages
+///
+/// `NetworkContainer` works only through this trait,
+/// so the main scene does not know what is behind it: a socket, a replay
+/// or an in-process server.
+pub trait NetworkTransport: Send + Sync {
+    /// Starts background processing if the transport needs it
+    fn start(&self) {}
+
+    /// Collects all server messages recieved since the previous call
+    fn poll(&self) -> Result<Vec<TimedMessage>, String>;
+
+    fn send_message(&self, message_type: NetworkMessageType, message: &ClientMessages);
+
+    /// Shortest time between two server states;
+    /// messages recieved closer to each other came in one burst
+    fn get_tick_interval(&self) -> Duration;
+
+    fn get_network_info(&self) -> Option<NetworkInfo> {
+        None
+    }
+
+    fn get_queue_stats(&self) -> Option<NetworkQueueStats> {
+        None
+    }
+
+    /// Opens a new transport to the same destination
+    fn reconnect(&self) -> Result<Box<dyn NetworkTransport>, String> {
+        Err("transport cannot be reconnected".to_string())
+    }
+
+    fn can_reconnect(&self) -> bool {
+        false
+    }
+
+    /// Replayed messages must not be recorded again
+    fn is_replay(&self) -> bool {
+        false
+    }
+
+    fn disconnect(&self);
+}
+
+/// Depth of the transport queues between the network thread and the main thread
+#[derive(Clone, Default, Debug)]
+pub struct NetworkQueueStats {
+    pub tick_rate: u32,
+
+    /// Server messages waiting for the main thread
+    pub incoming: usize,
+    pub incoming_capacity: usize,
+    pub incoming_peak: usize,
+
+    /// Client messages waiting for the network tick
+    pub outgoing: usize,
+    pub outgoing_capacity: usize,
+    pub outgoing_dropped: usize,
+}
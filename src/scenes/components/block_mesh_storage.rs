@@ -2,11 +2,16 @@ use crate::{
     client_scripts::resource_manager::{ResourceManager, ResourceStorage},
     utils::textures::texture_mapper::TextureMapper,
     world::{
+        block_render::BlockShape,
         block_storage::BlockStorage,
         chunks::{
             chunk_data_formatter::generate_single_block,
-            mesh::mesh_generator::{generate_buffer, generate_mesh},
-            objects_container::ObjectsContainer,
+            mesh::{
+                block_shapes::generate_shapes_geometry,
+                mesh_generator::{generate_buffer, generate_mesh, MeshPass},
+                mesh_settings::ChunkMeshSettings,
+            },
+            objects_container::create_model_node,
         },
         worlds_manager::WorldMaterials,
     },
@@ -14,10 +19,7 @@ use crate::{
 use ahash::HashMap;
 use common::{
     blocks::block_type::{BlockContent, BlockType},
-    chunks::{
-        block_position::BlockPosition,
-        chunk_data::{BlockDataInfo, BlockIndexType},
-    },
+    chunks::chunk_data::{BlockDataInfo, BlockIndexType},
 };
 use godot::{classes::MeshInstance3D, prelude::*};
 
@@ -40,6 +42,7 @@ impl BlockMeshStorage {
         block_id: BlockIndexType,
         block_type: &BlockType,
         materials: &WorldMaterials,
+        mesh_settings: &ChunkMeshSettings,
         texture_mapper: &TextureMapper,
         block_storage: &BlockStorage,
         resource_storage: &ResourceStorage,
@@ -49,8 +52,23 @@ impl BlockMeshStorage {
                 let block_info = BlockDataInfo::create(block_id, None);
                 let bordered_chunk_data = generate_single_block(&block_type, &block_info);
 
-                let buffer = generate_buffer(&bordered_chunk_data);
-                let mesh_ist = generate_mesh(texture_mapper, &buffer, &block_storage, false);
+                let mesh_data = match block_storage.get_shape(&block_info) {
+                    BlockShape::Cube => {
+                        let buffer = generate_buffer(&bordered_chunk_data);
+                        generate_mesh(
+                            texture_mapper,
+                            &bordered_chunk_data,
+                            None,
+                            &buffer,
+                            &block_storage,
+                            mesh_settings,
+                            MeshPass::Opaque,
+                            [0; 3],
+                        )
+                    }
+                    _ => generate_shapes_geometry(texture_mapper, &bordered_chunk_data, None, &block_storage, [0; 3]).mesh,
+                };
+                let mesh_ist = mesh_data.build_mesh();
 
                 let mut mesh = MeshInstance3D::new_alloc();
                 mesh.set_name("Block mesh");
@@ -65,41 +83,19 @@ impl BlockMeshStorage {
                 self.meshes.insert(block_id, (BlockMesh::Texture(obj), 2.0));
             }
             BlockContent::ModelCube {
-                model,
-                icon_size,
-                collider_type,
+                model, icon_size, ..
             } => {
-                let mut objects_container = ObjectsContainer::new_alloc();
-                objects_container.set_name(&format!("ObjectsContainer #{}", block_id));
-                let position = BlockPosition::new(0, 0, 0);
-                objects_container
-                    .bind_mut()
-                    .create_block_model(
-                        &position,
-                        model,
-                        collider_type,
-                        None,
-                        resource_storage,
-                        None,
-                    )
-                    .unwrap();
+                let glb = resource_storage
+                    .get_media(model)
+                    .and_then(|media| media.get_glb())
+                    .expect(&format!("model:{} is not found", model));
+                let obj = create_model_node(glb, None);
 
                 let icon_size = match icon_size {
                     Some(s) => s,
                     None => &1.0,
                 };
 
-                // Get first object of chunk
-                let obj = objects_container.bind().get_first().clone();
-
-                // Get content of object
-                let obj = obj
-                    .bind()
-                    .get_content()
-                    .duplicate()
-                    .unwrap()
-                    .cast::<Node3D>();
-
                 self.meshes
                     .insert(block_id, (BlockMesh::ModelCube(obj), 3.0 / icon_size));
             }
@@ -109,6 +105,7 @@ impl BlockMeshStorage {
     pub fn init(
         block_storage: &BlockStorage,
         materials: &WorldMaterials,
+        mesh_settings: &ChunkMeshSettings,
         resource_manager: &ResourceManager,
         texture_mapper: &TextureMapper,
     ) -> Gd<Self> {
@@ -123,6 +120,7 @@ impl BlockMeshStorage {
                 block_id,
                 block_type,
                 materials,
+                mesh_settings,
                 texture_mapper,
                 block_storage,
                 &*resource_manager.get_resources_storage(),
@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+use strum_macros::Display;
+
+/// If the spawn area is not loaded in this time, the player is released anyway
+pub const SPAWN_AREA_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Display, Clone, Copy, PartialEq, Debug)]
+pub enum LoadingStage {
+    Connecting,
+    Resources,
+    Settings,
+    Chunks,
+    Ready,
+}
+
+impl LoadingStage {
+    /// Part of the whole progress bar at which the stage starts and ends
+    fn get_range(&self) -> (f32, f32) {
+        match self {
+            LoadingStage::Connecting => (0.0, 0.05),
+            LoadingStage::Resources => (0.05, 0.5),
+            LoadingStage::Settings => (0.5, 0.6),
+            LoadingStage::Chunks => (0.6, 1.0),
+            LoadingStage::Ready => (1.0, 1.0),
+        }
+    }
+}
+
+/// Loading state machine
+///
+/// connecting → resources → settings/textures → initial chunks → ready
+pub struct LoadingProgress {
+    stage: LoadingStage,
+    stage_progress: f32,
+    stage_started: Instant,
+    started: Instant,
+}
+
+impl Default for LoadingProgress {
+    fn default() -> Self {
+        Self {
+            stage: LoadingStage::Connecting,
+            stage_progress: 0.0,
+            stage_started: Instant::now(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl LoadingProgress {
+    /// Starts the loading from the stage
+    pub fn restart(&mut self, stage: LoadingStage) {
+        *self = Default::default();
+        self.stage = stage;
+    }
+
+    pub fn set_stage(&mut self, stage: LoadingStage) {
+        if self.stage == stage {
+            return;
+        }
+        log::info!(target: "main", "Loading stage &e{}&r finished &8(executed:{:.2?})", self.stage, self.stage_started.elapsed());
+
+        self.stage = stage;
+        self.stage_progress = 0.0;
+        self.stage_started = Instant::now();
+
+        if stage == LoadingStage::Ready {
+            log::info!(target: "main", "&aLoading is complete &8(total:{:.2?})", self.started.elapsed());
+        }
+    }
+
+    pub fn get_stage(&self) -> LoadingStage {
+        self.stage
+    }
+
+    pub fn get_stage_elapsed(&self) -> Duration {
+        self.stage_started.elapsed()
+    }
+
+    /// Progress inside the current stage from 0 to 1
+    pub fn set_stage_progress(&mut self, progress: f32) {
+        self.stage_progress = progress.clamp(0.0, 1.0);
+    }
+
+    /// Progress of the whole loading from 0 to 1
+    pub fn get_total_progress(&self) -> f32 {
+        let (from, to) = self.stage.get_range();
+        from + (to - from) * self.stage_progress
+    }
+}
@@ -1,7 +1,11 @@
 use super::{
     connect_scene::ConnectScreen, main_menu_button::MainMenuButton, main_scene::MainScene, text_screen::TextScreen,
 };
-use crate::{utils::settings::GameSettings, VERSION};
+use crate::{
+    network::{client::ConnectionTarget, recording::NetworkRecorder},
+    utils::settings::GameSettings,
+    VERSION,
+};
 use godot::{
     classes::{BoxContainer, Control, Engine, IControl, RichTextLabel},
     meta::AsArg,
@@ -10,6 +14,7 @@ use godot::{
 use std::{cell::RefCell, rc::Rc};
 
 const TEXT_CONNECT: &str = "Connect";
+const TEXT_REPLAY: &str = "Replay";
 const TEXT_EXIT: &str = "Exit";
 
 #[derive(GodotClass)]
@@ -61,6 +66,24 @@ impl MainMenu {
 
         self.buttons_holder.as_mut().unwrap().add_child(&menu_button);
     }
+
+    fn start_main_scene(&mut self, target: ConnectionTarget, username: String) {
+        self.gui.as_mut().unwrap().set_visible(false);
+
+        let main_scene = MainScene::create(target, username, self.game_settings.clone());
+        main_scene
+            .signals()
+            .network_disconnect()
+            .connect_other(&self.to_gd(), MainMenu::on_network_disconnect);
+        self.base_mut().add_child(&main_scene);
+        self.main_scene = Some(main_scene);
+    }
+
+    fn show_message(&mut self, message: String) {
+        let mut text_screen = self.text_screen.bind_mut();
+        text_screen.update_text(message);
+        text_screen.toggle(true);
+    }
 }
 
 #[godot_api]
@@ -87,15 +110,30 @@ impl MainMenu {
             game_settings.save().expect("Settings save error");
         }
 
-        self.gui.as_mut().unwrap().set_visible(false);
+        self.start_main_scene(ConnectionTarget::Server(ip_port.to_string()), username.to_string());
+    }
 
-        let main_scene = MainScene::create(ip_port.to_string(), username.to_string(), self.game_settings.clone());
-        main_scene
-            .signals()
-            .network_disconnect()
-            .connect_other(&self.to_gd(), MainMenu::on_network_disconnect);
-        self.base_mut().add_child(&main_scene);
-        self.main_scene = Some(main_scene);
+    /// Plays the last network recording without a server
+    #[func]
+    fn replay_pressed(&mut self) {
+        let path = match NetworkRecorder::get_last_recording() {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                self.show_message("There are no network recordings yet.\nEnable them with \"setting record true\" in the console.".to_string());
+                return;
+            }
+            Err(e) => {
+                self.show_message(format!("Network recordings error: {}", e));
+                return;
+            }
+        };
+        let username = self
+            .game_settings
+            .borrow()
+            .username
+            .clone()
+            .unwrap_or("replay".to_string());
+        self.start_main_scene(ConnectionTarget::Replay(path), username);
     }
 
     #[func]
@@ -105,9 +143,7 @@ impl MainMenu {
         }
 
         if message.len() > 0 {
-            let mut text_screen = self.text_screen.bind_mut();
-            text_screen.update_text(message.to_string());
-            text_screen.toggle(true);
+            self.show_message(message.to_string());
         }
 
         self.gui.as_mut().unwrap().set_visible(true);
@@ -168,6 +204,7 @@ impl IControl for MainMenu {
         }
 
         self.add_button(TEXT_CONNECT, "connect_pressed");
+        self.add_button(TEXT_REPLAY, "replay_pressed");
         self.add_button(TEXT_EXIT, "exit_pressed");
 
         let mut connect_screen = self
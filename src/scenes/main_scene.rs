@@ -5,8 +5,12 @@ use crate::controller::enums::controller_actions::ControllerActions;
 use crate::controller::player_action::PlayerAction;
 use crate::controller::selected_item::{SelectedItem, SelectedItemGd};
 use crate::debug::debug_info::DebugInfo;
-use crate::network::client::NetworkContainer;
+use crate::network::client::{ConnectionTarget, NetworkContainer};
+use crate::network::event_queue::{NetworkBudgetSettings, NetworkEventQueue};
 use crate::network::events::handle_network_events;
+use crate::network::reconnect::ReconnectState;
+use crate::network::recording::NetworkRecorder;
+use crate::scenes::loading_progress::{LoadingProgress, LoadingStage, SPAWN_AREA_TIMEOUT};
 use crate::scenes::text_screen::TextScreen;
 use crate::utils::settings::GameSettings;
 use crate::utils::world_generator::generate_chunks;
@@ -14,7 +18,8 @@ use crate::world::physics::PhysicsType;
 use crate::world::worlds_manager::WorldsManager;
 use crate::{LOG_LEVEL, MAX_THREADS};
 use common::blocks::block_info::generate_block_id_map;
-use common::chunks::chunk_data::BlockIndexType;
+use common::chunks::block_position::BlockPosition;
+use common::chunks::chunk_data::{BlockDataInfo, BlockIndexType};
 use common::world_generator::default::WorldGeneratorSettings;
 use godot::classes::display_server::VSyncMode;
 use godot::classes::file_access::ModeFlags;
@@ -39,11 +44,22 @@ const TRACE_FLUSH_EVERY_N_FRAMES: u32 = 10;
 #[class(init, tool, base=Node)]
 pub struct MainScene {
     pub(crate) base: Base<Node>,
-    ip_port: Option<String>,
+    target: Option<ConnectionTarget>,
     login: Option<String>,
 
     network: Option<NetworkContainer>,
 
+    // Recieved server messages which are not handled yet
+    event_queue: NetworkEventQueue,
+
+    // Writes recieved server messages when recording is enabled
+    recorder: Option<NetworkRecorder>,
+
+    loading: LoadingProgress,
+
+    // Connection is lost and the client is trying to restore it
+    reconnect: Option<ReconnectState>,
+
     resource_manager: ResourceManagerType,
 
     #[export]
@@ -85,14 +101,14 @@ pub struct MainScene {
 }
 
 impl MainScene {
-    pub fn create(ip_port: String, login: String, game_settings: Rc<RefCell<GameSettings>>) -> Gd<Self> {
+    pub fn create(target: ConnectionTarget, login: String, game_settings: Rc<RefCell<GameSettings>>) -> Gd<Self> {
         rayon::ThreadPoolBuilder::new()
             .num_threads(MAX_THREADS)
             .build_global()
             .unwrap();
 
         let mut scene = load::<PackedScene>(MAIN_SCENE_PATH).instantiate_as::<Self>();
-        scene.bind_mut().ip_port = Some(ip_port);
+        scene.bind_mut().target = Some(target);
         scene.bind_mut().login = Some(login);
         scene.bind_mut().game_settings = Some(game_settings);
 
@@ -111,6 +127,37 @@ impl MainScene {
         self.network.as_ref()
     }
 
+    pub fn get_event_queue_mut(&mut self) -> &mut NetworkEventQueue {
+        &mut self.event_queue
+    }
+
+    pub fn get_network_budget(&self) -> NetworkBudgetSettings {
+        match self.game_settings.as_ref() {
+            Some(s) => s.borrow().network_budget.clone(),
+            None => Default::default(),
+        }
+    }
+
+    pub fn get_recorder_mut(&mut self) -> Option<&mut NetworkRecorder> {
+        self.recorder.as_mut()
+    }
+
+    pub fn stop_recording(&mut self) {
+        if self.recorder.take().is_some() {
+            log::info!(target: "network", "Network recording stopped");
+        }
+    }
+
+    fn start_recording(&mut self) {
+        if self.recorder.is_some() {
+            return;
+        }
+        match NetworkRecorder::create() {
+            Ok(r) => self.recorder = Some(r),
+            Err(e) => log::error!(target: "network", "Network recording error: {}", e),
+        }
+    }
+
     pub fn get_login(&self) -> &String {
         self.login.as_ref().unwrap()
     }
@@ -136,13 +183,14 @@ impl MainScene {
     }
 
     fn connect_to_server(&mut self) {
-        let ip = self.ip_port.as_ref().expect("init_data is not called");
+        let target = self.target.take().expect("init_data is not called");
+        self.loading.restart(LoadingStage::Connecting);
 
         self.text_screen
             .bind_mut()
-            .update_text(format!("Connecting to {}...", ip));
+            .update_text(format!("Connecting to {}...", target.get_description()));
 
-        let network = match NetworkContainer::new(ip.clone()) {
+        let network = match NetworkContainer::connect(target) {
             Ok(c) => c,
             Err(e) => {
                 self.send_disconnect_event(format!("Connection error: {}", e));
@@ -150,38 +198,229 @@ impl MainScene {
             }
         };
         network.spawn_network_thread();
+
+        let record_network = match self.game_settings.as_ref() {
+            Some(s) => s.borrow().record_network,
+            None => false,
+        };
+        if record_network && !network.is_replay() {
+            self.start_recording();
+        }
         self.network = Some(network);
     }
 
+    pub fn set_loading_stage(&mut self, stage: LoadingStage) {
+        self.loading.set_stage(stage);
+        self.update_loading_screen();
+    }
+
+    pub fn set_loading_stage_progress(&mut self, progress: f32) {
+        self.loading.set_stage_progress(progress);
+        self.update_loading_screen();
+    }
+
+    fn update_loading_screen(&mut self) {
+        let progress = match self.loading.get_stage() {
+            LoadingStage::Ready => None,
+            _ => Some(self.loading.get_total_progress()),
+        };
+        self.text_screen.bind_mut().set_progress(progress);
+    }
+
+    /// The world is spawned; wait for chunks around the player
+    pub fn start_world_loading(&mut self) {
+        self.set_loading_stage(LoadingStage::Chunks);
+        let mut text_screen = self.text_screen.bind_mut();
+        text_screen.update_text("Loading world...".to_string());
+        text_screen.toggle(true);
+    }
+
+    fn process_world_loading(&mut self) {
+        let Some((loaded, total)) = self.get_wm().bind().get_spawn_area_progress() else {
+            return;
+        };
+        if total > 0 {
+            self.set_loading_stage_progress(loaded as f32 / total as f32);
+        }
+
+        let timeout = self.loading.get_stage_elapsed() > SPAWN_AREA_TIMEOUT;
+        if loaded < total && !timeout {
+            return;
+        }
+        if timeout {
+            log::warn!(target: "main", "Spawn area is not loaded in {:?} &7(chunks {}/{})", SPAWN_AREA_TIMEOUT, loaded, total);
+        }
+
+        self.set_loading_stage(LoadingStage::Ready);
+        self.text_screen.bind_mut().toggle(false);
+        self.get_worlds_manager_mut().unlock_spawn();
+    }
+
+    /// Starts reconnect if the session can be restored
+    /// otherwise returns to the main menu
+    fn on_connection_lost(&mut self, error: String) {
+        let settings = match self.game_settings.as_ref() {
+            Some(s) => s.borrow().reconnect.clone(),
+            None => Default::default(),
+        };
+        let has_world = self.get_wm().bind().get_world().is_some();
+
+        let Some(network) = self.network.take() else {
+            return;
+        };
+        network.disconnect();
+
+        // Messages of the lost session will be sent again
+        self.event_queue.clear();
+
+        if !settings.enabled || !has_world || !network.can_reconnect() {
+            self.send_disconnect_event(format!("Network error: {}", error));
+            return;
+        }
+
+        log::warn!(target: "network", "Connection lost: &e{}&r; trying to reconnect...", error);
+        self.reconnect = Some(ReconnectState::create(network, settings, error));
+        self.text_screen.bind_mut().toggle(true);
+    }
+
+    fn process_reconnect(&mut self) {
+        let Some(reconnect) = self.reconnect.as_mut() else {
+            return;
+        };
+
+        if !reconnect.is_connecting() {
+            if !reconnect.is_ready() {
+                let text = format!(
+                    "Connection lost: {}\nReconnecting in {:.0?} (attempt {}/{})...",
+                    reconnect.get_reason(),
+                    reconnect.get_time_left(),
+                    reconnect.get_attempt(),
+                    reconnect.get_max_attempts(),
+                );
+                self.text_screen.bind_mut().update_text(text);
+                return;
+            }
+            reconnect.start_attempt();
+        }
+
+        let Some(result) = reconnect.poll_attempt() else {
+            let text = format!(
+                "Connection lost: {}\nReconnecting (attempt {}/{})...",
+                reconnect.get_reason(),
+                reconnect.get_attempt(),
+                reconnect.get_max_attempts(),
+            );
+            self.text_screen.bind_mut().update_text(text);
+            return;
+        };
+        match result {
+            Ok(network) => {
+                log::info!(target: "network", "&aReconnected to the server &7(attempt {})", reconnect.get_attempt());
+                network.spawn_network_thread();
+                self.network = Some(network);
+                self.reconnect = None;
+                self.loading.restart(LoadingStage::Connecting);
+                self.text_screen
+                    .bind_mut()
+                    .update_text("Restoring session...".to_string());
+                self.update_loading_screen();
+            }
+            Err(e) => {
+                log::warn!(target: "network", "Reconnect attempt {} failed: {}", reconnect.get_attempt(), e);
+                if !reconnect.schedule_next() {
+                    let reason = reconnect.get_reason().clone();
+                    self.reconnect = None;
+                    self.send_disconnect_event(format!("Network error: {}\nReconnect failed: {}", reason, e));
+                }
+            }
+        }
+    }
+
+    /// Applies the edit locally and sends it to the server
+    ///
+    /// Server answers with EditBlock which confirms or corrects the prediction
+    fn send_edit_block(
+        &mut self,
+        network: &NetworkContainer,
+        position: BlockPosition,
+        new_block_info: Option<BlockDataInfo>,
+    ) {
+        let world_slug = {
+            let wm = self.get_wm().bind();
+            let Some(world) = wm.get_world() else {
+                return;
+            };
+            let mut world = world.clone();
+
+            let block_storage = wm.get_block_storage();
+            let resource_manager = self.get_resource_manager();
+            let resources_storage = resource_manager.get_resources_storage();
+            let predicted = world.bind_mut().predict_edit_block(
+                position.clone(),
+                &block_storage,
+                new_block_info.clone(),
+                &*resources_storage,
+            );
+            if !predicted {
+                log::warn!(target: "main", "Block edit at {:?} is skipped: the block can't be edited", position);
+                return;
+            }
+            let slug = world.bind().get_slug().clone();
+            slug
+        };
+
+        let msg = ClientMessages::EditBlockRequest {
+            world_slug,
+            position,
+            new_block_info,
+        };
+        // Edits must not be lost, the prediction is rolled back if there is no answer
+        network.send_message(NetworkMessageType::ReliableOrdered, &msg);
+    }
+
+    /// Leaves the server on purpose, so the session is not restored
+    pub fn disconnect(&mut self, message: String) {
+        let Some(network) = self.network.take() else {
+            return;
+        };
+        network.disconnect();
+        self.reconnect = None;
+        self.event_queue.clear();
+        self.send_disconnect_event(message);
+    }
+
     pub fn send_disconnect_event(&mut self, message: String) {
         Input::singleton().set_mouse_mode(MouseMode::VISIBLE);
         self.signals().network_disconnect().emit(&message.to_godot());
     }
 
     /// Signaling that everything is loaded from the server
-    pub fn on_server_connected(&mut self) {
+    /// Block meshes are built again only if the blocks are changed
+    pub fn on_server_connected(&mut self, blocks_changed: bool) {
         self.debug_info.bind_mut().toggle(true);
-        self.get_worlds_manager_mut().on_network_connected();
+        if blocks_changed {
+            self.get_worlds_manager_mut().on_network_connected();
+        }
     }
 
     /// Player can teleport in new world, between worlds or in exsting world
-    /// so worlds can be created and destroyed
+    /// so worlds can be created, paused and resumed
     pub fn spawn_world(&mut self, world_slug: String) {
         let mut wm = self.worlds_manager.as_mut().unwrap().clone();
         let mut worlds_manager = wm.bind_mut();
 
-        let world = if let Some(world) = worlds_manager.get_world() {
-            if world.bind().get_slug() != &world_slug {
-                log::debug!("Destroying old world... (Player moving to another world; old one must be destroyed)");
-                worlds_manager.destroy_world();
-                worlds_manager.create_world(world_slug)
-            } else {
-                // The same world
-                return;
-            }
-        } else {
-            // New world
-            worlds_manager.create_world(world_slug)
+        let is_same_world = match worlds_manager.get_world() {
+            Some(world) => world.bind().get_slug() == &world_slug,
+            None => false,
+        };
+        if is_same_world {
+            return;
+        }
+
+        // Player moving to another world; the old one is paused and kept warm
+        let world = match worlds_manager.resume_world(&world_slug) {
+            Some(world) => world,
+            None => worlds_manager.create_world(world_slug),
         };
 
         let mut player_controller = worlds_manager.create_player(&world);
@@ -211,7 +450,9 @@ impl MainScene {
 
     #[func]
     fn handler_player_move(&mut self, movement: Gd<EntityMovement>, _new_chunk: bool) {
-        let network = self.get_network().unwrap();
+        let Some(network) = self.get_network() else {
+            return;
+        };
         network.send_message(NetworkMessageType::Unreliable, &movement.bind().into_network());
     }
 
@@ -234,9 +475,7 @@ impl MainScene {
 
         if *command.get_name() == "disconnect" {
             log::info!(target: "main", "&cDisconnecting from the server...");
-            if let Some(n) = self.network.as_ref() {
-                n.disconnect();
-            }
+            self.disconnect("Disconnected from the server".to_string());
             return;
         }
 
@@ -300,6 +539,61 @@ impl MainScene {
                     log::info!(target: "main", "&aSetting vsync changed to &2{}", settings.max_fps);
                     return;
                 }
+                "record" => {
+                    let value = match command.get_arg::<bool, _>("value") {
+                        Ok(c) => c,
+                        Err(e) => {
+                            log::error!(target: "main", "&cSetting value error: {}", e);
+                            return;
+                        }
+                    };
+                    settings.record_network = value;
+                    settings.save().unwrap();
+                    drop(settings);
+
+                    // Recording must contain the handshake and the world messages, so it starts only with the connection
+                    let is_connected = self.network.is_some();
+                    if !value {
+                        self.stop_recording();
+                    }
+                    log::info!(target: "main", "&aSetting network recording changed to &2{}", value);
+                    if value && is_connected && self.recorder.is_none() {
+                        log::info!(target: "main", "Network recording will start with the next connection");
+                    }
+                    return;
+                }
+                "ao" => {
+                    let value = match command.get_arg::<bool, _>("value") {
+                        Ok(c) => c,
+                        Err(e) => {
+                            log::error!(target: "main", "&cSetting value error: {}", e);
+                            return;
+                        }
+                    };
+                    settings.chunk_mesh.ambient_occlusion = value;
+                    settings.save().unwrap();
+                    if let Some(worlds_manager) = self.worlds_manager.as_mut() {
+                        worlds_manager.bind_mut().set_mesh_settings(settings.chunk_mesh.clone());
+                    }
+                    log::info!(target: "main", "&aSetting ambient occlusion changed to &2{}", value);
+                    return;
+                }
+                "lod" => {
+                    let value = match command.get_arg::<u16, _>("value") {
+                        Ok(c) => c,
+                        Err(e) => {
+                            log::error!(target: "main", "&cSetting value error: {}", e);
+                            return;
+                        }
+                    };
+                    settings.chunk_mesh.lod_distance = value;
+                    settings.save().unwrap();
+                    if let Some(worlds_manager) = self.worlds_manager.as_mut() {
+                        worlds_manager.bind_mut().set_mesh_settings(settings.chunk_mesh.clone());
+                    }
+                    log::info!(target: "main", "&aSetting level of detail distance changed to &2{}", value);
+                    return;
+                }
                 _ => {
                     log::error!(target: "main", "&cSetting type \"{}\" not found", setting_type.as_str());
                     return;
@@ -311,7 +605,10 @@ impl MainScene {
 
     #[func]
     fn on_network_command_sended(&mut self, command: GString) {
-        let network = self.get_network().unwrap();
+        let Some(network) = self.get_network() else {
+            log::error!(target: "main", "&cThere is no connection to the server");
+            return;
+        };
         let message = ClientMessages::ConsoleInput {
             command: command.to_string(),
         };
@@ -357,39 +654,26 @@ impl MainScene {
     #[func]
     fn handler_player_action(&mut self, action: Gd<PlayerAction>, item: Gd<SelectedItemGd>) {
         let a = action.bind();
-        let network = self.get_network().unwrap();
+        let Some(network) = self.get_network().cloned() else {
+            return;
+        };
         if let Some(look_at) = a.get_hit() {
-            let world_slug = {
-                let worlds_manager = self.worlds_manager.as_ref().unwrap();
-                let wm = worlds_manager.bind();
-                let world = wm.get_world().unwrap();
-                let world = world.bind();
-                world.get_slug().clone()
-            };
-
             match look_at.bind().get_physics_type() {
                 PhysicsType::ChunkMeshCollider(_chunk_position) => {
                     let selected_block = look_at.bind().get_cast_result().get_selected_block();
-                    if a.is_main_type() {
-                        if let Some(i) = item.bind().get_selected_item() {
-                            match i {
-                                SelectedItem::BlockPlacing(block_info) => {
-                                    let msg = ClientMessages::EditBlockRequest {
-                                        world_slug: world_slug.clone(),
-                                        position: look_at.bind().get_cast_result().get_place_block(),
-                                        new_block_info: Some(block_info.clone()),
-                                    };
-                                    network.send_message(NetworkMessageType::Unreliable, &msg);
-                                }
-                            }
+                    let edit = if a.is_main_type() {
+                        match item.bind().get_selected_item() {
+                            Some(SelectedItem::BlockPlacing(block_info)) => Some((
+                                look_at.bind().get_cast_result().get_place_block(),
+                                Some(block_info.clone()),
+                            )),
+                            None => None,
                         }
                     } else {
-                        let msg = ClientMessages::EditBlockRequest {
-                            world_slug: world_slug.clone(),
-                            position: selected_block,
-                            new_block_info: None,
-                        };
-                        network.send_message(NetworkMessageType::Unreliable, &msg);
+                        Some((selected_block, None))
+                    };
+                    if let Some((position, new_block_info)) = edit {
+                        self.send_edit_block(&network, position, new_block_info);
                     }
                 }
                 PhysicsType::EntityCollider(_entity_id) => {}
@@ -416,7 +700,7 @@ impl INode for MainScene {
                 let mut wm = wm.bind_mut();
 
                 wm.resource_manager = Some(self.resource_manager.clone());
-                
+
                 let mut block_storage = wm.get_block_storage_mut();
                 let mut block_id_map: BTreeMap<BlockIndexType, String> = Default::default();
                 let _ = generate_block_id_map(&mut block_id_map, block_storage.iter_values());
@@ -469,6 +753,9 @@ impl INode for MainScene {
                 environment.set_ssao_enabled(settings.ssao);
             }
             Engine::singleton().set_max_fps(settings.max_fps as i32);
+            if let Some(worlds_manager) = self.worlds_manager.as_mut() {
+                worlds_manager.bind_mut().set_mesh_settings(settings.chunk_mesh.clone());
+            }
             if settings.vsync {
                 DisplayServer::singleton().window_set_vsync_mode(VSyncMode::ENABLED);
             } else {
@@ -483,6 +770,16 @@ impl INode for MainScene {
 
         let _span = crate::span!("main_scene.process");
 
+        if self.reconnect.is_some() {
+            let _span = crate::span!("main_scene.process::process_reconnect");
+            self.process_reconnect();
+        }
+
+        if self.loading.get_stage() == LoadingStage::Chunks {
+            let _span = crate::span!("main_scene.process::process_world_loading");
+            self.process_world_loading();
+        }
+
         if self.network.is_some() {
             let network_info = {
                 let _span = crate::span!("main_scene.process::handle_network_events");
@@ -491,7 +788,7 @@ impl INode for MainScene {
                     Ok(i) => i,
                     Err(e) => {
                         log::error!(target: "main", "Network error: {}", e);
-                        self.send_disconnect_event(format!("Network error: {}", e));
+                        self.on_connection_lost(e);
                         return;
                     }
                 }
@@ -499,7 +796,14 @@ impl INode for MainScene {
 
             {
                 let _span = crate::span!("main_scene.process::set_network_info");
-                self.debug_info.bind_mut().set_network_info(network_info);
+                let queue_stats = self.network.as_ref().and_then(|n| n.get_queue_stats());
+                let pending_messages = self.event_queue.len();
+                let mut debug_info = self.debug_info.bind_mut();
+                if let Some(network_info) = network_info {
+                    debug_info.set_network_info(network_info);
+                }
+                debug_info.set_queue_stats(queue_stats);
+                debug_info.set_pending_messages(pending_messages);
             }
         }
 
@@ -530,9 +834,7 @@ impl INode for MainScene {
     fn exit_tree(&mut self) {
         {
             let mut worlds_manager = self.get_worlds_manager_mut();
-            if worlds_manager.get_world().is_some() {
-                worlds_manager.destroy_world()
-            }
+            worlds_manager.destroy_all_worlds();
         }
         if let Some(n) = self.network.as_ref() {
             n.disconnect();
@@ -1,5 +1,6 @@
 pub mod connect_scene;
 pub mod main_menu;
+pub mod loading_progress;
 pub mod main_scene;
 pub mod text_screen;
 pub mod main_menu_button;
@@ -1,6 +1,6 @@
 use common::utils::colors::parse_to_console_godot;
 use godot::{
-    classes::{Button, IMarginContainer, MarginContainer, RichTextLabel},
+    classes::{Button, IMarginContainer, MarginContainer, ProgressBar, RichTextLabel},
     prelude::*,
 };
 
@@ -14,6 +14,10 @@ pub struct TextScreen {
 
     #[export]
     close_button: Option<Gd<Button>>,
+
+    /// Created under the text if not set
+    #[export]
+    progress_bar: Option<Gd<ProgressBar>>,
 }
 
 impl TextScreen {
@@ -22,6 +26,18 @@ impl TextScreen {
         self.text.as_mut().unwrap().set_text(&msg);
     }
 
+    /// Progress from 0 to 1; None hides the progress bar
+    pub fn set_progress(&mut self, progress: Option<f32>) {
+        let progress_bar = self.progress_bar.as_mut().unwrap();
+        match progress {
+            Some(p) => {
+                progress_bar.set_value(p as f64 * 100.0);
+                progress_bar.set_visible(true);
+            }
+            None => progress_bar.set_visible(false),
+        }
+    }
+
     pub fn toggle(&mut self, state: bool) {
         self.base_mut().set_visible(state);
     }
@@ -58,5 +74,13 @@ impl IMarginContainer for TextScreen {
             &Callable::from_object_method(&self.base().to_godot(), "close_button_pressed"),
         );
         self.toggle_close_button(None);
+
+        if self.progress_bar.is_none() {
+            let progress_bar = ProgressBar::new_alloc();
+            let mut parent = self.text.as_ref().unwrap().get_parent().unwrap();
+            parent.add_child(&progress_bar);
+            self.progress_bar = Some(progress_bar);
+        }
+        self.set_progress(None);
     }
 }
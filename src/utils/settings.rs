@@ -1,3 +1,6 @@
+use crate::network::event_queue::NetworkBudgetSettings;
+use crate::network::reconnect::ReconnectSettings;
+use crate::world::chunks::mesh::mesh_settings::ChunkMeshSettings;
 use serde::{Deserialize, Serialize};
 use std::{
     fs::{create_dir_all, File},
@@ -17,6 +20,18 @@ pub struct GameSettings {
 
     #[serde(default)]
     pub vsync: bool,
+
+    #[serde(default)]
+    pub reconnect: ReconnectSettings,
+
+    #[serde(default)]
+    pub record_network: bool,
+
+    #[serde(default)]
+    pub network_budget: NetworkBudgetSettings,
+
+    #[serde(default)]
+    pub chunk_mesh: ChunkMeshSettings,
 }
 
 impl GameSettings {
@@ -1 +1,2 @@
 pub mod texture_mapper;
+pub mod terrain_material;
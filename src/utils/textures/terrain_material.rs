@@ -0,0 +1,85 @@
+use godot::{
+    classes::{Shader, ShaderMaterial},
+    obj::{Gd, NewGd},
+    prelude::ToGodot,
+};
+
+use crate::client_scripts::texture_image::TerrainTextures;
+
+/// Greedy meshing merges faces into quads larger than one block;
+/// UV contains block coordinates inside the quad and UV2.x the layer,
+/// so the layer rect inside its page of the texture array is repeated per block.
+/// Animated textures switch the layer by the time, so the sections are not remeshed.
+const TERRAIN_SHADER: &str = "
+shader_type spatial;
+render_mode {render_mode};
+
+uniform float sky_light_energy : hint_range(0.0, 1.0) = 1.0;
+uniform float min_light = 0.04;
+uniform sampler2DArray texture_albedo : source_color, filter_nearest_mipmap, repeat_disable;
+uniform sampler2D texture_layers : filter_nearest, repeat_disable;
+
+// Light level 0..15 is stored as level / 15
+float light_curve(float level) {
+	return mix(min_light, 1.0, pow(level, 1.6));
+}
+
+void fragment() {
+	// First row R: frames count, G: frame time of the texture starting at the layer, B: page of the layer
+	int layer = int(UV2.x + 0.5);
+	vec2 animation = texelFetch(texture_layers, ivec2(layer, 0), 0).rg;
+	layer += int(mod(floor(TIME / max(animation.g, 0.001)), max(animation.r, 1.0)));
+	float page = texelFetch(texture_layers, ivec2(layer, 0), 0).b;
+
+	// Second row: rect of the layer inside the page
+	vec4 rect = texelFetch(texture_layers, ivec2(layer, 1), 0);
+	vec2 uv = rect.xy + fract(UV) * rect.zw;
+	vec4 albedo = textureGrad(texture_albedo, vec3(uv, page), dFdx(UV) * rect.zw, dFdy(UV) * rect.zw);
+
+	// COLOR.r is the ambient occlusion, COLOR.g the sky light and COLOR.b the block light
+	float light = max(light_curve(COLOR.g) * sky_light_energy, light_curve(COLOR.b));
+	ALBEDO = albedo.rgb * COLOR.r * light;
+	{alpha}
+}
+";
+
+fn create_shader(render_mode: &str, alpha: &str) -> Gd<Shader> {
+    let code = TERRAIN_SHADER
+        .replace("{render_mode}", render_mode)
+        .replace("{alpha}", alpha);
+
+    let mut shader = Shader::new_gd();
+    shader.set_code(&code);
+    shader
+}
+
+fn create_material(shader: &Gd<Shader>) -> Gd<ShaderMaterial> {
+    let mut material = ShaderMaterial::new_gd();
+    material.set_shader(shader);
+    material
+}
+
+pub fn create_terrain_material(transparent: bool) -> Gd<ShaderMaterial> {
+    let shader = match transparent {
+        true => create_shader("blend_mix, depth_draw_opaque, cull_back", "ALPHA = albedo.a;"),
+        false => create_shader(
+            "depth_draw_opaque, cull_back",
+            "ALPHA = albedo.a;\n\tALPHA_SCISSOR_THRESHOLD = 0.5;",
+        ),
+    };
+    create_material(&shader)
+}
+
+/// Liquid surfaces are seen from both sides, e.g. from under the water
+pub fn create_liquid_material() -> Gd<ShaderMaterial> {
+    let shader = create_shader(
+        "blend_mix, depth_draw_opaque, cull_disabled",
+        "ALPHA = albedo.a;\n\tROUGHNESS = 0.1;\n\tSPECULAR = 0.6;",
+    );
+    create_material(&shader)
+}
+
+pub fn set_terrain_texture(material: &mut Gd<ShaderMaterial>, textures: &TerrainTextures) {
+    material.set_shader_parameter("texture_albedo", &textures.albedo.to_variant());
+    material.set_shader_parameter("texture_layers", &textures.layers.to_variant());
+}
@@ -1,170 +1,256 @@
-use common::blocks::block_type::{BlockColor, BlockContent, BlockType};
-use godot::{
-    builtin::PackedByteArray,
-    classes::{Image, ImageTexture},
-    obj::{Gd, NewGd},
-};
+use ahash::AHashMap;
+use common::blocks::block_type::{BlockContent, BlockType};
+use image::GenericImageView;
 
 use crate::{
-    client_scripts::{resource_manager::ResourceStorage, texture_image::TexturePack},
-    world::block_storage::BlockStorage,
+    client_scripts::{
+        resource_manager::ResourceStorage,
+        texture_image::{TerrainTextures, TextureImage, TexturePack},
+    },
+    world::{block_render::TextureAnimation, block_storage::BlockStorage},
 };
 
-#[derive(Debug, Default)]
-pub struct TextureMapper {
-    textures_map: Vec<String>,
+/// Connected texture has a layer for every combination of the hidden borders
+const CONNECTED_TEXTURE_LAYERS: usize = 16;
+
+/// Frames count and bits of the frame time
+type AnimationKey = (Option<u32>, u32);
+
+fn get_animation_key(animation: &TextureAnimation) -> AnimationKey {
+    (animation.frames, animation.frame_time.to_bits())
 }
 
-impl TextureMapper {
-    pub fn build(
-        &mut self,
-        block_storage: &BlockStorage,
-        resource_storage: &ResourceStorage,
-    ) -> Result<Gd<ImageTexture>, String> {
-        let mut pba = PackedByteArray::new();
+/// One layer of the texture array: the texture with the optional overlay tinted by the color
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TextureKey {
+    texture: String,
+    overlay: Option<String>,
+    color: Option<[u8; 3]>,
+    connected: bool,
 
-        let m = match self.generate_texture(block_storage, resource_storage) {
-            Ok(m) => m,
-            Err(e) => return Err(e),
-        };
-        pba.extend(m);
+    /// The same image is split into different frames by every animation
+    animation: Option<AnimationKey>,
+}
 
-        let mut image = Image::new_gd();
-        image.load_png_from_buffer(&pba);
-        let mut image_texture = ImageTexture::new_gd();
-        image_texture.set_image(&image);
-        Ok(image_texture)
-    }
+/// Which of the block textures is used for the face
+#[derive(Clone, Copy, Default)]
+pub struct TextureChoice<'a> {
+    /// Index in the colors scheme of the block type
+    pub color_variant: usize,
 
-    pub fn clear(&mut self) {
-        self.textures_map.clear();
+    /// Texture used instead of the main one
+    pub variant: Option<&'a String>,
+
+    pub connected: bool,
+}
+
+/// Which texture the side of the block uses
+///
+/// Top is tinted by the color, sides only through the overlay, the bottom is never tinted
+fn get_texture_key(
+    content: &BlockContent,
+    side_index: i8,
+    choice: &TextureChoice,
+    animation: Option<AnimationKey>,
+) -> Option<TextureKey> {
+    let BlockContent::Texture {
+        texture,
+        side_texture,
+        side_overlay,
+        bottom_texture,
+        colors_scheme,
+    } = content
+    else {
+        return None;
+    };
+    let texture = choice.variant.unwrap_or(texture);
+    let color = colors_scheme
+        .as_ref()
+        .and_then(|colors| colors.get(choice.color_variant).or(colors.first()))
+        .map(|c| [c[0], c[1], c[2]]);
+    let connected = choice.connected;
+    let key = match side_index {
+        // Topside
+        4 => TextureKey {
+            texture: texture.clone(),
+            overlay: None,
+            color,
+            connected,
+            animation,
+        },
+        // Bottom
+        1 => TextureKey {
+            texture: bottom_texture.as_ref().unwrap_or(texture).clone(),
+            overlay: None,
+            color: match bottom_texture {
+                Some(_) => None,
+                None => color,
+            },
+            connected,
+            animation,
+        },
+        // Sides
+        _ => match side_texture {
+            Some(side_texture) => TextureKey {
+                texture: side_texture.clone(),
+                overlay: side_overlay.clone(),
+                color: side_overlay.as_ref().and(color),
+                connected,
+                animation,
+            },
+            None => TextureKey {
+                texture: texture.clone(),
+                overlay: None,
+                color,
+                connected,
+                animation,
+            },
+        },
+    };
+    Some(key)
+}
+
+/// Tints the texture or only its overlay
+fn compose_texture(mut texture_image: TextureImage, overlay_image: Option<&TextureImage>, color: Option<[u8; 3]>) -> TextureImage {
+    match overlay_image {
+        Some(overlay_image) => match color {
+            Some(color) => texture_image.overlay_on_top(&overlay_image.change_color_balance(color)),
+            None => texture_image.overlay_on_top(overlay_image),
+        },
+        None => match color {
+            Some(color) => texture_image.change_color_balance(color),
+            None => texture_image,
+        },
     }
+}
+
+#[derive(Debug, Default)]
+pub struct TextureMapper {
+    textures_map: AHashMap<TextureKey, usize>,
 
-    fn generate_texture(
+    /// Animation of the block textures by the block slug
+    animations: AHashMap<String, AnimationKey>,
+}
+
+impl TextureMapper {
+    pub fn build(
         &mut self,
         block_storage: &BlockStorage,
         resource_storage: &ResourceStorage,
-    ) -> Result<Vec<u8>, String> {
+    ) -> Result<TerrainTextures, String> {
         let mut texture_pack = TexturePack::create();
 
         for block_type in block_storage.iter_values() {
-            match block_type.get_block_content() {
-                BlockContent::Texture {
-                    texture,
-                    side_texture,
-                    side_overlay,
-                    bottom_texture,
-                    colors_scheme,
-                } => {
-                    // Top texture
-                    let mut texture_image = resource_storage.generate_image(texture).unwrap();
-                    match colors_scheme {
-                        Some(colors) => {
-                            for color in colors {
-                                let index = self.add_texture_index(texture.clone(), Some(&color));
-                                texture_image = texture_image.change_color_balance(color);
-                                texture_pack.add_subimage(&texture_image, index);
-                            }
-                        }
-                        None => {
-                            let index = self.add_texture_index(texture.clone(), None);
-                            texture_pack.add_subimage(&texture_image, index);
-                        }
-                    }
+            let BlockContent::Texture { colors_scheme, .. } = block_type.get_block_content() else {
+                continue;
+            };
+            let settings = block_storage.get_render_settings(block_type.get_slug());
+            let animation = settings.and_then(|s| s.animation.as_ref());
+            let animation_key = animation.map(get_animation_key);
+            if let Some(animation_key) = animation_key {
+                self.animations.insert(block_type.get_slug().to_string(), animation_key);
+            }
+            let colors_count = colors_scheme.as_ref().map_or(1, |colors| colors.len().max(1));
+            let mut variants: Vec<Option<&String>> = vec![None];
+            if let Some(settings) = settings {
+                variants.extend(settings.texture_variants.iter().map(Some));
+            }
+            let connected = settings.is_some_and(|s| s.has_connected_texture());
 
-                    // Side texture
-                    if let Some(texture) = side_texture.as_ref() {
-                        let mut side_texture_image =
-                            resource_storage.generate_image(texture).unwrap();
-                        match colors_scheme {
-                            Some(colors) => {
-                                for color in colors {
-                                    let index =
-                                        self.add_texture_index(texture.clone(), Some(&color));
-
-                                    if let Some(side_overlay) = side_overlay {
-                                        let mut overlay_texture_image =
-                                            resource_storage.generate_image(side_overlay).unwrap();
-                                        overlay_texture_image =
-                                            overlay_texture_image.change_color_balance(color);
-
-                                        let new_side_texture_image = side_texture_image
-                                            .overlay_on_top(&overlay_texture_image);
-                                        texture_pack.add_subimage(&new_side_texture_image, index);
-                                    } else {
-                                        texture_pack.add_subimage(&side_texture_image, index);
-                                    }
-                                }
-                            }
-                            None => {
-                                let index = self.add_texture_index(texture.clone(), None);
-                                if let Some(side_overlay) = side_overlay {
-                                    let overlay_texture_image =
-                                        resource_storage.generate_image(side_overlay).unwrap();
-
-                                    let new_side_texture_image =
-                                        side_texture_image.overlay_on_top(&overlay_texture_image);
-                                    texture_pack.add_subimage(&new_side_texture_image, index);
-                                } else {
-                                    texture_pack.add_subimage(&side_texture_image, index);
-                                }
-                            }
+            for color_variant in 0..colors_count {
+                for variant in variants.iter() {
+                    let choice = TextureChoice {
+                        color_variant,
+                        variant: *variant,
+                        connected,
+                    };
+                    // Top, bottom and sides
+                    for side_index in [4, 1, 0] {
+                        let Some(key) =
+                            get_texture_key(block_type.get_block_content(), side_index, &choice, animation_key)
+                        else {
+                            continue;
+                        };
+                        if let Err(e) = self.add_texture(key, animation, &mut texture_pack, resource_storage) {
+                            return Err(format!("&cblock &4\"{}\" &ctexture error: {}", block_type.get_slug(), e));
                         }
                     }
-
-                    // Bottom texture
-                    if let Some(texture) = bottom_texture.as_ref() {
-                        let index = self.add_texture_index(texture.clone(), None);
-                        let texture_image = resource_storage.generate_image(texture).unwrap();
-                        texture_pack.add_subimage(&texture_image, index);
-                    }
                 }
-                _ => continue,
             }
         }
 
-        return Ok(texture_pack.generate());
+        texture_pack.generate()
     }
 
-    pub fn add_texture_index(&mut self, texture_name: String, _color: Option<&BlockColor>) -> i64 {
-        // TODO: color
-
-        assert!(
-            !self.textures_map.contains(&texture_name),
-            "texture already exists"
-        );
-        self.textures_map.push(texture_name);
-        self.textures_map.len() as i64 - 1_i64
+    pub fn clear(&mut self) {
+        self.textures_map.clear();
+        self.animations.clear();
     }
 
-    #[allow(unused_variables)]
-    pub fn get_uv_offset(&self, block_type: &BlockType, side_index: i8) -> Option<usize> {
-        let texture = match block_type.get_block_content() {
-            BlockContent::Texture {
-                texture,
-                side_texture,
-                bottom_texture,
-                ..
-            } => {
-                match side_index {
-                    // Topside
-                    4 => texture,
-                    // Bottom
-                    1 => match bottom_texture {
-                        Some(t) => t,
-                        None => texture,
-                    },
-                    // Sides
-                    _ => match side_texture {
-                        Some(t) => t,
-                        None => texture,
-                    },
+    /// Textures shared by the blocks are added once
+    fn add_texture(
+        &mut self,
+        key: TextureKey,
+        animation: Option<&TextureAnimation>,
+        texture_pack: &mut TexturePack,
+        resource_storage: &ResourceStorage,
+    ) -> Result<(), String> {
+        if self.textures_map.contains_key(&key) {
+            return Ok(());
+        }
+
+        let texture_image = resource_storage.generate_image(&key.texture)?;
+        let overlay_image = match key.overlay.as_ref() {
+            Some(overlay) => Some(resource_storage.generate_image(overlay)?),
+            None => None,
+        };
+
+        let layer = match animation {
+            Some(animation) => {
+                let (width, height) = texture_image.get_source().dimensions();
+                let frames = animation.frames.unwrap_or(height / width.max(1));
+                let frames: Vec<TextureImage> = texture_image
+                    .split_frames(frames)
+                    .into_iter()
+                    .map(|frame| compose_texture(frame, overlay_image.as_ref(), key.color))
+                    .collect();
+                texture_pack.add_animation(&frames, animation.frame_time)
+            }
+            None => {
+                let (width, height) = texture_image.get_source().dimensions();
+                if width != height {
+                    log::warn!(
+                        target: "resources",
+                        "Texture &e\"{}\"&r is not square ({}x{}); it is stretched on the block face",
+                        key.texture,
+                        width,
+                        height
+                    );
+                }
+                let texture_image = compose_texture(texture_image, overlay_image.as_ref(), key.color);
+                match key.connected {
+                    true => {
+                        let first = texture_pack.len();
+                        for mask in 0..CONNECTED_TEXTURE_LAYERS {
+                            texture_pack.add_subimage(&texture_image.connect_borders(mask as u8));
+                        }
+                        first
+                    }
+                    false => texture_pack.add_subimage(&texture_image),
                 }
             }
-            BlockContent::ModelCube { model, .. } => return None,
         };
+        self.textures_map.insert(key, layer);
+        Ok(())
+    }
 
-        self.textures_map.iter().position(|t| t == texture)
+    /// Layer of the texture array with the side texture
+    ///
+    /// Connected textures return the first of their layers
+    pub fn get_texture_layer(&self, block_type: &BlockType, side_index: i8, choice: &TextureChoice) -> Option<usize> {
+        let animation = self.animations.get(block_type.get_slug()).copied();
+        let key = get_texture_key(block_type.get_block_content(), side_index, choice, animation)?;
+        self.textures_map.get(&key).cloned()
     }
 
     pub fn len(&self) -> usize {
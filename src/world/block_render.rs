@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Render properties of the block type
+///
+/// The block scheme from the server describes only the content of the block,
+/// so everything about how it's rendered comes with the resource packs by the block slug
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BlockRenderSettings {
+    /// Block light level from 0 to 15
+    #[serde(default)]
+    pub light_emission: u8,
+
+    /// Translucent block is rendered with the liquid material
+    #[serde(default)]
+    pub liquid: bool,
+
+    #[serde(default)]
+    pub shape: BlockShape,
+
+    /// Textures of the block are vertical strips of frames
+    #[serde(default)]
+    pub animation: Option<TextureAnimation>,
+
+    /// Textures used instead of the main texture, picked by the block position
+    #[serde(default)]
+    pub texture_variants: Vec<String>,
+
+    /// Texture borders are hidden between the same blocks, e.g. glass
+    #[serde(default)]
+    pub connected_texture: bool,
+}
+
+impl BlockRenderSettings {
+    /// Animated textures are never connected
+    pub fn has_connected_texture(&self) -> bool {
+        self.connected_texture && self.animation.is_none()
+    }
+
+    /// Texture of the face depends on the block position or neighbours
+    pub fn has_per_block_texture(&self) -> bool {
+        !self.texture_variants.is_empty() || self.has_connected_texture()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TextureAnimation {
+    /// Seconds of one frame
+    pub frame_time: f32,
+
+    /// By default the frames are square
+    #[serde(default)]
+    pub frames: Option<u32>,
+}
+
+/// Geometry of the texture block
+///
+/// Everything except the cube is built by the mesher block by block
+/// and doesn't take part in the greedy meshing
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockShape {
+    #[default]
+    Cube,
+    /// Lower half of the block
+    Slab,
+    /// Turned by the block face like the models
+    Stairs,
+    /// Two crossed planes, e.g. grass and flowers; has no collider
+    Cross,
+    /// Post connected to the neighbour fences and solid blocks
+    Fence,
+    /// Thin plate connected to the neighbour panes and solid blocks
+    Pane,
+}
+
+/// Render settings file: block slug to its settings
+pub type BlockRenderSettingsMap = HashMap<String, BlockRenderSettings>;
+
+/// Media of the resource pack with its block render settings
+pub const BLOCK_RENDER_SETTINGS_MEDIA: &str = "blocks.yml";
+
+/// The file name must match exactly: "myblocks.yml" is not the render settings
+pub fn is_block_render_settings_media(media_slug: &str) -> bool {
+    Path::new(media_slug)
+        .file_name()
+        .is_some_and(|name| name == OsStr::new(BLOCK_RENDER_SETTINGS_MEDIA))
+}
+
+pub fn parse_block_render_settings(yaml: &str) -> Result<BlockRenderSettingsMap, String> {
+    match serde_yaml::from_str::<BlockRenderSettingsMap>(yaml) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(format!("Block render settings yaml error: {}", e)),
+    }
+}
@@ -1,13 +1,21 @@
 use common::blocks::block_type::{BlockContent, BlockType};
-use common::chunks::chunk_data::BlockIndexType;
+use common::blocks::voxel_visibility::VoxelVisibility;
+use common::chunks::chunk_data::{BlockDataInfo, BlockIndexType};
 use common::default_blocks::generate_default_blocks;
 use std::collections::BTreeMap;
 
 use crate::client_scripts::resource_manager::ResourceStorage;
 
+use super::block_render::{BlockRenderSettings, BlockRenderSettingsMap, BlockShape};
+use super::chunks::light::column_light::MAX_LIGHT;
+
 pub struct BlockStorage {
     blocks: BTreeMap<String, BlockType>,
     block_id_map: BTreeMap<BlockIndexType, String>,
+    render_settings: BlockRenderSettingsMap,
+
+    // Serialized block types of the last loaded server scheme
+    scheme: Option<String>,
 }
 
 impl Default for BlockStorage {
@@ -15,6 +23,8 @@ impl Default for BlockStorage {
         let mut block_storage = Self {
             blocks: Default::default(),
             block_id_map: Default::default(),
+            render_settings: Default::default(),
+            scheme: None,
         };
 
         let default_blocks = match generate_default_blocks() {
@@ -69,6 +79,88 @@ impl BlockStorage {
         }
         categories
     }
+    /// Variant textures of the described blocks must be in the resources
+    ///
+    /// Local packs are shared across servers, so blocks the server doesn't define are skipped
+    fn set_render_settings(
+        &mut self,
+        mut render_settings: BlockRenderSettingsMap,
+        resources_storage: &ResourceStorage,
+    ) -> Result<(), String> {
+        render_settings.retain(|slug, _settings| {
+            let is_known = self.blocks.contains_key(slug);
+            if !is_known {
+                log::warn!(target: "resources", "Render settings of unknown block &e\"{}\"&r are skipped", slug);
+            }
+            is_known
+        });
+        for (slug, settings) in render_settings.iter() {
+            for texture in settings.texture_variants.iter() {
+                if let Err(e) = resources_storage.has_media(texture) {
+                    return Err(format!(
+                        "&cblock &4\"{}\" &ctexture variant not found: &4\"{}\" &7({})",
+                        slug, texture, e,
+                    ));
+                }
+            }
+        }
+        self.render_settings = render_settings;
+        Ok(())
+    }
+
+    pub fn get_render_settings(&self, slug: &String) -> Option<&BlockRenderSettings> {
+        self.render_settings.get(slug)
+    }
+
+    pub fn get_block_render_settings(&self, block_info: &BlockDataInfo) -> Option<&BlockRenderSettings> {
+        let slug = self.block_id_map.get(&block_info.get_id())?;
+        self.render_settings.get(slug)
+    }
+
+    pub fn is_liquid(&self, block_info: &BlockDataInfo) -> bool {
+        self.get_block_render_settings(block_info).is_some_and(|s| s.liquid)
+    }
+
+    pub fn get_light_emission(&self, block_info: &BlockDataInfo) -> u8 {
+        match self.get_block_render_settings(block_info) {
+            Some(s) => s.light_emission.min(MAX_LIGHT),
+            None => 0,
+        }
+    }
+
+    pub fn get_shape(&self, block_info: &BlockDataInfo) -> BlockShape {
+        match self.get_block_render_settings(block_info) {
+            Some(s) => s.shape,
+            None => BlockShape::Cube,
+        }
+    }
+
+    /// Texture of the face depends on the block position or neighbours,
+    /// so the faces of these blocks are not merged
+    pub fn has_per_block_texture(&self, block_info: &BlockDataInfo) -> bool {
+        let has_colors = match self.get(&block_info.get_id()).map(|b| b.get_block_content()) {
+            Some(BlockContent::Texture {
+                colors_scheme: Some(colors),
+                ..
+            }) => colors.len() > 1,
+            _ => false,
+        };
+        has_colors
+            || self
+                .get_block_render_settings(block_info)
+                .is_some_and(|s| s.has_per_block_texture())
+    }
+
+    /// Model blocks, shapes and translucent textures let the light through
+    pub fn is_light_opaque(&self, block_info: &BlockDataInfo) -> bool {
+        let Some(block_type) = self.get(&block_info.get_id()) else {
+            return false;
+        };
+        block_type.get_block_content().is_texture()
+            && *block_type.get_voxel_visibility() == VoxelVisibility::Opaque
+            && self.get_shape(block_info) == BlockShape::Cube
+    }
+
     pub fn textures_blocks_count(&self) -> i32 {
         let mut result = 0;
         for b in self.blocks.values() {
@@ -79,13 +171,22 @@ impl BlockStorage {
         result
     }
 
-    /// Saves the server-side block scheme
+    /// Server sends the same scheme again after the reconnect;
+    /// then the blocks and everything built from them are kept
+    pub fn is_same_scheme(&self, block_id_map: &BTreeMap<BlockIndexType, String>, block_types: &[BlockType]) -> bool {
+        self.scheme.is_some()
+            && self.block_id_map == *block_id_map
+            && self.scheme == serde_json::to_string(block_types).ok()
+    }
+
+    /// Saves the server-side block scheme and the render settings of the resource packs
     pub fn load_blocks_types(
         &mut self,
         block_types: Vec<BlockType>,
         resources_storage: &ResourceStorage,
     ) -> Result<(), String> {
         self.blocks.clear();
+        self.scheme = None;
         for block_type in block_types.iter() {
             match block_type.get_block_content() {
                 BlockContent::Texture {
@@ -147,6 +248,10 @@ impl BlockStorage {
             }
             self.blocks.insert(block_type.get_slug().clone(), block_type.clone());
         }
+
+        let render_settings = resources_storage.get_block_render_settings()?;
+        self.set_render_settings(render_settings, resources_storage)?;
+        self.scheme = serde_json::to_string(&block_types).ok();
         return Ok(());
     }
 }
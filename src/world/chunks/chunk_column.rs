@@ -9,15 +9,19 @@ use common::{
 use godot::prelude::*;
 use parking_lot::RwLock;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
     Arc,
 };
 
 use crate::world::worlds_manager::WorldMaterials;
 
 use super::chunk_section::ChunkSection;
+use super::light::column_light::ColumnLightLockType;
+use super::mesh::lod::ChunkLod;
+use super::section_visibility::SectionVisibility;
 
-type SectionsGdType = Vec<Gd<ChunkSection>>;
+/// Sections without geometry and models have no nodes
+type SectionsGdType = Vec<Option<Gd<ChunkSection>>>;
 
 pub type ColumnDataLockType = Arc<RwLock<ChunkData>>;
 
@@ -27,13 +31,17 @@ pub struct ChunkBase {
     pub base: Base<Node3D>,
 
     pub sections: SectionsGdType,
+
+    // Set on spawn; sections are created with them on demand
+    materials: Option<WorldMaterials>,
 }
 
 impl ChunkBase {
     pub fn create(base: Base<Node3D>) -> Self {
         Self {
             base,
-            sections: Default::default(),
+            sections: vec![None; VERTICAL_SECTIONS],
+            materials: None,
         }
     }
 
@@ -41,20 +49,28 @@ impl ChunkBase {
         let name = format!("ChunkColumn {}", chunk_position);
         self.base_mut().set_name(&name);
 
-        for y in 0..VERTICAL_SECTIONS {
-            let mut section = Gd::<ChunkSection>::from_init_fn(|base| {
-                ChunkSection::create(base, materials, y as u8, chunk_position.clone())
-            });
+        self.materials = Some(materials.clone());
+    }
 
-            let name = format!("Section {}", y);
-            section.bind_mut().base_mut().set_name(&name);
+    fn get_or_create_section(&mut self, y: usize, chunk_position: &ChunkPosition) -> Gd<ChunkSection> {
+        if let Some(section) = self.sections[y].as_ref() {
+            return section.clone();
+        }
 
-            self.base_mut().add_child(&section);
-            let pos = section.bind().get_section_local_position();
-            section.bind_mut().base_mut().set_position(pos);
+        let materials = self.materials.as_ref().expect("chunk sections are not spawned");
+        let mut section = Gd::<ChunkSection>::from_init_fn(|base| {
+            ChunkSection::create(base, materials, y as u8, chunk_position.clone())
+        });
 
-            self.sections.push(section);
-        }
+        let name = format!("Section {}", y);
+        section.bind_mut().base_mut().set_name(&name);
+
+        self.base_mut().add_child(&section);
+        let pos = section.bind().get_section_local_position();
+        section.bind_mut().base_mut().set_position(pos);
+
+        self.sections[y] = Some(section.clone());
+        section
     }
 }
 
@@ -66,12 +82,24 @@ pub struct ChunkColumn {
     chunk_position: ChunkPosition,
     data: ColumnDataLockType,
 
+    // Computed before the column is meshed
+    light: ColumnLightLockType,
+
     // Is chunk spawned on base
     loaded: Arc<AtomicBool>,
+
+    // ChunkLod of the meshes; changed as the player moves
+    lod: Arc<AtomicU8>,
+
+    // Kept for all sections, including the ones without nodes
+    visibility: RwLock<Vec<SectionVisibility>>,
+
+    // Changed when the data is replaced; older generated geometry is discarded
+    generation: AtomicU32,
 }
 
 impl ChunkColumn {
-    pub fn create(chunk_position: ChunkPosition, data: ChunkData) -> Self {
+    pub fn create(chunk_position: ChunkPosition, data: ChunkData, lod: ChunkLod) -> Self {
         let chunk_base = Gd::<ChunkBase>::from_init_fn(|base| ChunkBase::create(base));
 
         let chunk_column = Self {
@@ -79,7 +107,11 @@ impl ChunkColumn {
 
             chunk_position,
             data: Arc::new(RwLock::new(data)),
+            light: Arc::new(RwLock::new(None)),
             loaded: Arc::new(AtomicBool::new(false)),
+            lod: Arc::new(AtomicU8::new(lod as u8)),
+            visibility: RwLock::new(vec![SectionVisibility::ALL; VERTICAL_SECTIONS]),
+            generation: AtomicU32::new(0),
         };
 
         chunk_column
@@ -94,9 +126,8 @@ impl ChunkColumn {
         base
     }
 
-    /// Spawning ChunkColumn
-    /// all 0..VERTICAL_SECTIONS from bottom to top
-    /// and add them as a childs to the base node of chunk column
+    /// Prepares the base node of chunk column;
+    /// sections are added as childs by `get_chunk_section` when they are needed
     pub fn spawn_sections(&self, materials: &WorldMaterials) {
         assert!(!self.is_loaded(), "Chunk cannot spawn sections twice!");
 
@@ -107,20 +138,36 @@ impl ChunkColumn {
             .spawn_sections(&self.chunk_position, materials);
     }
 
+    /// Returns the section and creates it if it's not exists
     pub fn get_chunk_section(&self, y: &usize) -> Gd<ChunkSection> {
         assert!(
             *y < VERTICAL_SECTIONS,
             "get_chunk_section_mut y cannot be more than VERTICAL_SECTIONS"
         );
+        let mut chunk_base = self.get_base();
+        let mut c = chunk_base.bind_mut();
+        c.get_or_create_section(*y, &self.chunk_position)
+    }
+
+    /// Returns the section only if it's already created
+    pub fn get_created_section(&self, y: &usize) -> Option<Gd<ChunkSection>> {
         let chunk_base = self.get_base();
         let c = chunk_base.bind();
         c.sections[*y].clone()
     }
 
+    pub fn get_section_visibility(&self, y: &usize) -> SectionVisibility {
+        self.visibility.read()[*y]
+    }
+
+    pub fn set_section_visibility(&self, y: &usize, visibility: SectionVisibility) {
+        self.visibility.write()[*y] = visibility;
+    }
+
     pub fn free(&mut self) {
         let mut chunk_base = self.get_base();
         let mut c = chunk_base.bind_mut();
-        for section in c.sections.iter_mut() {
+        for section in c.sections.iter_mut().flatten() {
             section.bind_mut().destory();
         }
 
@@ -133,11 +180,15 @@ impl ChunkColumn {
         &self.data
     }
 
+    pub fn get_light_lock(&self) -> &ColumnLightLockType {
+        &self.light
+    }
+
     pub fn update_collider_group(&self, is_near: bool) {
         let chunk_base = self.get_base();
         let c = chunk_base.bind();
 
-        for section in c.sections.iter() {
+        for section in c.sections.iter().flatten() {
             section.bind().update_collider_group(is_near);
         }
     }
@@ -150,6 +201,26 @@ impl ChunkColumn {
         self.loaded.store(true, Ordering::Relaxed);
     }
 
+    pub fn get_generation(&self) -> u32 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Replaces the data of the column; light is computed again by the next generation
+    pub fn replace_data(&self, data: ChunkData) {
+        *self.data.write() = data;
+        *self.light.write() = None;
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn get_lod(&self) -> ChunkLod {
+        ChunkLod::from_u8(self.lod.load(Ordering::Relaxed))
+    }
+
+    /// Returns true if the level of detail is changed
+    pub fn set_lod(&self, lod: ChunkLod) -> bool {
+        self.lod.swap(lod as u8, Ordering::Relaxed) != lod as u8
+    }
+
     pub fn get_chunk_position(&self) -> &ChunkPosition {
         &self.chunk_position
     }
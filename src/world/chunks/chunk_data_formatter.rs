@@ -1,20 +1,21 @@
-use arrayvec::ArrayVec;
 use common::{
     CHUNK_SIZE, VERTICAL_SECTIONS,
     blocks::{block_type::BlockType, chunk_collider_info::ChunkColliderInfo, voxel_visibility::VoxelVisibility},
     chunks::{
         block_position::ChunkBlockPosition,
-        chunk_data::{BlockDataInfo, ChunkSectionData},
+        chunk_data::{BlockDataInfo, ChunkData, ChunkSectionData},
     },
 };
 use ndshape::ConstShape;
+use parking_lot::RwLockReadGuard;
 
-use crate::world::block_storage::BlockStorage;
+use crate::world::{block_render::BlockShape, block_storage::BlockStorage};
 
 use super::{
     chunk_column::ColumnDataLockType,
     chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
     near_chunk_data::NearChunksData,
+    section_visibility::{compute_section_visibility, SectionVisibility},
 };
 
 fn get_collider(info: Option<&BlockDataInfo>, block_storage: &BlockStorage) -> Result<ChunkColliderInfo, String> {
@@ -27,7 +28,13 @@ fn get_collider(info: Option<&BlockDataInfo>, block_storage: &BlockStorage) -> R
                 }
             };
             if block_type.get_block_content().is_texture() {
-                ChunkColliderInfo::create(block_type.get_voxel_visibility().clone(), Some(block_info.clone()))
+                match block_storage.get_shape(block_info) {
+                    BlockShape::Cube => {
+                        ChunkColliderInfo::create(block_type.get_voxel_visibility().clone(), Some(block_info.clone()))
+                    }
+                    // Shapes are meshed separately; greedy meshing sees them as empty
+                    _ => ChunkColliderInfo::create(VoxelVisibility::Empty, Some(block_info.clone())),
+                }
             } else {
                 ChunkColliderInfo::create(VoxelVisibility::Empty, None)
             }
@@ -48,12 +55,13 @@ pub fn generate_single_block(block_type: &BlockType, block_info: &BlockDataInfo)
 
 /// Generates collider data for mesh
 /// with size of CHUNK_SIZE + 2 boundary
+/// and the visibility graph of the section faces
 pub fn format_chunk_data_with_boundaries(
     chunks_near: Option<&NearChunksData>,
     chunk_data: &ColumnDataLockType,
     block_storage: &BlockStorage,
     y: usize,
-) -> Result<(ChunkColliderDataBordered, usize), String> {
+) -> Result<(ChunkColliderDataBordered, usize, SectionVisibility), String> {
     // Fill with solid block by default
     let mut b_chunk = [ChunkColliderInfo::create(VoxelVisibility::Opaque, None); ChunkBordersShape::SIZE as usize];
 
@@ -73,7 +81,7 @@ pub fn format_chunk_data_with_boundaries(
                     Err(e) => return Err(e),
                 };
 
-                if *collider.get_voxel_visibility() != VoxelVisibility::Empty {
+                if *collider.get_voxel_visibility() != VoxelVisibility::Empty || collider.get_block_info().is_some() {
                     mesh_count += 1
                 }
 
@@ -82,39 +90,45 @@ pub fn format_chunk_data_with_boundaries(
         }
     }
 
-    // fill boundaries
     if mesh_count == 0 {
-        return Ok((b_chunk, mesh_count));
+        return Ok((b_chunk, mesh_count, SectionVisibility::ALL));
     }
+    let visibility = compute_section_visibility(&b_chunk);
 
+    // fill boundaries
     let chunks_near = match chunks_near {
         Some(c) => c,
         None => {
-            return Ok((b_chunk, mesh_count));
+            return Ok((b_chunk, mesh_count, visibility));
         }
     };
-    let boundary = get_boundaries_chunks(&chunks_near, &chunk_data, y);
-    for (axis, axis_diff, chunk_section) in boundary {
-        for i in 0_u32..(CHUNK_SIZE as u32) {
-            for j in 0_u32..(CHUNK_SIZE as u32) {
-                let (i_v, o_v) = match axis_diff {
-                    -1 => (0, CHUNK_SIZE as u32 - 1),
-                    _ => (CHUNK_SIZE as u32 + 1, 0),
-                };
-
-                let (pos_inside, pos_outside) = match axis {
-                    0 => ([i_v, i + 1, j + 1], [o_v, i, j]),
-                    1 => ([i + 1, i_v, j + 1], [i, o_v, j]),
-                    _ => ([i + 1, j + 1, i_v], [i, j, o_v]),
-                };
+    // Read locks of the neighbour columns are held while the borders are filled
+    let mut columns_near: Vec<((i32, i32), RwLockReadGuard<'_, ChunkData>)> = Default::default();
+    for x in -1..=1 {
+        for z in -1..=1 {
+            if let Some(column) = chunks_near.get(x, z) {
+                columns_near.push(((x, z), column.read()));
+            }
+        }
+    }
 
-                let pos_i = ChunkBordersShape::linearize(pos_inside);
+    // Faces, edges and corners; edges are needed for the ambient occlusion
+    let border = CHUNK_SIZE as u32 + 1;
+    for x in 0_u32..=border {
+        for b_y in 0_u32..=border {
+            for z in 0_u32..=border {
+                if x > 0 && x < border && b_y > 0 && b_y < border && z > 0 && z < border {
+                    continue;
+                }
 
-                let pos_o = ChunkBlockPosition::new(pos_outside[0] as u8, pos_outside[1] as u8, pos_outside[2] as u8);
+                let (offset_x, local_x) = get_border_offset(x);
+                let (offset_y, local_y) = get_border_offset(b_y);
+                let (offset_z, local_z) = get_border_offset(z);
 
-                let collider = match chunk_section.as_ref() {
+                let section = get_section_near(&*cd, &columns_near, y, [offset_x, offset_y, offset_z]);
+                let collider = match section {
                     Some(border_chunk_data) => {
-                        let block_info = border_chunk_data.get(&pos_o);
+                        let block_info = border_chunk_data.get(&ChunkBlockPosition::new(local_x, local_y, local_z));
                         match get_collider(block_info, block_storage) {
                             Ok(m) => m,
                             Err(e) => return Err(e),
@@ -122,73 +136,39 @@ pub fn format_chunk_data_with_boundaries(
                     }
                     None => ChunkColliderInfo::create(VoxelVisibility::Empty, None),
                 };
-                b_chunk[pos_i as usize] = collider;
+                b_chunk[ChunkBordersShape::linearize([x, b_y, z]) as usize] = collider;
             }
         }
     }
 
-    return Ok((b_chunk, mesh_count));
+    return Ok((b_chunk, mesh_count, visibility));
 }
 
-type BondaryType<'a> = ArrayVec<(i8, i32, Option<Box<ChunkSectionData>>), 6>;
-
-fn get_boundaries_chunks<'a>(
-    chunks_near: &'a NearChunksData,
-    chunk_data: &'a ColumnDataLockType,
-    y: usize,
-) -> BondaryType<'a> {
-    let mut result: BondaryType = Default::default();
-
-    let current_section = chunk_data.read();
-    // x, y, z
-    for axis in 0_i8..3_i8 {
-        for axis_diff in (-1_i32..2_i32).step_by(2) {
-            let side = match axis {
-                // x
-                0 => {
-                    if axis_diff == -1 {
-                        get_section(&chunks_near.forward, y)
-                    } else {
-                        get_section(&chunks_near.behind, y)
-                    }
-                }
-
-                // y
-                1 => {
-                    let index = y as i32 + axis_diff;
-                    if index >= 0 && index < VERTICAL_SECTIONS as i32 {
-                        match current_section.get(index as usize) {
-                            Some(r) => Some(r.clone()),
-                            None => None,
-                        }
-                    } else {
-                        None
-                    }
-                }
-
-                // z
-                2 => {
-                    if axis_diff == -1 {
-                        get_section(&chunks_near.left, y)
-                    } else {
-                        get_section(&chunks_near.right, y)
-                    }
-                }
-                _ => panic!(),
-            };
-
-            result.push((axis, axis_diff, side));
-        }
+/// Section offset and local position of the bordered coordinate
+fn get_border_offset(b: u32) -> (i32, u8) {
+    if b == 0 {
+        (-1, CHUNK_SIZE - 1)
+    } else if b == CHUNK_SIZE as u32 + 1 {
+        (1, 0)
+    } else {
+        (0, (b - 1) as u8)
     }
-    result
 }
 
-fn get_section<'a>(column: &'a Option<ColumnDataLockType>, y: usize) -> Option<Box<ChunkSectionData>> {
-    match column {
-        Some(c) => match c.read().get(y) {
-            Some(r) => Some(r.clone()),
-            None => None,
-        },
-        None => None,
+fn get_section_near<'a>(
+    current_column: &'a ChunkData,
+    columns_near: &'a [((i32, i32), RwLockReadGuard<'a, ChunkData>)],
+    y: usize,
+    offset: [i32; 3],
+) -> Option<&'a ChunkSectionData> {
+    let section_y = y as i32 + offset[1];
+    if section_y < 0 || section_y >= VERTICAL_SECTIONS as i32 {
+        return None;
     }
+
+    let column = match (offset[0], offset[2]) {
+        (0, 0) => current_column,
+        (x, z) => &**columns_near.iter().find(|(o, _)| *o == (x, z))?.1,
+    };
+    column.get(section_y as usize).map(|s| &**s)
 }
@@ -1,115 +1,372 @@
 use super::{
+    chunk_column::ChunkColumn,
     chunk_data_formatter::format_chunk_data_with_boundaries,
     chunk_section::{ChunkColliderDataBordered, ChunkSection},
     chunks_map::ChunkLock,
-    mesh::mesh_generator::{build_collider, generate_buffer, generate_mesh},
+    light::{
+        column_light::ChunkLightBordered,
+        light_engine::{compute_column_light, format_light_from_columns, update_column_light_on_edit, LightColumns},
+    },
+    mesh::{
+        block_shapes::generate_shapes_geometry,
+        lod::{downsample_chunk_data, generate_skirts, ChunkLod},
+        mesh_generator::{build_collider, generate_buffer, generate_mesh, MeshData, MeshPass},
+        mesh_settings::{ChunkMeshSettings, ChunkMeshSettingsType},
+        translucent::generate_translucent_meshes,
+    },
     near_chunk_data::NearChunksData,
+    objects_container::ObjectsContainer,
+    remesh_queue::SectionKey,
 };
 use crate::{
-    client_scripts::resource_manager::ResourceManager,
     utils::textures::texture_mapper::TextureMapper,
     world::{
         block_storage::BlockStorage,
-        physics::PhysicsProxy,
-        worlds_manager::{BlockStorageType, TextureMapperType, WorldMaterials},
+        worlds_manager::{BlockStorageType, TextureMapperType},
     },
 };
-use common::VERTICAL_SECTIONS;
+use ahash::AHashSet;
+use common::{
+    chunks::{
+        block_position::{BlockPosition, BlockPositionTrait},
+        chunk_position::ChunkPosition,
+    },
+    CHUNK_SIZE, VERTICAL_SECTIONS,
+};
 use flume::Sender;
 use godot::{
     classes::Engine,
     obj::{Gd, Singleton},
 };
+use physics::PhysicsColliderBuilder;
+use std::{any::Any, panic::AssertUnwindSafe};
+
+/// Section geometry built without godot objects
+pub struct SectionGeometry {
+    y: usize,
+    mesh: MeshData,
+    mesh_transparent: MeshData,
+    mesh_liquid: MeshData,
+    collider: Option<PhysicsColliderBuilder>,
+}
+
+impl SectionGeometry {
+    pub fn has_mesh(&self) -> bool {
+        !self.mesh.is_empty() || !self.mesh_transparent.is_empty() || !self.mesh_liquid.is_empty()
+    }
+}
+
+/// Generated chunk column waiting for the commit on the main thread
+pub struct ChunkGeometry {
+    // Generation of the column data the geometry is built from
+    pub generation: u32,
+
+    pub sections: Vec<SectionGeometry>,
+
+    // Sections with block models; models are godot nodes, so they are created on the commit
+    pub models_sections: Vec<usize>,
+
+    // Sections of the neighbours lit by the column; their meshes are outdated
+    pub light_changed: Vec<SectionKey>,
+}
+
+impl ChunkGeometry {
+    /// Sections which will get nodes on the commit
+    pub fn get_commit_size(&self) -> usize {
+        self.sections.len() + self.models_sections.len()
+    }
+}
+
+/// Generated column or the error of its generation
+pub type ChunkGeometryResult = (ChunkLock, Result<ChunkGeometry, String>);
+
+/// Remeshed section of the column or the error of the data formatting
+pub type RemeshResult = (ChunkLock, Result<SectionGeometry, String>);
 
 /// Generate chunk data in separate thread
-/// and send gd instance id to the main thread to add_child it to the main tree
+/// and send it to the main thread to create the nodes and add_child them to the main tree
 pub(crate) fn generate_chunk(
     chunk_column: ChunkLock,
     chunks_near: NearChunksData,
-    chunks_loaded: Sender<ChunkLock>,
-    materials: WorldMaterials,
+    light_columns: LightColumns,
+    chunks_loaded: Sender<ChunkGeometryResult>,
 
     texture_mapper: TextureMapperType,
     block_storage: BlockStorageType,
-
-    physics: PhysicsProxy,
-    resource_manager: &ResourceManager,
+    mesh_settings: ChunkMeshSettingsType,
 ) {
-    let resources_storage_lock = resource_manager.get_resources_storage_lock();
     rayon::spawn(move || {
-        let data = chunk_column.read().get_data_lock().clone();
+        // The column is always sent back, so the error is logged on the main thread
+        let geometry = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            build_chunk(
+                &chunk_column,
+                &chunks_near,
+                &light_columns,
+                &texture_mapper.read(),
+                &block_storage.read(),
+                &mesh_settings.read(),
+            )
+        }))
+        .unwrap_or_else(|e| Err(format!("column build panicked: {}", get_panic_message(&*e))));
+        chunks_loaded
+            .send((chunk_column, geometry))
+            .expect("chunks_loaded channel poisoned");
+    });
+}
+
+fn build_chunk(
+    chunk_column: &ChunkLock,
+    chunks_near: &NearChunksData,
+    light_columns: &LightColumns,
+    texture_mapper: &TextureMapper,
+    block_storage: &BlockStorage,
+    mesh_settings: &ChunkMeshSettings,
+) -> Result<ChunkGeometry, String> {
+    // Taken before the data, so replaced data is never committed as the old generation
+    let generation = chunk_column.read().get_generation();
+    let data = chunk_column.read().get_data_lock().clone();
+    let lod = chunk_column.read().get_lod();
+    let chunk_position = chunk_column.read().get_chunk_position().clone();
 
-        let chunk_position = chunk_column.read().get_chunk_position().clone();
+    let light_changed = compute_column_light(light_columns, block_storage)
+        .into_iter()
+        .map(|((x, z), y)| (chunk_position.clone() + ChunkPosition::new(x as i64, z as i64), y))
+        .collect();
 
-        chunk_column.read().spawn_sections(&materials);
-        for y in 0..VERTICAL_SECTIONS {
-            let (bordered_chunk_data, mesh_count) = match format_chunk_data_with_boundaries(
-                Some(&chunks_near),
-                &data,
-                &*block_storage.read(),
+    let mut geometry = ChunkGeometry {
+        generation,
+        sections: Default::default(),
+        models_sections: Default::default(),
+        light_changed,
+    };
+
+    for y in 0..VERTICAL_SECTIONS {
+        let (bordered_chunk_data, mesh_count, visibility) =
+            format_chunk_data_with_boundaries(Some(chunks_near), &data, block_storage, y)?;
+
+        chunk_column.read().set_section_visibility(&y, visibility);
+
+        if mesh_count > 0 {
+            let chunk_light = format_light_from_columns(light_columns, y);
+            let section_geometry = generate_section_geometry(
                 y,
-            ) {
-                Ok(i) => i,
-                Err(e) => {
-                    log::error!("&cformat_chunk_data_with_boundaries error: &4{}", e);
-                    panic!("format_chunk_data_with_boundaries error: {}", e);
-                },
-            };
-
-            if mesh_count > 0 {
-                let mut chunk_section = chunk_column.read().get_chunk_section(&y);
-                generate_chunk_geometry(
-                    &mut chunk_section,
-                    &texture_mapper.read(),
-                    &bordered_chunk_data,
-                    &block_storage.read(),
-                );
-
-                let mut cs = chunk_section.bind_mut();
-                let objects_container = cs.get_objects_container_mut();
-
-                let d = data.read();
-                let section_data = d.get(y).unwrap();
-                objects_container
-                    .bind_mut()
-                    .setup(
-                        y as u32,
-                        &chunk_position,
-                        section_data,
-                        &*block_storage.read(),
-                        &physics,
-                        &*resources_storage_lock.read(),
-                    )
-                    .unwrap();
+                &chunk_position,
+                texture_mapper,
+                &bordered_chunk_data,
+                Some(&chunk_light),
+                block_storage,
+                mesh_settings,
+                lod,
+            );
+            if section_geometry.has_mesh() {
+                geometry.sections.push(section_geometry);
             }
         }
 
-        chunks_loaded
-            .send(chunk_column.clone())
-            .expect("chunks_loaded channel poisoned");
+        let d = data.read();
+        let section_data = d.get(y).unwrap();
+        if ObjectsContainer::contains_models(section_data, block_storage) {
+            geometry.models_sections.push(y);
+        }
+    }
+    Ok(geometry)
+}
+
+/// Remesh one section of the loaded column in separate thread
+pub(crate) fn generate_section(
+    chunk_column: ChunkLock,
+    y: usize,
+    chunks_near: NearChunksData,
+    light_columns: LightColumns,
+    results: Sender<RemeshResult>,
+
+    texture_mapper: TextureMapperType,
+    block_storage: BlockStorageType,
+    mesh_settings: ChunkMeshSettingsType,
+) {
+    rayon::spawn(move || {
+        // The batch waits for every section, so the panic is sent as the error too
+        let geometry = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            build_section(
+                &chunk_column,
+                y,
+                &chunks_near,
+                &light_columns,
+                &texture_mapper.read(),
+                &block_storage.read(),
+                &mesh_settings.read(),
+            )
+        }))
+        .unwrap_or_else(|e| Err(format!("section {} build panicked: {}", y, get_panic_message(&*e))));
+        results
+            .send((chunk_column, geometry))
+            .expect("remesh results channel poisoned");
+    });
+}
+
+/// Updates the light around the edited blocks in separate thread
+///
+/// Sections with the changed light are sent back to be remeshed
+pub(crate) fn update_edits_light(
+    edits: Vec<(BlockPosition, LightColumns)>,
+    results: Sender<Vec<SectionKey>>,
+    block_storage: BlockStorageType,
+) {
+    rayon::spawn(move || {
+        // Remeshing waits for the light, so the panic is only logged
+        let changed_sections = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let block_storage = block_storage.read();
+            let mut changed_sections: AHashSet<SectionKey> = Default::default();
+            for (position, light_columns) in edits.iter() {
+                let center = position.get_chunk_position();
+                let (section, block_position) = position.get_block_position();
+                let pos = [
+                    block_position.x as i32,
+                    (section * CHUNK_SIZE as u32) as i32 + block_position.y as i32,
+                    block_position.z as i32,
+                ];
+                for ((x, z), y) in update_column_light_on_edit(light_columns, &block_storage, pos) {
+                    changed_sections.insert((center.clone() + ChunkPosition::new(x as i64, z as i64), y));
+                }
+            }
+            changed_sections.into_iter().collect()
+        }))
+        .unwrap_or_else(|e| {
+            log::error!(target: "chunk_map", "&cEdits light update panicked: &4{}", get_panic_message(&*e));
+            Default::default()
+        });
+        results.send(changed_sections).expect("light results channel poisoned");
     });
 }
 
-pub fn generate_chunk_geometry(
-    chunk_section: &mut Gd<ChunkSection>,
+fn get_panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("unknown", |m| m.as_str()),
+    }
+}
+
+fn build_section(
+    chunk_column: &ChunkLock,
+    y: usize,
+    chunks_near: &NearChunksData,
+    light_columns: &LightColumns,
+    texture_mapper: &TextureMapper,
+    block_storage: &BlockStorage,
+    mesh_settings: &ChunkMeshSettings,
+) -> Result<SectionGeometry, String> {
+    let data = chunk_column.read().get_data_lock().clone();
+
+    let (bordered_chunk_data, _mesh_count, visibility) =
+        format_chunk_data_with_boundaries(Some(chunks_near), &data, block_storage, y)?;
+    chunk_column.read().set_section_visibility(&y, visibility);
+
+    // Light is reset when the server sends the column data again;
+    // all columns around are sent again too, so their sections are remeshed anyway
+    compute_column_light(light_columns, block_storage);
+    let chunk_light = format_light_from_columns(light_columns, y);
+
+    let lod = chunk_column.read().get_lod();
+    let chunk_position = chunk_column.read().get_chunk_position().clone();
+    Ok(generate_section_geometry(
+        y,
+        &chunk_position,
+        texture_mapper,
+        &bordered_chunk_data,
+        Some(&chunk_light),
+        block_storage,
+        mesh_settings,
+        lod,
+    ))
+}
+
+/// Builds the meshes and the collider of the section; safe to call from any thread
+pub fn generate_section_geometry(
+    y: usize,
+    chunk_position: &ChunkPosition,
     texture_mapper: &TextureMapper,
     chunk_collider_data: &ChunkColliderDataBordered,
+    chunk_light: Option<&ChunkLightBordered>,
     block_storage: &BlockStorage,
-) {
+    mesh_settings: &ChunkMeshSettings,
+    lod: ChunkLod,
+) -> SectionGeometry {
     let buffer = generate_buffer(chunk_collider_data);
-    let mut cs = chunk_section.bind_mut();
+    let size = CHUNK_SIZE as i32;
+    let origin = [chunk_position.x as i32 * size, y as i32 * size, chunk_position.z as i32 * size];
 
-    let new_mesh = generate_mesh(&texture_mapper, &buffer, &block_storage, false);
-    let new_mesh_transparent = generate_mesh(&texture_mapper, &buffer, &block_storage, true);
-    cs.set_new_mesh(&new_mesh, &new_mesh_transparent);
+    // Collider is always built from the full data
+    let lod_data = match lod {
+        ChunkLod::Full => None,
+        _ => Some(downsample_chunk_data(chunk_collider_data, lod)),
+    };
+    let lod_buffer = lod_data.as_ref().map(|d| generate_buffer(d));
+    let (mesh_data, mesh_buffer) = match (lod_data.as_ref(), lod_buffer.as_ref()) {
+        (Some(d), Some(b)) => (d, b),
+        _ => (chunk_collider_data, &buffer),
+    };
 
-    let has_mesh = new_mesh.get_surface_count() > 0 || new_mesh_transparent.get_surface_count() > 0;
+    let mut mesh = generate_mesh(
+        &texture_mapper,
+        mesh_data,
+        chunk_light,
+        mesh_buffer,
+        &block_storage,
+        mesh_settings,
+        MeshPass::Opaque,
+        origin,
+    );
+    if let Some(lod_data) = lod_data.as_ref() {
+        mesh.append(generate_skirts(
+            texture_mapper,
+            chunk_collider_data,
+            lod_data,
+            chunk_light,
+            block_storage,
+            origin,
+        ));
+    }
+    let mut translucent =
+        generate_translucent_meshes(texture_mapper, mesh_data, chunk_light, block_storage, mesh_settings, origin);
+
+    let shapes = generate_shapes_geometry(texture_mapper, chunk_collider_data, chunk_light, block_storage, origin);
+    // Small shapes are not rendered with the lower detail
+    if lod == ChunkLod::Full {
+        mesh.append(shapes.mesh);
+        translucent.transparent.append(shapes.mesh_transparent);
+    }
+
+    let mut geometry = SectionGeometry {
+        y,
+        mesh,
+        mesh_transparent: translucent.transparent,
+        mesh_liquid: translucent.liquid,
+        collider: None,
+    };
+    // Plants have meshes without any collider faces
+    let has_collider_faces = buffer.groups.iter().any(|g| !g.is_empty()) || !shapes.collider_quads.is_empty();
+    if geometry.has_mesh() && has_collider_faces {
+        geometry.collider = Some(build_collider(&buffer, &shapes.collider_quads));
+    }
+    geometry
+}
+
+/// Creates godot meshes from the geometry; must be called from the main thread
+///
+/// Returns the section if it has nodes;
+/// sections without geometry are not created
+pub fn apply_section_geometry(chunk_column: &ChunkColumn, geometry: SectionGeometry) -> Option<Gd<ChunkSection>> {
+    let mut chunk_section = match geometry.has_mesh() {
+        true => chunk_column.get_chunk_section(&geometry.y),
+        false => chunk_column.get_created_section(&geometry.y)?,
+    };
+
+    let mut cs = chunk_section.bind_mut();
+    cs.set_new_mesh(&geometry.mesh.build_mesh(), geometry.mesh_transparent, geometry.mesh_liquid);
 
     if !Engine::singleton().is_editor_hint() {
-        let collider_builder = match has_mesh {
-            true => Some(build_collider(&buffer)),
-            false => None,
-        };
-        cs.set_collider(collider_builder);
+        cs.set_collider(geometry.collider);
     }
+    drop(cs);
+    Some(chunk_section)
 }
@@ -1,4 +1,4 @@
-use super::objects_container::ObjectsContainer;
+use super::{mesh::mesh_generator::MeshData, objects_container::ObjectsContainer};
 use crate::{
     utils::bridge::{GodotPositionConverter, IntoNetworkVector},
     world::{
@@ -25,6 +25,9 @@ use std::borrow::BorrowMut;
 
 const TRANSPARENCY_SPEED: f32 = 5.0;
 
+// Translucent faces are sorted again when the camera moved farther (in blocks)
+pub const RESORT_DISTANCE: f32 = 1.0;
+
 //pub type ChunkShape = ConstShape3u32<CHUNK_SIZE_BOUNDARY, CHUNK_SIZE_BOUNDARY, CHUNK_SIZE_BOUNDARY>;
 pub type ChunkBordersShape = ConstShape3u32<CHUNK_SIZE_BOUNDARY, CHUNK_SIZE_BOUNDARY, CHUNK_SIZE_BOUNDARY>;
 
@@ -40,8 +43,14 @@ pub struct ChunkSection {
 
     mesh: Gd<MeshInstance3D>,
     mesh_transparent: Gd<MeshInstance3D>,
+    mesh_liquid: Gd<MeshInstance3D>,
     objects_container: Gd<ObjectsContainer>,
 
+    // Translucent geometry is kept to sort it by the camera distance
+    transparent_data: MeshData,
+    liquid_data: MeshData,
+    sorted_from: Option<Vector3>,
+
     chunk_position: ChunkPosition,
     y: u8,
 
@@ -51,6 +60,9 @@ pub struct ChunkSection {
 
     set_geometry_first_time: bool,
     transparancy: f32,
+
+    // Hidden by the occlusion culling
+    culled: bool,
 }
 
 impl ChunkSection {
@@ -63,10 +75,18 @@ impl ChunkSection {
         mesh_transparent.set_name(&format!("ChunkMesh {} Transparent", y));
         mesh_transparent.set_material_override(&materials.get_material_3d_transparent());
 
+        let mut mesh_liquid = MeshInstance3D::new_alloc();
+        mesh_liquid.set_name(&format!("ChunkMesh {} Liquid", y));
+        mesh_liquid.set_material_override(&materials.get_material_3d_liquid());
+
         Self {
             base,
             mesh,
             mesh_transparent,
+            mesh_liquid,
+            transparent_data: Default::default(),
+            liquid_data: Default::default(),
+            sorted_from: None,
             chunk_position,
             y,
 
@@ -77,6 +97,8 @@ impl ChunkSection {
             objects_container: ObjectsContainer::new_alloc(),
             set_geometry_first_time: false,
             transparancy: 1.0,
+
+            culled: false,
         }
     }
 
@@ -106,26 +128,72 @@ impl ChunkSection {
     }
 
     /// Updates the mesh from a separate thread
-    pub fn set_new_mesh(&mut self, new_mesh: &Gd<ArrayMesh>, new_mesh_transparent: &Gd<ArrayMesh>) {
+    ///
+    /// Translucent meshes are built unsorted and sorted by `sort_translucent` later
+    pub fn set_new_mesh(&mut self, new_mesh: &Gd<ArrayMesh>, transparent: MeshData, liquid: MeshData) {
         // Set active only for sections that conatains vertices
-        let has_mesh = new_mesh.get_surface_count() > 0 || new_mesh_transparent.get_surface_count() > 0;
+        let has_mesh = new_mesh.get_surface_count() > 0 || !transparent.is_empty() || !liquid.is_empty();
 
         let mesh = self.mesh.borrow_mut();
         mesh.set_mesh(new_mesh);
 
-        let mesh_transparent = self.mesh_transparent.borrow_mut();
-        mesh_transparent.set_mesh(new_mesh_transparent);
+        self.transparent_data = transparent;
+        self.liquid_data = liquid;
+        self.sorted_from = None;
+        self.update_translucent_meshes();
 
         if has_mesh && !self.set_geometry_first_time {
             self.set_geometry_first_time = true;
             self.transparancy = 1.0;
-            mesh.set_transparency(self.transparancy);
+            self.mesh.set_transparency(self.transparancy);
         } else {
             // self.base_mut().set_process(false);
             self.base_mut().call_deferred("set_process", &[false.to_variant()]);
         }
     }
 
+    fn update_translucent_meshes(&mut self) {
+        self.mesh_transparent.set_mesh(&self.transparent_data.build_mesh());
+        self.mesh_liquid.set_mesh(&self.liquid_data.build_mesh());
+    }
+
+    pub fn has_translucent(&self) -> bool {
+        !self.transparent_data.is_empty() || !self.liquid_data.is_empty()
+    }
+
+    /// Never sorted or the camera moved since the last sorting
+    pub fn is_resort_needed(&self, camera_position: Vector3) -> bool {
+        if !self.has_translucent() {
+            return false;
+        }
+        match self.sorted_from {
+            Some(sorted_from) => sorted_from.distance_squared_to(camera_position) > RESORT_DISTANCE * RESORT_DISTANCE,
+            None => true,
+        }
+    }
+
+    /// Orders translucent faces from the farthest to the nearest to the camera
+    pub fn sort_translucent(&mut self, camera_position: Vector3) {
+        let local_camera = camera_position - self.get_section_position();
+        self.transparent_data.sort_back_to_front(local_camera);
+        self.liquid_data.sort_back_to_front(local_camera);
+        self.update_translucent_meshes();
+        self.sorted_from = Some(camera_position);
+    }
+
+    /// Hides the section which can't be seen from the camera
+    pub fn set_culled(&mut self, culled: bool) {
+        if self.culled == culled {
+            return;
+        }
+        self.culled = culled;
+        self.base_mut().set_visible(!culled);
+    }
+
+    pub fn is_culled(&self) -> bool {
+        self.culled
+    }
+
     pub fn update_collider_group(&self, is_near: bool) {
         let Some(collider) = self.collider.as_ref() else {
             return;
@@ -186,6 +254,9 @@ impl INode3D for ChunkSection {
         let mesh_transparent = self.mesh_transparent.clone();
         self.base_mut().add_child(&mesh_transparent);
 
+        let mesh_liquid = self.mesh_liquid.clone();
+        self.base_mut().add_child(&mesh_liquid);
+
         let objects_container = self.objects_container.clone();
         self.base_mut().add_child(&objects_container);
     }
@@ -203,6 +274,9 @@ impl INode3D for ChunkSection {
 
             let mesh_transparent = self.mesh_transparent.borrow_mut();
             mesh_transparent.set_transparency(self.transparancy);
+
+            let mesh_liquid = self.mesh_liquid.borrow_mut();
+            mesh_liquid.set_transparency(self.transparancy);
         } else {
             self.base_mut().set_process(false);
         }
@@ -1,12 +1,21 @@
 use super::{
     chunk_column::{ChunkColumn, ColumnDataLockType},
-    chunk_data_formatter::format_chunk_data_with_boundaries,
-    chunk_generator::{generate_chunk, generate_chunk_geometry},
+    chunk_generator::{
+        apply_section_geometry, generate_chunk, generate_section, update_edits_light, ChunkGeometryResult, RemeshResult,
+    },
+    chunk_section::{ChunkSection, RESORT_DISTANCE},
+    light::light_engine::LightColumns,
+    mesh::{
+        lod::ChunkLod,
+        mesh_settings::{ChunkMeshSettings, ChunkMeshSettingsType},
+    },
     near_chunk_data::NearChunksData,
+    remesh_queue::{RemeshBatch, RemeshPriority, RemeshQueue, SectionKey},
+    section_visibility::{opposite_face, SECTION_FACES},
 };
 use crate::{
-    client_scripts::resource_manager::{ResourceManager, ResourceStorage},
-    utils::textures::texture_mapper::TextureMapper,
+    client_scripts::resource_manager::ResourceStorage,
+    utils::bridge::{GodotPositionConverter, IntoChunkPositionVector},
     world::{
         block_storage::BlockStorage,
         physics::PhysicsProxy,
@@ -26,16 +35,41 @@ use common::{
 use flume::{unbounded, Receiver, Sender};
 use godot::prelude::*;
 use parking_lot::RwLock;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::rc::Rc;
 use std::sync::Arc;
 
 const MAX_CHUNKS_SPAWN_PER_FRAME: usize = 6;
+
+/// Sections which get nodes and meshes on the main thread in one frame
+const MAX_SECTIONS_SPAWN_PER_FRAME: usize = 48;
+
+/// Sections remeshed on the workers at once
+const MAX_REMESH_BATCH_SIZE: usize = 64;
+
+/// Sections with translucent faces sorted again in one frame
+const MAX_TRANSLUCENT_SORTS_PER_FRAME: usize = 8;
 pub const LIMIT_CHUNK_LOADING_AT_A_TIME: usize = 16;
 
 pub type ChunkLock = Arc<RwLock<ChunkColumn>>;
 pub type ChunksType = AHashMap<ChunkPosition, ChunkLock>;
 
+/// Result of the last sections culling
+///
+/// Culling is computed again only if the camera or the sections changed;
+/// the flood fill buffers are kept to not allocate them each time.
+#[derive(Default)]
+struct SectionsCulling {
+    camera_section: Option<SectionKey>,
+    camera_loaded: bool,
+    frustum: Vec<Plane>,
+    visible: HashSet<SectionKey>,
+
+    next_visible: HashSet<SectionKey>,
+    queue: VecDeque<(ChunkPosition, usize, Option<usize>, u8)>,
+}
+
 /// Container of all chunk sections
 #[derive(GodotClass)]
 #[class(no_init, tool, base=Node)]
@@ -51,10 +85,32 @@ pub struct ChunkMap {
     // Limited queue by LIMIT_CHUNK_LOADING_AT_A_TIME
     loading_queue: Rc<RefCell<Vec<ChunkPosition>>>,
 
-    // Loaded chunks waiting to be added to tree
-    chunks_to_spawn: (Sender<ChunkLock>, Receiver<ChunkLock>),
+    // Generated chunks waiting to get nodes and be added to tree
+    chunks_to_spawn: (Sender<ChunkGeometryResult>, Receiver<ChunkGeometryResult>),
+
+    chunks_to_update: Rc<RefCell<RemeshQueue>>,
+
+    // Sections remeshing on the workers; only one batch is in flight
+    remesh_batch: Rc<RefCell<Option<RemeshBatch>>>,
+    remesh_results: (Sender<RemeshResult>, Receiver<RemeshResult>),
+
+    // Edited blocks waiting for the light update on the workers
+    light_edits: Rc<RefCell<Vec<BlockPosition>>>,
+
+    // Light update in flight; sections are not remeshed until it's done
+    light_update: Cell<bool>,
+    light_results: (Sender<Vec<SectionKey>>, Receiver<Vec<SectionKey>>),
 
-    chunks_to_update: Rc<RefCell<HashSet<(ChunkPosition, usize)>>>,
+    culling: RefCell<SectionsCulling>,
+
+    // Sections were loaded, remeshed or unloaded since the last culling
+    culling_outdated: Cell<bool>,
+
+    // Camera position of the last translucent sorting
+    sorting_camera: Cell<Option<Vector3>>,
+
+    // Sections were shown or not all of them were sorted in the last frame
+    sorting_outdated: Cell<bool>,
 }
 
 #[godot_api]
@@ -71,6 +127,18 @@ impl ChunkMap {
             chunks_to_spawn: unbounded(),
 
             chunks_to_update: Default::default(),
+            remesh_batch: Default::default(),
+            remesh_results: unbounded(),
+
+            light_edits: Default::default(),
+            light_update: Cell::new(false),
+            light_results: unbounded(),
+
+            culling: Default::default(),
+            culling_outdated: Cell::new(true),
+
+            sorting_camera: Cell::new(None),
+            sorting_outdated: Cell::new(true),
         }
     }
 
@@ -119,26 +187,39 @@ impl ChunkMap {
     }
 
     /// Create chunk column and send it to render queue
-    pub fn create_chunk_column(&mut self, center: ChunkPosition, chunk_position: ChunkPosition, sections: ChunkData) {
-        if self.chunks.contains_key(&chunk_position) {
+    pub fn create_chunk_column(
+        &mut self,
+        center: ChunkPosition,
+        chunk_position: ChunkPosition,
+        sections: ChunkData,
+        lod: ChunkLod,
+    ) {
+        if sections.len() == 0 {
             log::error!(
                 target: "chunk_map",
-                "Network sended chunk to load, but it already exists: {}",
+                "Recieved empty ChunkData for chunk_position: {}",
                 chunk_position
             );
             return;
         }
 
-        if sections.len() == 0 {
-            log::error!(
-                target: "chunk_map",
-                "Recieved empty ChunkData for chunk_position: {}",
-                chunk_position
-            );
+        if let Some(chunk_column) = self.chunks.get(&chunk_position) {
+            // The server sends chunks again after the session was restored;
+            // keep the column and rebuild its geometry from the new data.
+            // Geometry which is generated now is discarded by the generation and loaded again.
+            let chunk_column = chunk_column.read();
+            chunk_column.replace_data(sections);
+            if chunk_column.is_loaded() {
+                let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+                for y in 0..VERTICAL_SECTIONS {
+                    chunks_to_update.insert((chunk_position, y), RemeshPriority::Edit);
+                }
+            }
+            log::debug!(target: "chunk_map", "Chunk data is refreshed: {}", chunk_position);
             return;
         }
 
-        let chunk_column = ChunkColumn::create(chunk_position, sections);
+        let chunk_column = ChunkColumn::create(chunk_position, sections, lod);
         self.chunks
             .insert(chunk_position.clone(), Arc::new(RwLock::new(chunk_column)));
 
@@ -177,11 +258,9 @@ impl ChunkMap {
 
     pub fn send_chunks_to_load(
         &self,
-        materials: &WorldMaterials,
         texture_mapper: TextureMapperType,
         block_storage: BlockStorageType,
-        physics: &PhysicsProxy,
-        resource_manager: &ResourceManager,
+        mesh_settings: &ChunkMeshSettingsType,
     ) {
         let mut loading_len = self.loading_queue.borrow().len();
 
@@ -205,49 +284,119 @@ impl ChunkMap {
             generate_chunk(
                 chunk_column.clone(),
                 near_chunks_data,
+                self.get_light_columns(chunk_position),
                 self.chunks_to_spawn.0.clone(),
-                materials.clone(),
                 texture_mapper.clone(),
                 block_storage.clone(),
-                physics.clone(),
-                resource_manager,
+                mesh_settings.clone(),
             );
             loading_len += 1;
             return false;
         });
     }
 
-    /// Retrieving loaded chunks to add them to the root node
-    pub fn spawn_loaded_chunks(&mut self, physics: &PhysicsProxy) -> Vec<ChunkPosition> {
+    /// Retrieving generated chunks to create their nodes and add them to the root node
+    ///
+    /// Godot objects are created only here, on the main thread
+    pub fn spawn_loaded_chunks(
+        &mut self,
+        materials: &WorldMaterials,
+        block_storage: &BlockStorage,
+        physics: &PhysicsProxy,
+        resource_storage: &ResourceStorage,
+    ) -> Vec<ChunkPosition> {
         let mut base = self.base_mut().clone();
 
-        // for l in self.chunks_to_spawn.1.drain() {
-
         let mut loaded_chunks: Vec<ChunkPosition> = Default::default();
+        let mut spawned_sections = 0;
         loop {
-            if loaded_chunks.len() > MAX_CHUNKS_SPAWN_PER_FRAME {
+            if loaded_chunks.len() > MAX_CHUNKS_SPAWN_PER_FRAME || spawned_sections >= MAX_SECTIONS_SPAWN_PER_FRAME {
                 break;
             }
             // Take only one chunk
-            if let Ok(l) = self.chunks_to_spawn.1.try_recv() {
-                let chunk_column = l.read();
+            let Ok((chunk_lock, geometry)) = self.chunks_to_spawn.1.try_recv() else {
+                break;
+            };
 
-                let mut chunk_base = chunk_column.get_base();
-                base.add_child(&chunk_base);
-                chunk_column.set_loaded();
+            let chunk_column = chunk_lock.read();
+            let chunk_position = chunk_column.get_chunk_position().clone();
+
+            // Column could be unloaded while it was generated
+            let is_actual = self
+                .chunks
+                .get(&chunk_position)
+                .is_some_and(|c| Arc::ptr_eq(c, &chunk_lock));
+            if !is_actual {
+                chunk_column.get_base().free();
+                continue;
+            }
 
-                let mut c = chunk_base.bind_mut();
+            let geometry = match geometry {
+                Ok(g) => g,
+                Err(e) => {
+                    log::error!(target: "chunk_map", "&cChunk {} generation error: &4{}", chunk_position, e);
+                    continue;
+                }
+            };
+            spawned_sections += geometry.get_commit_size();
 
-                for section in c.sections.iter_mut() {
-                    if section.bind().is_collider_update_needed() {
-                        section.bind_mut().update_collider(physics);
-                    }
+            // Neighbours are meshed with the light before the column was lit
+            let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+            for key in geometry.light_changed.iter() {
+                if self.chunks.contains_key(&key.0) {
+                    chunks_to_update.insert(key.clone(), RemeshPriority::Edit);
                 }
+            }
+            drop(chunks_to_update);
+
+            // Data was replaced while it was generated; light could be computed from the old data
+            if geometry.generation != chunk_column.get_generation() {
+                *chunk_column.get_light_lock().write() = None;
+                let mut loading_queue = self.loading_queue.borrow_mut();
+                if !loading_queue.contains(&chunk_position) {
+                    loading_queue.push(chunk_position);
+                }
+                continue;
+            }
 
-                loaded_chunks.push(chunk_column.get_chunk_position().clone());
-            } else {
-                break;
+            chunk_column.spawn_sections(materials);
+            for section_geometry in geometry.sections {
+                apply_section_geometry(&chunk_column, section_geometry);
+            }
+
+            let data = chunk_column.get_data_lock().read();
+            for y in geometry.models_sections.iter() {
+                let mut chunk_section = chunk_column.get_chunk_section(y);
+                let mut cs = chunk_section.bind_mut();
+                let objects_container = cs.get_objects_container_mut();
+                objects_container
+                    .bind_mut()
+                    .setup(
+                        *y as u32,
+                        &chunk_position,
+                        data.get(*y).unwrap(),
+                        block_storage,
+                        physics,
+                        resource_storage,
+                    )
+                    .unwrap();
+            }
+            drop(data);
+
+            let mut chunk_base = chunk_column.get_base();
+            base.add_child(&chunk_base);
+            chunk_column.set_loaded();
+            self.culling_outdated.set(true);
+
+            let mut c = chunk_base.bind_mut();
+
+            for section in c.sections.iter_mut().flatten() {
+                if section.bind().is_collider_update_needed() {
+                    section.bind_mut().update_collider(physics);
+                }
             }
+
+            loaded_chunks.push(chunk_position);
         }
         loaded_chunks
     }
@@ -260,6 +409,8 @@ impl ChunkMap {
         }
 
         if unloaded {
+            self.culling_outdated.set(true);
+
             let mut recieved_chunks = self.recieved_chunks.borrow_mut();
             if let Some(i) = recieved_chunks.iter().position(|c| *c == chunk_position) {
                 recieved_chunks.swap_remove(i);
@@ -311,7 +462,7 @@ impl ChunkMap {
 
                     let mut cs = chunk_section.bind_mut();
                     let objects_container = cs.get_objects_container_mut();
-                    objects_container.bind_mut().remove(&block_position);
+                    objects_container.bind_mut().remove(&block_position, physics);
                 }
             }
         }
@@ -319,6 +470,7 @@ impl ChunkMap {
         chunk_column
             .write()
             .change_block_info(section, &block_position, new_block_info.clone());
+        self.light_edits.borrow_mut().push(position.clone());
 
         if let Some(new_block_info) = new_block_info {
             let Some(new_block_type) = block_storage.get(&new_block_info.get_id()) else {
@@ -349,7 +501,7 @@ impl ChunkMap {
                             &position,
                             model,
                             collider_type,
-                            Some(physics),
+                            physics,
                             resource_storage,
                             new_block_info.get_face(),
                         )
@@ -361,85 +513,413 @@ impl ChunkMap {
         Ok(())
     }
 
+    /// Data and light of the column and its neighbours
+    fn get_light_columns(&self, center: &ChunkPosition) -> LightColumns {
+        let mut columns: LightColumns = Default::default();
+        for x in -1..=1_i32 {
+            for z in -1..=1_i32 {
+                let chunk_position = center.clone() + ChunkPosition::new(x as i64, z as i64);
+                let Some(chunk_column) = self.chunks.get(&chunk_position) else {
+                    continue;
+                };
+                let c = chunk_column.read();
+                columns.push(((x, z), c.get_data_lock().clone(), c.get_light_lock().clone()));
+            }
+        }
+        columns
+    }
+
     /// Sent to the update queue
+    ///
+    /// Blocks on the borders are part of the neighbours meshes,
+    /// including diagonal ones because of the ambient occlusion
     fn send_to_update_chunk_mesh(&self, position: &BlockPosition) {
         let (section, block_position) = position.get_block_position();
 
-        self.chunks_to_update
-            .borrow_mut()
-            .insert((position.get_chunk_position(), section as usize));
-
-        if block_position.y == 0 && section > 0 {
-            self.chunks_to_update
-                .borrow_mut()
-                .insert((position.get_chunk_position(), section as usize - 1));
-        }
+        let get_offsets = |value: u8| -> Vec<i64> {
+            let mut offsets = vec![0];
+            if value == 0 {
+                offsets.push(-1);
+            }
+            if value == CHUNK_SIZE - 1 {
+                offsets.push(1);
+            }
+            offsets
+        };
 
-        if block_position.y == CHUNK_SIZE - 1 && section < VERTICAL_SECTIONS as u32 - 1 {
-            self.chunks_to_update
-                .borrow_mut()
-                .insert((position.get_chunk_position(), section as usize + 1));
+        let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+        for x in get_offsets(block_position.x) {
+            for z in get_offsets(block_position.z) {
+                let chunk_position = position.get_chunk_position() + ChunkPosition::new(x, z);
+                if (x != 0 || z != 0) && self.get_chunk(&chunk_position).is_none() {
+                    continue;
+                }
+                for y in get_offsets(block_position.y) {
+                    let section_y = section as i64 + y;
+                    if section_y < 0 || section_y >= VERTICAL_SECTIONS as i64 {
+                        continue;
+                    }
+                    chunks_to_update.insert((chunk_position, section_y as usize), RemeshPriority::Edit);
+                }
+            }
         }
+    }
 
-        let x = position.get_chunk_position() + ChunkPosition::new(-1, 0);
-        if block_position.x == 0 && self.get_chunk(&x).is_some() {
-            self.chunks_to_update.borrow_mut().insert((x, section as usize));
+    /// Rebuilds meshes of all loaded chunks
+    pub fn update_all_chunks_geometry(&self) {
+        let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+        for (chunk_position, chunk_column) in self.chunks.iter() {
+            if !chunk_column.read().is_loaded() {
+                continue;
+            }
+            for y in 0..VERTICAL_SECTIONS {
+                chunks_to_update.insert((chunk_position.clone(), y), RemeshPriority::Background);
+            }
         }
+    }
 
-        let x = position.get_chunk_position() + ChunkPosition::new(1, 0);
-        if block_position.x == CHUNK_SIZE - 1 && self.get_chunk(&x).is_some() {
-            self.chunks_to_update.borrow_mut().insert((x, section as usize));
+    /// Puts sections of columns with the changed level of detail to the update queue
+    pub fn update_chunks_lod(&self, center: &ChunkPosition, mesh_settings: &ChunkMeshSettings) {
+        let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+        for (chunk_position, chunk_column) in self.chunks.iter() {
+            let c = chunk_column.read();
+            let lod = ChunkLod::from_distance(chunk_position.get_distance(center), mesh_settings);
+            if !c.set_lod(lod) || !c.is_loaded() {
+                continue;
+            }
+            for y in 0..VERTICAL_SECTIONS {
+                chunks_to_update.insert((chunk_position.clone(), y), RemeshPriority::Background);
+            }
         }
+    }
 
-        let z = position.get_chunk_position() + ChunkPosition::new(0, -1);
-        if block_position.z == 0 && self.get_chunk(&z).is_some() {
-            self.chunks_to_update.borrow_mut().insert((z, section as usize));
+    /// Sections around the block edited by the player are remeshed first
+    pub fn prioritize_player_edit(&self, position: &BlockPosition) {
+        let (section, _block_position) = position.get_block_position();
+        let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+        for x in -1..=1_i64 {
+            for z in -1..=1_i64 {
+                let chunk_position = position.get_chunk_position() + ChunkPosition::new(x, z);
+                for y in -1..=1_i64 {
+                    let section_y = section as i64 + y;
+                    if section_y < 0 || section_y >= VERTICAL_SECTIONS as i64 {
+                        continue;
+                    }
+                    chunks_to_update.raise(&(chunk_position, section_y as usize), RemeshPriority::PlayerEdit);
+                }
+            }
         }
+    }
 
-        let z = position.get_chunk_position() + ChunkPosition::new(0, 1);
-        if block_position.z == CHUNK_SIZE - 1 && self.get_chunk(&z).is_some() {
-            self.chunks_to_update.borrow_mut().insert((z, section as usize));
-        }
+    pub fn get_remesh_queue_len(&self) -> usize {
+        self.chunks_to_update.borrow().len()
     }
 
     /// Every frame job to update edited chunks
+    ///
+    /// Sections are remeshed on the workers by batches;
+    /// the batch is applied when all of its sections are ready.
+    /// Light of the edited blocks is updated on the workers before the next batch.
     pub fn update_chunks_geometry(
         &self,
         physics: &PhysicsProxy,
-        block_storage: &BlockStorage,
-        texture_mapper: &TextureMapper,
+        texture_mapper: TextureMapperType,
+        block_storage: BlockStorageType,
+        mesh_settings: &ChunkMeshSettingsType,
+        camera_position: Option<Vector3>,
     ) {
-        self.chunks_to_update.borrow_mut().retain(|(chunk_position, y)| {
-            let chunks_near = NearChunksData::new(&self.chunks, &chunk_position);
+        let mut remesh_batch = self.remesh_batch.borrow_mut();
+        if let Some(batch) = remesh_batch.as_mut() {
+            for result in self.remesh_results.1.try_iter() {
+                batch.push(result);
+            }
+            if !batch.is_complete() {
+                return;
+            }
+            let batch = remesh_batch.take().unwrap();
+            self.apply_remesh_batch(batch, physics);
+        }
 
-            // Load only if all chunks around are loaded
-            if !chunks_near.is_full() {
-                return true;
+        // Sections of the edits are remeshed with the updated light
+        if self.light_update.get() {
+            let Ok(changed_sections) = self.light_results.1.try_recv() else {
+                return;
+            };
+            self.light_update.set(false);
+
+            let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+            for (chunk_position, section) in changed_sections {
+                let is_loaded = self.chunks.get(&chunk_position).is_some_and(|c| c.read().is_loaded());
+                if is_loaded {
+                    chunks_to_update.insert((chunk_position, section), RemeshPriority::Edit);
+                }
             }
+        }
 
-            let Some(chunk_column) = self.get_chunk(&chunk_position) else {
-                // Remove if chunk is not existing for some reason
-                return false;
+        let light_edits = std::mem::take(&mut *self.light_edits.borrow_mut());
+        if !light_edits.is_empty() {
+            let edits = light_edits
+                .into_iter()
+                .map(|position| {
+                    let light_columns = self.get_light_columns(&position.get_chunk_position());
+                    (position, light_columns)
+                })
+                .collect();
+            update_edits_light(edits, self.light_results.0.clone(), block_storage.clone());
+            self.light_update.set(true);
+            return;
+        }
+
+        let sections = {
+            let mut chunks_to_update = self.chunks_to_update.borrow_mut();
+
+            // Remove if chunk is not existing for some reason
+            chunks_to_update.retain(|(chunk_position, _y)| self.chunks.contains_key(chunk_position));
+
+            chunks_to_update.take_batch(camera_position, MAX_REMESH_BATCH_SIZE, |(chunk_position, _y)| {
+                let is_loaded = self.chunks.get(chunk_position).is_some_and(|c| c.read().is_loaded());
+                // Load only if all chunks around are loaded
+                is_loaded && NearChunksData::new(&self.chunks, chunk_position).is_full()
+            })
+        };
+        if sections.is_empty() {
+            return;
+        }
+
+        for (chunk_position, y) in sections.iter() {
+            let chunk_column = self.get_chunk(chunk_position).unwrap();
+            generate_section(
+                chunk_column,
+                *y,
+                NearChunksData::new(&self.chunks, chunk_position),
+                self.get_light_columns(chunk_position),
+                self.remesh_results.0.clone(),
+                texture_mapper.clone(),
+                block_storage.clone(),
+                mesh_settings.clone(),
+            );
+        }
+        *remesh_batch = Some(RemeshBatch::create(sections.len()));
+    }
+
+    fn apply_remesh_batch(&self, batch: RemeshBatch, physics: &PhysicsProxy) {
+        let _span = crate::span!("chunk_map.apply_remesh_batch");
+
+        for (chunk_column, geometry) in batch.take_results() {
+            let geometry = match geometry {
+                Ok(g) => g,
+                Err(e) => {
+                    log::error!(target: "chunk_map", "&cSection remesh error: &4{}", e);
+                    continue;
+                }
             };
 
             let c = chunk_column.read();
 
-            let data = c.get_data_lock().clone();
+            // Column could be unloaded while it was remeshed
+            let is_actual = self
+                .chunks
+                .get(c.get_chunk_position())
+                .is_some_and(|chunk| Arc::ptr_eq(chunk, &chunk_column));
+            if !is_actual {
+                continue;
+            }
 
-            let (bordered_chunk_data, _mesh_count) =
-                format_chunk_data_with_boundaries(Some(&chunks_near), &data, &block_storage, y.clone()).unwrap();
+            // Visibility inside the section could change
+            self.culling_outdated.set(true);
+            if let Some(mut chunk_section) = apply_section_geometry(&c, geometry) {
+                if chunk_section.bind().is_collider_update_needed() {
+                    chunk_section.bind_mut().update_collider(physics);
+                }
+            }
+        }
+    }
 
-            let mut chunk_section = c.get_chunk_section(y);
-            generate_chunk_geometry(
-                &mut chunk_section,
-                &texture_mapper,
-                &bordered_chunk_data,
-                &block_storage,
-            );
-            if chunk_section.bind().is_collider_update_needed() {
-                chunk_section.bind_mut().update_collider(physics);
+    /// Hides sections which can't be seen from the camera
+    ///
+    /// Flood fill from the camera section: the next section is visited only through
+    /// the faces connected inside the current one, never back towards the camera
+    /// and only if it's inside the frustum.
+    ///
+    /// Skipped while the camera section, the frustum and the sections are the same.
+    pub fn update_sections_culling(&self, camera_position: Vector3, frustum: &[Plane]) {
+        let _span = crate::span!("chunk_map.update_sections_culling");
+
+        let camera_chunk = camera_position.to_chunk_position();
+        let camera_y = (camera_position.y / CHUNK_SIZE as f32).floor() as i64;
+        let camera_section: SectionKey = (camera_chunk, camera_y.clamp(0, VERTICAL_SECTIONS as i64 - 1) as usize);
+
+        let mut culling = self.culling.borrow_mut();
+        let culling = &mut *culling;
+        let sections_changed = self.culling_outdated.get();
+        if !sections_changed
+            && culling.camera_section.as_ref() == Some(&camera_section)
+            && culling.frustum.as_slice() == frustum
+        {
+            return;
+        }
+        self.culling_outdated.set(false);
+        culling.frustum.clear();
+        culling.frustum.extend_from_slice(frustum);
+
+        let camera_loaded = self.chunks.get(&camera_section.0).is_some_and(|c| c.read().is_loaded());
+
+        let visible = &mut culling.next_visible;
+        let queue = &mut culling.queue;
+        visible.clear();
+        queue.clear();
+        if camera_loaded {
+            // Section, the face it was entered through and the directions of the path
+            visible.insert(camera_section.clone());
+            queue.push_back((camera_section.0.clone(), camera_section.1, None, 0));
+
+            while let Some((chunk_position, y, entered, directions)) = queue.pop_front() {
+                let section_visibility = match self.chunks.get(&chunk_position) {
+                    Some(c) => c.read().get_section_visibility(&y),
+                    None => continue,
+                };
+
+                for (face, offset) in SECTION_FACES.iter().enumerate() {
+                    if directions & (1 << opposite_face(face)) != 0 {
+                        continue;
+                    }
+                    if let Some(entered) = entered {
+                        if !section_visibility.is_connected(entered, face) {
+                            continue;
+                        }
+                    }
+
+                    let next_y = y as i32 + offset[1];
+                    if next_y < 0 || next_y >= VERTICAL_SECTIONS as i32 {
+                        continue;
+                    }
+                    let next_y = next_y as usize;
+                    let next_position = chunk_position.clone() + ChunkPosition::new(offset[0] as i64, offset[2] as i64);
+                    if visible.contains(&(next_position.clone(), next_y)) {
+                        continue;
+                    }
+                    let next_loaded = self.chunks.get(&next_position).is_some_and(|c| c.read().is_loaded());
+                    if !next_loaded || !is_section_in_frustum(&next_position, next_y, frustum) {
+                        continue;
+                    }
+
+                    visible.insert((next_position.clone(), next_y));
+                    queue.push_back((
+                        next_position,
+                        next_y,
+                        Some(opposite_face(face)),
+                        directions | (1 << face),
+                    ));
+                }
             }
+        }
+
+        if sections_changed || !camera_loaded || !culling.camera_loaded {
+            for (chunk_position, chunk_column) in self.chunks.iter() {
+                let c = chunk_column.read();
+                if !c.is_loaded() {
+                    continue;
+                }
+                for y in 0..VERTICAL_SECTIONS {
+                    // Without the camera section everything is shown
+                    let culled = camera_loaded && !visible.contains(&(chunk_position.clone(), y));
+                    if let Some(mut section) = c.get_created_section(&y) {
+                        section.bind_mut().set_culled(culled);
+                    }
+                }
+            }
+            self.sorting_outdated.set(true);
+        } else {
+            // Only sections which changed their visibility are updated
+            for key in culling.visible.difference(visible) {
+                self.set_section_culled(key, true);
+            }
+            for key in visible.difference(&culling.visible) {
+                self.set_section_culled(key, false);
+                self.sorting_outdated.set(true);
+            }
+        }
+
+        std::mem::swap(&mut culling.visible, &mut culling.next_visible);
+        culling.camera_section = Some(camera_section);
+        culling.camera_loaded = camera_loaded;
+    }
+
+    fn set_section_culled(&self, (chunk_position, y): &SectionKey, culled: bool) {
+        let Some(chunk_column) = self.chunks.get(chunk_position) else {
+            return;
+        };
+        if let Some(mut section) = chunk_column.read().get_created_section(y) {
+            section.bind_mut().set_culled(culled);
+        }
+    }
+
+    /// Sorts translucent faces of the visible sections; the nearest to the camera are first
+    ///
+    /// Skipped until the camera moves farther than `RESORT_DISTANCE` or new sections are shown.
+    pub fn update_translucent_sorting(&self, camera_position: Vector3) {
+        let _span = crate::span!("chunk_map.update_translucent_sorting");
+
+        let camera_moved = match self.sorting_camera.get() {
+            Some(sorted_from) => sorted_from.distance_squared_to(camera_position) > RESORT_DISTANCE * RESORT_DISTANCE,
+            None => true,
+        };
+        if !camera_moved && !self.sorting_outdated.get() {
+            return;
+        }
+
+        let mut sections: Vec<(f32, Gd<ChunkSection>)> = Default::default();
+        for chunk_column in self.chunks.values() {
+            let c = chunk_column.read();
+            if !c.is_loaded() {
+                continue;
+            }
+            for y in 0..VERTICAL_SECTIONS {
+                let Some(section) = c.get_created_section(&y) else {
+                    continue;
+                };
+                let s = section.bind();
+                if s.is_culled() || !s.is_resort_needed(camera_position) {
+                    continue;
+                }
+                let half = CHUNK_SIZE as f32 / 2.0;
+                let center = s.get_section_position() + Vector3::new(half, half, half);
+                let distance = center.distance_squared_to(camera_position);
+                drop(s);
+                sections.push((distance, section));
+            }
+        }
+        sections.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        // The rest are sorted in the next frames
+        self.sorting_outdated
+            .set(sections.len() > MAX_TRANSLUCENT_SORTS_PER_FRAME);
+        self.sorting_camera.set(Some(camera_position));
+
+        for (_distance, mut section) in sections.into_iter().take(MAX_TRANSLUCENT_SORTS_PER_FRAME) {
+            section.bind_mut().sort_translucent(camera_position);
+        }
+    }
+}
+
+/// Frustum planes point outside; the box is outside if its nearest corner is in front of any plane
+fn is_section_in_frustum(chunk_position: &ChunkPosition, y: usize, frustum: &[Plane]) -> bool {
+    let size = CHUNK_SIZE as f32;
+    let min = Vector3::new(
+        chunk_position.x as f32 * size,
+        GodotPositionConverter::get_chunk_y_local(y as u8),
+        chunk_position.z as f32 * size,
+    );
+    let max = min + Vector3::new(size, size, size);
+    for plane in frustum.iter() {
+        let nearest = Vector3::new(
+            if plane.normal.x > 0.0 { min.x } else { max.x },
+            if plane.normal.y > 0.0 { min.y } else { max.y },
+            if plane.normal.z > 0.0 { min.z } else { max.z },
+        );
+        if plane.distance_to(nearest) > 0.0 {
             return false;
-        });
+        }
     }
+    true
 }
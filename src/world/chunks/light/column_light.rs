@@ -0,0 +1,69 @@
+use crate::world::chunks::chunk_section::ChunkBordersShape;
+use common::{CHUNK_SIZE, VERTICAL_SECTIONS};
+use ndshape::ConstShape;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Height of the column in blocks
+pub const COLUMN_HEIGHT: i32 = VERTICAL_SECTIONS as i32 * CHUNK_SIZE as i32;
+
+/// None until the light of the column is computed
+pub type ColumnLightLockType = Arc<RwLock<Option<ColumnLight>>>;
+
+/// Packed light of the section with one block boundary, like `ChunkColliderDataBordered`
+pub type ChunkLightBordered = [u8; ChunkBordersShape::SIZE as usize];
+
+/// Sky and block light are packed in one byte: sky in the high nibble
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightChannel {
+    Sky,
+    Block,
+}
+
+impl LightChannel {
+    pub fn get(&self, packed: u8) -> u8 {
+        match self {
+            LightChannel::Sky => packed >> 4,
+            LightChannel::Block => packed & 0x0F,
+        }
+    }
+
+    pub fn set(&self, packed: u8, value: u8) -> u8 {
+        match self {
+            LightChannel::Sky => (packed & 0x0F) | (value << 4),
+            LightChannel::Block => (packed & 0xF0) | value,
+        }
+    }
+}
+
+pub fn pack_light(sky: u8, block: u8) -> u8 {
+    (sky << 4) | block
+}
+
+/// Light levels of all blocks of the column
+pub struct ColumnLight {
+    light: Vec<u8>,
+}
+
+impl ColumnLight {
+    pub fn create() -> Self {
+        let size = CHUNK_SIZE as usize * CHUNK_SIZE as usize * COLUMN_HEIGHT as usize;
+        Self { light: vec![0; size] }
+    }
+
+    fn index(x: i32, y: i32, z: i32) -> usize {
+        let size = CHUNK_SIZE as i32;
+        ((y * size + z) * size + x) as usize
+    }
+
+    /// Position is local to the column; y is from the bottom of the column
+    pub fn get(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.light[Self::index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, z: i32, packed: u8) {
+        self.light[Self::index(x, y, z)] = packed;
+    }
+}
@@ -0,0 +1,617 @@
+use super::column_light::{
+    pack_light, ChunkLightBordered, ColumnLight, ColumnLightLockType, LightChannel, COLUMN_HEIGHT, MAX_LIGHT,
+};
+use crate::world::{
+    block_storage::BlockStorage,
+    chunks::{chunk_column::ColumnDataLockType, chunk_section::ChunkBordersShape},
+};
+use ahash::AHashSet;
+use common::{
+    chunks::{
+        block_position::ChunkBlockPosition,
+        chunk_data::{BlockDataInfo, ChunkData},
+    },
+    CHUNK_SIZE, VERTICAL_SECTIONS,
+};
+use ndshape::ConstShape;
+use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
+use std::collections::VecDeque;
+
+/// Position relative to the center column; y is from the bottom of the column
+pub type LightPosition = [i32; 3];
+
+const DIRECTIONS: [LightPosition; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+const DOWN: usize = 3;
+
+pub trait LightVolume {
+    /// Outside of the volume everything is opaque
+    fn is_opaque(&self, pos: LightPosition) -> bool;
+
+    fn get_emission(&self, pos: LightPosition) -> u8;
+
+    /// Packed light; None if the position is outside of the volume
+    fn get_light(&self, pos: LightPosition) -> Option<u8>;
+
+    fn set_light(&mut self, pos: LightPosition, packed: u8);
+}
+
+fn offset(pos: LightPosition, direction: &LightPosition) -> LightPosition {
+    [pos[0] + direction[0], pos[1] + direction[1], pos[2] + direction[2]]
+}
+
+/// Light level which comes to the neighbour
+fn get_spread_light(channel: LightChannel, level: u8, direction: usize) -> u8 {
+    // Sky light goes straight down without loss
+    if channel == LightChannel::Sky && direction == DOWN && level == MAX_LIGHT {
+        return MAX_LIGHT;
+    }
+    level.saturating_sub(1)
+}
+
+/// Flood fill from the queued positions
+pub fn propagate_light(volume: &mut impl LightVolume, channel: LightChannel, queue: &mut VecDeque<LightPosition>) {
+    while let Some(pos) = queue.pop_front() {
+        let Some(packed) = volume.get_light(pos) else {
+            continue;
+        };
+        let level = channel.get(packed);
+        for (direction, d) in DIRECTIONS.iter().enumerate() {
+            let spread = get_spread_light(channel, level, direction);
+            if spread == 0 {
+                continue;
+            }
+
+            let neighbour = offset(pos, d);
+            if volume.is_opaque(neighbour) {
+                continue;
+            }
+            let Some(neighbour_packed) = volume.get_light(neighbour) else {
+                continue;
+            };
+            if channel.get(neighbour_packed) >= spread {
+                continue;
+            }
+            volume.set_light(neighbour, channel.set(neighbour_packed, spread));
+            queue.push_back(neighbour);
+        }
+    }
+}
+
+/// Removes the light that came from the queued positions
+///
+/// Returns positions lit by other sources, they must be propagated again
+fn remove_light(
+    volume: &mut impl LightVolume,
+    channel: LightChannel,
+    queue: &mut VecDeque<(LightPosition, u8)>,
+) -> VecDeque<LightPosition> {
+    let mut relight: VecDeque<LightPosition> = Default::default();
+    while let Some((pos, level)) = queue.pop_front() {
+        for (direction, d) in DIRECTIONS.iter().enumerate() {
+            let neighbour = offset(pos, d);
+            let Some(neighbour_packed) = volume.get_light(neighbour) else {
+                continue;
+            };
+            let neighbour_level = channel.get(neighbour_packed);
+            if neighbour_level == 0 {
+                continue;
+            }
+
+            let lit_by_pos = neighbour_level < level
+                || (channel == LightChannel::Sky && direction == DOWN && neighbour_level == MAX_LIGHT);
+            if !lit_by_pos {
+                relight.push_back(neighbour);
+                continue;
+            }
+
+            volume.set_light(neighbour, channel.set(neighbour_packed, 0));
+            queue.push_back((neighbour, neighbour_level));
+
+            // Light sources stay lit
+            if channel == LightChannel::Block {
+                let emission = volume.get_emission(neighbour);
+                if emission > 0 {
+                    volume.set_light(neighbour, channel.set(neighbour_packed, emission));
+                    relight.push_back(neighbour);
+                }
+            }
+        }
+    }
+    relight
+}
+
+/// Updates the light around the block after it was changed in the volume
+pub fn update_light_on_edit(volume: &mut impl LightVolume, pos: LightPosition) {
+    for channel in [LightChannel::Sky, LightChannel::Block] {
+        let Some(packed) = volume.get_light(pos) else {
+            return;
+        };
+
+        let mut removal: VecDeque<(LightPosition, u8)> = Default::default();
+        let old_level = channel.get(packed);
+        if old_level > 0 {
+            volume.set_light(pos, channel.set(packed, 0));
+            removal.push_back((pos, old_level));
+        }
+        let mut queue = remove_light(volume, channel, &mut removal);
+
+        if channel == LightChannel::Block {
+            let emission = volume.get_emission(pos);
+            if emission > 0 {
+                let packed = volume.get_light(pos).unwrap_or_default();
+                volume.set_light(pos, channel.set(packed, emission));
+                queue.push_back(pos);
+            }
+        }
+
+        // Removed block lets the light of neighbours in
+        if !volume.is_opaque(pos) {
+            if channel == LightChannel::Sky && pos[1] == COLUMN_HEIGHT - 1 {
+                let packed = volume.get_light(pos).unwrap_or_default();
+                volume.set_light(pos, channel.set(packed, MAX_LIGHT));
+                queue.push_back(pos);
+            }
+            for d in DIRECTIONS.iter() {
+                let neighbour = offset(pos, d);
+                if volume.get_light(neighbour).is_some_and(|p| channel.get(p) > 0) {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        propagate_light(volume, channel, &mut queue);
+    }
+}
+
+/// Light of the section with the boundary for the mesher
+///
+/// Columns light is locked one by one, so the meshing doesn't wait for the light updates of the neighbours
+pub fn format_light_from_columns(columns: &LightColumns, y: usize) -> ChunkLightBordered {
+    let size = CHUNK_SIZE as i32;
+    let border = CHUNK_SIZE as u32 + 1;
+    let get_pos = |x: u32, b_y: u32, z: u32| -> LightPosition {
+        [x as i32 - 1, (y * CHUNK_SIZE as usize) as i32 + b_y as i32 - 1, z as i32 - 1]
+    };
+
+    let mut result = [0; ChunkBordersShape::SIZE as usize];
+    let mut filled = [false; ChunkBordersShape::SIZE as usize];
+    for (offset, _data, light) in columns.iter() {
+        let light = light.read();
+        let Some(light) = light.as_ref() else {
+            continue;
+        };
+        for x in 0_u32..=border {
+            for b_y in 0_u32..=border {
+                for z in 0_u32..=border {
+                    let pos = get_pos(x, b_y, z);
+                    if pos[1] < 0 || pos[1] >= COLUMN_HEIGHT {
+                        continue;
+                    }
+                    if (pos[0].div_euclid(size), pos[2].div_euclid(size)) != *offset {
+                        continue;
+                    }
+                    let i = ChunkBordersShape::linearize([x, b_y, z]) as usize;
+                    result[i] = light.get(pos[0].rem_euclid(size), pos[1], pos[2].rem_euclid(size));
+                    filled[i] = true;
+                }
+            }
+        }
+    }
+
+    // Outside of the column height or the neighbour light is not computed yet
+    let center = columns.iter().find(|(offset, _, _)| *offset == (0, 0));
+    let center_light = center.map(|(_, _, light)| light.read());
+    for x in 0_u32..=border {
+        for b_y in 0_u32..=border {
+            for z in 0_u32..=border {
+                let i = ChunkBordersShape::linearize([x, b_y, z]) as usize;
+                if filled[i] {
+                    continue;
+                }
+                let pos = get_pos(x, b_y, z);
+                result[i] = if pos[1] >= COLUMN_HEIGHT {
+                    pack_light(MAX_LIGHT, 0)
+                } else if pos[1] < 0 {
+                    0
+                } else {
+                    let max = size - 1;
+                    match center_light.as_ref().and_then(|l| l.as_ref()) {
+                        Some(light) => light.get(pos[0].clamp(0, max), pos[1], pos[2].clamp(0, max)),
+                        None => pack_light(MAX_LIGHT, 0),
+                    }
+                };
+            }
+        }
+    }
+    result
+}
+
+/// Fills the light of the center column; the column light must be empty
+///
+/// Light of the neighbours comes in through the borders
+/// and the light of the column spreads out to the neighbours which already have the light.
+pub fn fill_column_light(volume: &mut impl LightVolume) {
+    let size = CHUNK_SIZE as i32;
+    let mut sky_queue: VecDeque<LightPosition> = Default::default();
+    let mut block_queue: VecDeque<LightPosition> = Default::default();
+
+    for x in 0..size {
+        for z in 0..size {
+            let top = [x, COLUMN_HEIGHT - 1, z];
+            if !volume.is_opaque(top) {
+                volume.set_light(top, pack_light(MAX_LIGHT, 0));
+                sky_queue.push_back(top);
+            }
+            for y in 0..COLUMN_HEIGHT {
+                let pos = [x, y, z];
+                let emission = volume.get_emission(pos);
+                if emission > 0 {
+                    let packed = volume.get_light(pos).unwrap_or_default();
+                    volume.set_light(pos, LightChannel::Block.set(packed, emission));
+                    block_queue.push_back(pos);
+                }
+            }
+        }
+    }
+
+    for y in 0..COLUMN_HEIGHT {
+        for i in 0..size {
+            for pos in [[-1, y, i], [size, y, i], [i, y, -1], [i, y, size]] {
+                let Some(packed) = volume.get_light(pos) else {
+                    continue;
+                };
+                if LightChannel::Sky.get(packed) > 0 {
+                    sky_queue.push_back(pos);
+                }
+                if LightChannel::Block.get(packed) > 0 {
+                    block_queue.push_back(pos);
+                }
+            }
+        }
+    }
+
+    propagate_light(volume, LightChannel::Sky, &mut sky_queue);
+    propagate_light(volume, LightChannel::Block, &mut block_queue);
+}
+
+/// Computes the light of the center column if it's not computed yet
+///
+/// Returns sections of the neighbours whose light is changed by the column
+pub fn compute_column_light(columns: &LightColumns, block_storage: &BlockStorage) -> AHashSet<((i32, i32), usize)> {
+    let mut volume = ColumnsLightVolume::create(columns, block_storage);
+    if volume.has_light((0, 0)) {
+        return Default::default();
+    }
+    volume.reset_light((0, 0));
+    fill_column_light(&mut volume);
+
+    let mut changed_sections = volume.take_changed_sections();
+    changed_sections.retain(|(offset, _)| *offset != (0, 0));
+    changed_sections
+}
+
+/// Updates the light around the edited block of the center column
+///
+/// Returns sections whose light is changed
+pub fn update_column_light_on_edit(
+    columns: &LightColumns,
+    block_storage: &BlockStorage,
+    pos: LightPosition,
+) -> AHashSet<((i32, i32), usize)> {
+    let mut volume = ColumnsLightVolume::create(columns, block_storage);
+    // Light will be computed with the new data
+    if !volume.has_light((0, 0)) {
+        return Default::default();
+    }
+    update_light_on_edit(&mut volume, pos);
+    volume.take_changed_sections()
+}
+
+/// Column data and light locks by the offset from the center column
+pub type LightColumns = Vec<((i32, i32), ColumnDataLockType, ColumnLightLockType)>;
+
+/// Stored light of the column and its neighbours
+///
+/// Used to update the light after block edits and to mesh sections on the main thread
+pub struct ColumnsLightVolume<'a> {
+    block_storage: &'a BlockStorage,
+    columns: Vec<(
+        (i32, i32),
+        RwLockReadGuard<'a, ChunkData>,
+        RwLockWriteGuard<'a, Option<ColumnLight>>,
+    )>,
+
+    // Sections whose meshes contain changed light: offset of the column and section index
+    changed_sections: AHashSet<((i32, i32), usize)>,
+}
+
+impl<'a> ColumnsLightVolume<'a> {
+    pub fn create(columns: &'a LightColumns, block_storage: &'a BlockStorage) -> Self {
+        Self {
+            block_storage,
+            columns: columns
+                .iter()
+                .map(|(offset, data, light)| (*offset, data.read(), light.write()))
+                .collect(),
+            changed_sections: Default::default(),
+        }
+    }
+
+    pub fn has_light(&self, offset: (i32, i32)) -> bool {
+        self.columns.iter().any(|(o, _, light)| *o == offset && light.is_some())
+    }
+
+    /// Creates the empty light of the column
+    fn reset_light(&mut self, offset: (i32, i32)) {
+        if let Some((_, _, light)) = self.columns.iter_mut().find(|(o, _, _)| *o == offset) {
+            **light = Some(ColumnLight::create());
+        }
+    }
+
+    /// Returns index of the column and local position
+    fn locate(&self, pos: LightPosition) -> Option<(usize, LightPosition)> {
+        if pos[1] < 0 || pos[1] >= COLUMN_HEIGHT {
+            return None;
+        }
+        let size = CHUNK_SIZE as i32;
+        let offset = (pos[0].div_euclid(size), pos[2].div_euclid(size));
+        let i = self.columns.iter().position(|(o, _, _)| *o == offset)?;
+        Some((i, [pos[0].rem_euclid(size), pos[1], pos[2].rem_euclid(size)]))
+    }
+
+    fn get_block_info(&self, pos: LightPosition) -> Option<BlockDataInfo> {
+        let (i, local) = self.locate(pos)?;
+        let size = CHUNK_SIZE as i32;
+        let section = self.columns[i].1.get((local[1] / size) as usize)?;
+        let chunk_block = ChunkBlockPosition::new(local[0] as u8, (local[1] % size) as u8, local[2] as u8);
+        section.get(&chunk_block).cloned()
+    }
+
+    fn mark_changed(&mut self, pos: LightPosition) {
+        // Light is a part of the bordered data of the neighbour sections
+        let size = CHUNK_SIZE as i32;
+        let get_range = |v: i32| (v - 1).div_euclid(size)..=(v + 1).div_euclid(size);
+        for offset_x in get_range(pos[0]) {
+            for offset_z in get_range(pos[2]) {
+                for section in get_range(pos[1]) {
+                    if section < 0 || section >= VERTICAL_SECTIONS as i32 {
+                        continue;
+                    }
+                    self.changed_sections.insert(((offset_x, offset_z), section as usize));
+                }
+            }
+        }
+    }
+
+    pub fn take_changed_sections(&mut self) -> AHashSet<((i32, i32), usize)> {
+        std::mem::take(&mut self.changed_sections)
+    }
+}
+
+impl<'a> LightVolume for ColumnsLightVolume<'a> {
+    fn is_opaque(&self, pos: LightPosition) -> bool {
+        if self.locate(pos).is_none() {
+            return true;
+        }
+        match self.get_block_info(pos) {
+            Some(block_info) => self.block_storage.is_light_opaque(&block_info),
+            None => false,
+        }
+    }
+
+    fn get_emission(&self, pos: LightPosition) -> u8 {
+        match self.get_block_info(pos) {
+            Some(block_info) => self.block_storage.get_light_emission(&block_info),
+            None => 0,
+        }
+    }
+
+    fn get_light(&self, pos: LightPosition) -> Option<u8> {
+        let (i, local) = self.locate(pos)?;
+        let light = self.columns[i].2.as_ref()?;
+        Some(light.get(local[0], local[1], local[2]))
+    }
+
+    fn set_light(&mut self, pos: LightPosition, packed: u8) {
+        let Some((i, local)) = self.locate(pos) else {
+            return;
+        };
+        let Some(light) = self.columns[i].2.as_mut() else {
+            return;
+        };
+        light.set(local[0], local[1], local[2], packed);
+        self.mark_changed(pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two columns along x
+    const WIDTH: i32 = CHUNK_SIZE as i32 * 2;
+    const DEPTH: i32 = 4;
+
+    struct TestVolume {
+        opaque: Vec<bool>,
+        emission: Vec<u8>,
+        light: Vec<u8>,
+    }
+
+    impl TestVolume {
+        fn create() -> Self {
+            let size = (WIDTH * COLUMN_HEIGHT * DEPTH) as usize;
+            Self {
+                opaque: vec![false; size],
+                emission: vec![0; size],
+                light: vec![0; size],
+            }
+        }
+
+        fn index(pos: LightPosition) -> Option<usize> {
+            if pos[0] < 0 || pos[0] >= WIDTH || pos[1] < 0 || pos[1] >= COLUMN_HEIGHT || pos[2] < 0 || pos[2] >= DEPTH {
+                return None;
+            }
+            Some(((pos[1] * DEPTH + pos[2]) * WIDTH + pos[0]) as usize)
+        }
+
+        fn fill_sky(&mut self) {
+            let mut queue: VecDeque<LightPosition> = Default::default();
+            for x in 0..WIDTH {
+                for z in 0..DEPTH {
+                    let pos = [x, COLUMN_HEIGHT - 1, z];
+                    if self.is_opaque(pos) {
+                        continue;
+                    }
+                    self.set_light(pos, pack_light(MAX_LIGHT, 0));
+                    queue.push_back(pos);
+                }
+            }
+            propagate_light(self, LightChannel::Sky, &mut queue);
+        }
+
+        fn set_block(&mut self, pos: LightPosition, opaque: bool, emission: u8) {
+            let i = Self::index(pos).unwrap();
+            self.opaque[i] = opaque;
+            self.emission[i] = emission;
+            update_light_on_edit(self, pos);
+        }
+
+        fn get(&self, channel: LightChannel, pos: LightPosition) -> u8 {
+            channel.get(self.get_light(pos).unwrap())
+        }
+
+        /// Light of the first column as if it was not computed yet
+        fn clear_first_column(&mut self) {
+            for x in 0..CHUNK_SIZE as i32 {
+                for y in 0..COLUMN_HEIGHT {
+                    for z in 0..DEPTH {
+                        self.set_light([x, y, z], 0);
+                    }
+                }
+            }
+        }
+    }
+
+    impl LightVolume for TestVolume {
+        fn is_opaque(&self, pos: LightPosition) -> bool {
+            Self::index(pos).is_none_or(|i| self.opaque[i])
+        }
+
+        fn get_emission(&self, pos: LightPosition) -> u8 {
+            Self::index(pos).map_or(0, |i| self.emission[i])
+        }
+
+        fn get_light(&self, pos: LightPosition) -> Option<u8> {
+            Self::index(pos).map(|i| self.light[i])
+        }
+
+        fn set_light(&mut self, pos: LightPosition, packed: u8) {
+            if let Some(i) = Self::index(pos) {
+                self.light[i] = packed;
+            }
+        }
+    }
+
+    #[test]
+    fn sky_column_fill() {
+        let mut volume = TestVolume::create();
+        volume.opaque[TestVolume::index([2, 10, 1]).unwrap()] = true;
+        volume.fill_sky();
+
+        assert_eq!(volume.get(LightChannel::Sky, [5, COLUMN_HEIGHT - 1, 1]), MAX_LIGHT);
+        assert_eq!(volume.get(LightChannel::Sky, [5, 0, 1]), MAX_LIGHT);
+
+        // Under the block the light comes only from the sides
+        assert_eq!(volume.get(LightChannel::Sky, [2, 10, 1]), 0);
+        assert_eq!(volume.get(LightChannel::Sky, [2, 5, 1]), MAX_LIGHT - 1);
+    }
+
+    #[test]
+    fn torch_placement_and_removal() {
+        let mut volume = TestVolume::create();
+        let torch = [4, 20, 1];
+        volume.set_block(torch, false, 14);
+
+        assert_eq!(volume.get(LightChannel::Block, torch), 14);
+        assert_eq!(volume.get(LightChannel::Block, [5, 20, 1]), 13);
+        assert_eq!(volume.get(LightChannel::Block, [4, 23, 2]), 10);
+        assert_eq!(volume.get(LightChannel::Block, [4, 40, 1]), 0);
+
+        volume.set_block(torch, false, 0);
+        assert!(volume.light.iter().all(|l| LightChannel::Block.get(*l) == 0));
+    }
+
+    #[test]
+    fn opaque_placement_shadowing() {
+        let mut volume = TestVolume::create();
+        volume.fill_sky();
+
+        let block = [6, 30, 2];
+        volume.set_block(block, true, 0);
+
+        assert_eq!(volume.get(LightChannel::Sky, block), 0);
+        assert_eq!(volume.get(LightChannel::Sky, [6, 29, 2]), MAX_LIGHT - 1);
+        assert_eq!(volume.get(LightChannel::Sky, [6, 0, 2]), MAX_LIGHT - 1);
+        assert_eq!(volume.get(LightChannel::Sky, [6, 31, 2]), MAX_LIGHT);
+
+        // Removed block lets the sky straight down again
+        volume.set_block(block, false, 0);
+        assert_eq!(volume.get(LightChannel::Sky, [6, 0, 2]), MAX_LIGHT);
+    }
+
+    #[test]
+    fn cross_column_propagation() {
+        let mut volume = TestVolume::create();
+        let border = CHUNK_SIZE as i32 - 1;
+        volume.set_block([border, 20, 1], false, 14);
+
+        assert_eq!(volume.get(LightChannel::Block, [border + 1, 20, 1]), 13);
+        assert_eq!(volume.get(LightChannel::Block, [border + 4, 20, 1]), 10);
+
+        // Wall between the columns with the hole lower
+        let mut volume = TestVolume::create();
+        for y in 10..COLUMN_HEIGHT {
+            for z in 0..DEPTH {
+                volume.opaque[TestVolume::index([border + 1, y, z]).unwrap()] = true;
+            }
+        }
+        volume.set_block([border, 12, 1], false, 14);
+        // Around the wall under it: three blocks down, two across and three up
+        assert_eq!(volume.get(LightChannel::Block, [border + 2, 12, 1]), 14 - 8);
+    }
+
+    #[test]
+    fn column_fill_from_neighbour_light() {
+        let mut volume = TestVolume::create();
+        // Roof over the part of the first column
+        for x in 0..10 {
+            for z in 0..DEPTH {
+                volume.opaque[TestVolume::index([x, 30, z]).unwrap()] = true;
+            }
+        }
+        volume.fill_sky();
+        volume.set_block([20, 10, 1], false, 14);
+        let full_light = volume.light.clone();
+
+        volume.clear_first_column();
+        fill_column_light(&mut volume);
+        assert_eq!(volume.get(LightChannel::Block, [15, 10, 1]), 14 - 5);
+        assert_eq!(volume.get(LightChannel::Sky, [2, 20, 1]), MAX_LIGHT - 8);
+        assert!(volume.light == full_light);
+    }
+
+    #[test]
+    fn column_light_spreads_to_neighbours() {
+        let mut volume = TestVolume::create();
+        volume.fill_sky();
+        volume.clear_first_column();
+
+        let torch = [CHUNK_SIZE as i32 - 2, 10, 1];
+        volume.emission[TestVolume::index(torch).unwrap()] = 14;
+        fill_column_light(&mut volume);
+
+        assert_eq!(volume.get(LightChannel::Block, torch), 14);
+        assert_eq!(volume.get(LightChannel::Block, [CHUNK_SIZE as i32 + 1, 10, 1]), 14 - 3);
+        assert_eq!(volume.get(LightChannel::Sky, [3, 0, 1]), MAX_LIGHT);
+    }
+}
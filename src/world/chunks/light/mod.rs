@@ -0,0 +1,2 @@
+pub mod column_light;
+pub mod light_engine;
@@ -0,0 +1,69 @@
+use crate::world::chunks::chunk_section::{ChunkBordersShape, ChunkColliderDataBordered};
+use common::{blocks::voxel_visibility::VoxelVisibility, CHUNK_SIZE};
+use ndshape::ConstShape;
+
+use super::smooth_lighting::QuadLight;
+
+/// Vertex brightness by the ambient occlusion level; 3 is not occluded
+const AO_CURVE: [f32; 4] = [0.45, 0.65, 0.82, 1.0];
+
+pub type QuadAo = [u8; 4];
+
+pub const NO_OCCLUSION: QuadAo = [3; 4];
+
+pub fn get_ao_brightness(ao: u8) -> f32 {
+    AO_CURVE[ao as usize]
+}
+
+pub(crate) fn offset(a: [i32; 3], b: [i32; 3], scale: i32) -> [i32; 3] {
+    [a[0] + b[0] * scale, a[1] + b[1] * scale, a[2] + b[2] * scale]
+}
+
+pub(crate) fn is_occluder(data: &ChunkColliderDataBordered, pos: [i32; 3]) -> bool {
+    let max = CHUNK_SIZE as i32 + 1;
+    if pos.iter().any(|p| *p < 0 || *p > max) {
+        return false;
+    }
+    let i = ChunkBordersShape::linearize([pos[0] as u32, pos[1] as u32, pos[2] as u32]);
+    *data[i as usize].get_voxel_visibility() == VoxelVisibility::Opaque
+}
+
+/// Two sides and the corner around the vertex in the layer in front of the face
+fn get_vertex_ao(data: &ChunkColliderDataBordered, front: [i32; 3], side_u: [i32; 3], side_v: [i32; 3]) -> u8 {
+    let side_1 = is_occluder(data, offset(front, side_u, 1));
+    let side_2 = is_occluder(data, offset(front, side_v, 1));
+    if side_1 && side_2 {
+        return 0;
+    }
+    let corner = is_occluder(data, offset(offset(front, side_u, 1), side_v, 1));
+    3 - (side_1 as u8 + side_2 as u8 + corner as u8)
+}
+
+/// Ambient occlusion of the single block face
+///
+/// `voxel` is the bordered position of the block, `u` and `v` are the face axes;
+/// corners are in the `quad_corners` order: min-min, max-min, min-max, max-max
+pub fn get_face_ao(
+    data: &ChunkColliderDataBordered,
+    voxel: [i32; 3],
+    normal: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+) -> QuadAo {
+    let front = offset(voxel, normal, 1);
+    let (neg_u, neg_v) = (offset([0; 3], u, -1), offset([0; 3], v, -1));
+    [
+        get_vertex_ao(data, front, neg_u, neg_v),
+        get_vertex_ao(data, front, u, neg_v),
+        get_vertex_ao(data, front, neg_u, v),
+        get_vertex_ao(data, front, u, v),
+    ]
+}
+
+/// Triangles diagonal must connect the brighter corners,
+/// otherwise the occlusion is interpolated unevenly
+pub fn need_flip(ao: &QuadAo, light: &QuadLight) -> bool {
+    // Occlusion is more noticeable than the light difference
+    let weight = |i: usize| ao[i] as u16 * 64 + light[i][0].max(light[i][1]) as u16;
+    weight(0) + weight(3) > weight(1) + weight(2)
+}
@@ -0,0 +1,313 @@
+use crate::{
+    utils::textures::texture_mapper::{TextureChoice, TextureMapper},
+    world::{
+        block_render::BlockShape,
+        block_storage::BlockStorage,
+        chunks::{
+            chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
+            light::column_light::ChunkLightBordered,
+        },
+    },
+};
+use common::{
+    blocks::{block_type::BlockType, voxel_visibility::VoxelVisibility},
+    chunks::chunk_data::BlockDataInfo,
+    utils::block_mesh::RIGHT_HANDED_Y_UP_CONFIG,
+    CHUNK_SIZE,
+};
+use godot::prelude::{Vector2, Vector3};
+use ndshape::ConstShape;
+
+use super::{
+    ambient_occlusion::{is_occluder, offset, NO_OCCLUSION},
+    face_textures::{get_color_variant, get_world_position},
+    mesh_generator::{get_layer_uv, MeshData},
+    smooth_lighting::{get_block_light, QuadLight, FULL_LIGHT},
+};
+
+/// Normal, u and v axes of the faces in the `RIGHT_HANDED_Y_UP_CONFIG` order
+const FACE_AXES: [(usize, usize, usize); 3] = [(0, 2, 1), (1, 2, 0), (2, 0, 1)];
+
+/// +x, +z, -x, -z: the side index is the number of the 90 degrees turns
+const HORIZONTAL_SIDES: [[i32; 3]; 4] = [[1, 0, 0], [0, 0, 1], [-1, 0, 0], [0, 0, -1]];
+
+const SLAB: ShapeBox = ShapeBox::new([0.0, 0.0, 0.0], [1.0, 0.5, 1.0]);
+const STAIRS_STEP: ShapeBox = ShapeBox::new([0.0, 0.5, 0.5], [1.0, 1.0, 1.0]);
+const FENCE_POST: ShapeBox = ShapeBox::new([0.375, 0.0, 0.375], [0.625, 1.0, 0.625]);
+const FENCE_RAILS: [ShapeBox; 2] = [
+    ShapeBox::new([0.625, 0.375, 0.4375], [1.0, 0.5625, 0.5625]),
+    ShapeBox::new([0.625, 0.75, 0.4375], [1.0, 0.9375, 0.5625]),
+];
+const PANE_POST: ShapeBox = ShapeBox::new([0.4375, 0.0, 0.4375], [0.5625, 1.0, 0.5625]);
+const PANE_ARM: ShapeBox = ShapeBox::new([0.5625, 0.0, 0.4375], [1.0, 1.0, 0.5625]);
+
+/// Box of the shape in the block coordinates from 0 to 1
+#[derive(Clone, Copy)]
+struct ShapeBox {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl ShapeBox {
+    const fn new(min: [f32; 3], max: [f32; 3]) -> Self {
+        Self { min, max }
+    }
+
+    /// Turns the box around the vertical axis of the block by 90 degrees steps
+    fn rotate_y(&self, steps: i32) -> Self {
+        let mut b = *self;
+        for _ in 0..steps {
+            // (x, z) -> (1 - z, x)
+            b = Self::new([1.0 - b.max[2], b.min[1], b.min[0]], [1.0 - b.min[2], b.max[1], b.max[0]]);
+        }
+        b
+    }
+}
+
+/// Geometry of the block shapes of the section
+#[derive(Default)]
+pub struct ShapesGeometry {
+    pub mesh: MeshData,
+    pub mesh_transparent: MeshData,
+
+    /// Faces of the solid shapes for the section collider
+    pub collider_quads: Vec<[Vector3; 4]>,
+}
+
+/// Builds the blocks which are not cubes one by one
+///
+/// Faces on the block border are culled by the opaque neighbours;
+/// the neighbour cubes always keep their faces, because shapes are empty for the greedy meshing.
+/// `origin` is the position of the section in the world
+pub fn generate_shapes_geometry(
+    texture_mapper: &TextureMapper,
+    chunk_collider_data: &ChunkColliderDataBordered,
+    chunk_light: Option<&ChunkLightBordered>,
+    block_storage: &BlockStorage,
+    origin: [i32; 3],
+) -> ShapesGeometry {
+    let mut geometry = ShapesGeometry::default();
+
+    let size = CHUNK_SIZE as u32;
+    for x in 1..=size {
+        for y in 1..=size {
+            for z in 1..=size {
+                let i = ChunkBordersShape::linearize([x, y, z]) as usize;
+                let Some(block_info) = chunk_collider_data[i].get_block_info().as_ref() else {
+                    continue;
+                };
+                let shape = block_storage.get_shape(block_info);
+                if shape == BlockShape::Cube {
+                    continue;
+                }
+                let block_type = block_storage
+                    .get(&block_info.get_id())
+                    .expect("GENERATE_SHAPES_GEOMETRY block type is not found");
+                let voxel = [x as i32, y as i32, z as i32];
+                let color_variant = get_color_variant(block_type, get_world_position(origin, voxel));
+                let light = match chunk_light {
+                    Some(chunk_light) => get_block_light(chunk_light, voxel),
+                    None => FULL_LIGHT,
+                };
+
+                // magic: Offset -1 because of chunk mesh one block boundary
+                let origin = Vector3::new(x as f32 - 1.0, y as f32 - 1.0, z as f32 - 1.0);
+
+                let mesh = match *block_type.get_voxel_visibility() == VoxelVisibility::Translucent {
+                    true => &mut geometry.mesh_transparent,
+                    false => &mut geometry.mesh,
+                };
+
+                if shape == BlockShape::Cross {
+                    push_cross(mesh, texture_mapper, block_type, color_variant, origin, light);
+                    continue;
+                }
+
+                let connections = get_connections(chunk_collider_data, block_storage, voxel, shape);
+                for shape_box in get_shape_boxes(shape, get_rotation_steps(block_info), connections) {
+                    push_box(
+                        mesh,
+                        &mut geometry.collider_quads,
+                        chunk_collider_data,
+                        texture_mapper,
+                        block_type,
+                        color_variant,
+                        voxel,
+                        origin,
+                        &shape_box,
+                        light,
+                    );
+                }
+            }
+        }
+    }
+    geometry
+}
+
+/// Same angle turns the block models around the vertical axis
+fn get_rotation_steps(block_info: &BlockDataInfo) -> i32 {
+    let Some(face) = block_info.get_face() else {
+        return 0;
+    };
+    ((face.get_rotation().pitch / 90.0).round() as i32).rem_euclid(4)
+}
+
+/// Fences and panes connect to the same shapes and to the opaque blocks
+fn get_connections(
+    data: &ChunkColliderDataBordered,
+    block_storage: &BlockStorage,
+    voxel: [i32; 3],
+    shape: BlockShape,
+) -> [bool; 4] {
+    HORIZONTAL_SIDES.map(|side| {
+        let pos = offset(voxel, side, 1);
+        if is_occluder(data, pos) {
+            return true;
+        }
+        let i = ChunkBordersShape::linearize([pos[0] as u32, pos[1] as u32, pos[2] as u32]);
+        data[i as usize]
+            .get_block_info()
+            .as_ref()
+            .is_some_and(|b| block_storage.get_shape(b) == shape)
+    })
+}
+
+fn get_shape_boxes(shape: BlockShape, rotation: i32, connections: [bool; 4]) -> Vec<ShapeBox> {
+    match shape {
+        BlockShape::Cube => vec![ShapeBox::new([0.0; 3], [1.0; 3])],
+        BlockShape::Slab => vec![SLAB],
+        BlockShape::Stairs => vec![SLAB, STAIRS_STEP.rotate_y(rotation)],
+        BlockShape::Cross => vec![],
+        BlockShape::Fence => {
+            let mut boxes = vec![FENCE_POST];
+            for (side, _) in connections.iter().enumerate().filter(|(_, c)| **c) {
+                boxes.extend(FENCE_RAILS.iter().map(|rail| rail.rotate_y(side as i32)));
+            }
+            boxes
+        }
+        BlockShape::Pane => {
+            // Single pane is a plate along the x axis
+            let connections = match connections.iter().any(|c| *c) {
+                true => connections,
+                false => [true, false, true, false],
+            };
+            let mut boxes = vec![PANE_POST];
+            for (side, _) in connections.iter().enumerate().filter(|(_, c)| **c) {
+                boxes.push(PANE_ARM.rotate_y(side as i32));
+            }
+            boxes
+        }
+    }
+}
+
+/// Corners of the box side inside the block in the `quad_corners` order
+/// and the part of the texture under them, so it isn't stretched
+pub(crate) fn get_box_face(min: [f32; 3], max: [f32; 3], side_index: usize) -> ([Vector3; 4], [Vector2; 4]) {
+    let (n, u, v) = FACE_AXES[side_index % 3];
+    let plane = match side_index >= 3 {
+        true => max[n],
+        false => min[n],
+    };
+
+    let mut points = [[0.0_f32; 3]; 4];
+    for (i, (pu, pv)) in [(min[u], min[v]), (max[u], min[v]), (min[u], max[v]), (max[u], max[v])]
+        .into_iter()
+        .enumerate()
+    {
+        points[i][n] = plane;
+        points[i][u] = pu;
+        points[i][v] = pv;
+    }
+
+    let tex_coords = points.map(|p| {
+        let tex_u = match side_index == 2 || side_index == 3 {
+            true => 1.0 - p[u],
+            false => p[u],
+        };
+        let tex_v = match n == 1 {
+            true => p[v],
+            false => 1.0 - p[v],
+        };
+        Vector2::new(tex_u, tex_v)
+    });
+    (points.map(|p| Vector3::new(p[0], p[1], p[2])), tex_coords)
+}
+
+fn push_box(
+    mesh: &mut MeshData,
+    collider_quads: &mut Vec<[Vector3; 4]>,
+    data: &ChunkColliderDataBordered,
+    texture_mapper: &TextureMapper,
+    block_type: &BlockType,
+    color_variant: usize,
+    voxel: [i32; 3],
+    origin: Vector3,
+    shape_box: &ShapeBox,
+    light: QuadLight,
+) {
+    for (side_index, face) in RIGHT_HANDED_Y_UP_CONFIG.faces.iter().enumerate() {
+        let n = FACE_AXES[side_index % 3].0;
+        let positive = side_index >= 3;
+        let plane = match positive {
+            true => shape_box.max[n],
+            false => shape_box.min[n],
+        };
+
+        let normal = face.signed_normal();
+        let on_border = (positive && plane >= 1.0) || (!positive && plane <= 0.0);
+        if on_border && is_occluder(data, offset(voxel, [normal.x, normal.y, normal.z], 1)) {
+            continue;
+        }
+
+        let (corners, tex_coords) = get_box_face(shape_box.min, shape_box.max, side_index);
+        let corners = corners.map(|c| origin + c);
+
+        collider_quads.push(corners);
+
+        let Some(layer) = texture_mapper.get_texture_layer(
+            block_type,
+            side_index as i8,
+            &TextureChoice {
+                color_variant,
+                ..Default::default()
+            },
+        ) else {
+            continue;
+        };
+        mesh.push_quad(face, corners, tex_coords, get_layer_uv(layer), NO_OCCLUSION, light);
+    }
+}
+
+fn push_cross(
+    mesh: &mut MeshData,
+    texture_mapper: &TextureMapper,
+    block_type: &BlockType,
+    color_variant: usize,
+    origin: Vector3,
+    light: QuadLight,
+) {
+    // Side texture
+    let choice = TextureChoice {
+        color_variant,
+        ..Default::default()
+    };
+    let Some(layer) = texture_mapper.get_texture_layer(block_type, 0, &choice) else {
+        return;
+    };
+    let layer_uv = get_layer_uv(layer);
+    let tex_coords = [
+        Vector2::new(0.0, 1.0),
+        Vector2::new(1.0, 1.0),
+        Vector2::new(0.0, 0.0),
+        Vector2::new(1.0, 0.0),
+    ];
+    for (start, end) in [([0.0, 0.0], [1.0, 1.0]), ([1.0, 0.0], [0.0, 1.0])] {
+        let corners = [
+            origin + Vector3::new(start[0], 0.0, start[1]),
+            origin + Vector3::new(end[0], 0.0, end[1]),
+            origin + Vector3::new(start[0], 1.0, start[1]),
+            origin + Vector3::new(end[0], 1.0, end[1]),
+        ];
+        mesh.push_double_sided_quad(corners, tex_coords, layer_uv, light);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    utils::textures::texture_mapper::{TextureChoice, TextureMapper},
+    world::{
+        block_storage::BlockStorage,
+        chunks::chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
+    },
+};
+use common::{
+    blocks::block_type::{BlockContent, BlockType},
+    chunks::chunk_data::BlockDataInfo,
+    CHUNK_SIZE,
+};
+use godot::prelude::Vector2;
+use ndshape::ConstShape;
+
+use super::ambient_occlusion::offset;
+
+/// Single block face of the merged quad
+pub struct FaceCell {
+    /// Bordered position of the block
+    pub voxel: [i32; 3],
+    pub u: [i32; 3],
+    pub v: [i32; 3],
+
+    /// Texture coordinates step along the u and v axes
+    pub tex_u: Vector2,
+    pub tex_v: Vector2,
+}
+
+/// Seeds of the hash, so the color and the texture variant are picked independently
+const COLOR_SEED: u32 = 0x68e3_1da4;
+const TEXTURE_SEED: u32 = 0xb529_7a4d;
+
+/// Same result for the block position in every section and on every client
+fn get_position_hash(position: [i32; 3], seed: u32) -> u32 {
+    let mut hash = (position[0] as u32).wrapping_mul(0x8da6_b343)
+        ^ (position[1] as u32).wrapping_mul(0xd816_3841)
+        ^ (position[2] as u32).wrapping_mul(0xcb1a_b31f)
+        ^ seed;
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^ (hash >> 12)
+}
+
+/// World position of the block from the bordered position inside the section
+pub fn get_world_position(origin: [i32; 3], voxel: [i32; 3]) -> [i32; 3] {
+    // magic: Offset -1 because of chunk mesh one block boundary
+    offset(offset(origin, voxel, 1), [1; 3], -1)
+}
+
+/// Index in the colors scheme of the block type picked by the block position
+pub fn get_color_variant(block_type: &BlockType, position: [i32; 3]) -> usize {
+    let colors_count = match block_type.get_block_content() {
+        BlockContent::Texture {
+            colors_scheme: Some(colors),
+            ..
+        } => colors.len(),
+        _ => 0,
+    };
+    match colors_count > 1 {
+        true => get_position_hash(position, COLOR_SEED) as usize % colors_count,
+        false => 0,
+    }
+}
+
+fn is_same_block(data: &ChunkColliderDataBordered, pos: [i32; 3], block_info: &BlockDataInfo) -> bool {
+    let max = CHUNK_SIZE as i32 + 1;
+    if pos.iter().any(|p| *p < 0 || *p > max) {
+        return false;
+    }
+    let i = ChunkBordersShape::linearize([pos[0] as u32, pos[1] as u32, pos[2] as u32]);
+    data[i as usize]
+        .get_block_info()
+        .as_ref()
+        .is_some_and(|b| b.get_id() == block_info.get_id())
+}
+
+/// Borders of the texture which are hidden by the same neighbour blocks: left, right, top, bottom
+fn get_connection_mask(data: &ChunkColliderDataBordered, block_info: &BlockDataInfo, cell: &FaceCell) -> usize {
+    let mut mask = 0;
+    for (axis, tex_step) in [(cell.u, cell.tex_u), (cell.v, cell.tex_v)] {
+        for sign in [-1, 1] {
+            if !is_same_block(data, offset(cell.voxel, axis, sign), block_info) {
+                continue;
+            }
+            // Face axes are turned differently in the texture on every side
+            let d = tex_step * sign as f32;
+            mask |= match d.x.abs() > d.y.abs() {
+                true if d.x < 0.0 => 1 << 0,
+                true => 1 << 1,
+                false if d.y < 0.0 => 1 << 2,
+                false => 1 << 3,
+            };
+        }
+    }
+    mask
+}
+
+/// Layer of the single block face for the textures which depend on the block position or neighbours
+///
+/// `origin` is the position of the section in the world
+pub fn get_face_layer(
+    texture_mapper: &TextureMapper,
+    block_storage: &BlockStorage,
+    data: &ChunkColliderDataBordered,
+    block_info: &BlockDataInfo,
+    side_index: i8,
+    cell: &FaceCell,
+    origin: [i32; 3],
+) -> Option<usize> {
+    let block_type = block_storage.get(&block_info.get_id())?;
+    let settings = block_storage.get_block_render_settings(block_info);
+    let position = get_world_position(origin, cell.voxel);
+
+    // Zero is the main texture
+    let variants_count = settings.map_or(0, |s| s.texture_variants.len());
+    let variant = match get_position_hash(position, TEXTURE_SEED) as usize % (variants_count + 1) {
+        0 => None,
+        i => settings.and_then(|s| s.texture_variants.get(i - 1)),
+    };
+    let choice = TextureChoice {
+        color_variant: get_color_variant(block_type, position),
+        variant,
+        connected: settings.is_some_and(|s| s.has_connected_texture()),
+    };
+    let layer = texture_mapper.get_texture_layer(block_type, side_index, &choice)?;
+    match choice.connected {
+        true => Some(layer + get_connection_mask(data, block_info, cell)),
+        false => Some(layer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        blocks::{chunk_collider_info::ChunkColliderInfo, voxel_visibility::VoxelVisibility},
+        chunks::chunk_data::BlockIndexType,
+    };
+
+    fn create_data(blocks: &[([u32; 3], BlockIndexType)]) -> ChunkColliderDataBordered {
+        let mut data = [ChunkColliderInfo::create(VoxelVisibility::Empty, None); ChunkBordersShape::SIZE as usize];
+        for (pos, id) in blocks.iter() {
+            let block_info = BlockDataInfo::create(*id, None);
+            data[ChunkBordersShape::linearize(*pos) as usize] =
+                ChunkColliderInfo::create(VoxelVisibility::Opaque, Some(block_info));
+        }
+        data
+    }
+
+    fn create_cell(tex_u: Vector2, tex_v: Vector2) -> FaceCell {
+        FaceCell {
+            voxel: [5, 5, 5],
+            u: [1, 0, 0],
+            v: [0, 0, 1],
+            tex_u,
+            tex_v,
+        }
+    }
+
+    #[test]
+    fn position_hash_is_deterministic() {
+        // Every client must pick the same variants
+        assert_eq!(get_position_hash([3, 4, 5], COLOR_SEED), 460871493);
+        assert_eq!(get_position_hash([-7, 64, 1025], TEXTURE_SEED), 2588310594);
+
+        let hash = get_position_hash([3, 4, 5], COLOR_SEED);
+        assert_ne!(hash, get_position_hash([3, 4, 5], TEXTURE_SEED));
+        assert_ne!(hash, get_position_hash([5, 4, 3], COLOR_SEED));
+    }
+
+    #[test]
+    fn position_hash_picks_every_variant() {
+        let mut counts = [0; 4];
+        for x in -16..16 {
+            for z in -16..16 {
+                counts[get_position_hash([x, 70, z], TEXTURE_SEED) as usize % counts.len()] += 1;
+            }
+        }
+        // 1024 positions, so every variant is picked close to 256 times
+        assert!(counts.iter().all(|c| *c > 150), "{:?}", counts);
+    }
+
+    #[test]
+    fn world_position() {
+        assert_eq!(get_world_position([16, 32, -16], [1, 1, 1]), [16, 32, -16]);
+        assert_eq!(get_world_position([16, 32, -16], [16, 2, 5]), [31, 33, -12]);
+    }
+
+    #[test]
+    fn connection_mask_bits() {
+        let block_info = BlockDataInfo::create(1, None);
+        let cell = create_cell(Vector2::new(1.0, 0.0), Vector2::new(0.0, 1.0));
+
+        assert_eq!(get_connection_mask(&create_data(&[]), &block_info, &cell), 0);
+
+        // Left is -u, right +u, top -v and bottom +v
+        let sides = [
+            ([4, 5, 5], 0b0001),
+            ([6, 5, 5], 0b0010),
+            ([5, 5, 4], 0b0100),
+            ([5, 5, 6], 0b1000),
+        ];
+        for (pos, mask) in sides.iter() {
+            let data = create_data(&[(*pos, 1)]);
+            assert_eq!(get_connection_mask(&data, &block_info, &cell), *mask);
+        }
+
+        let all: Vec<([u32; 3], BlockIndexType)> = sides.iter().map(|(pos, _)| (*pos, 1)).collect();
+        assert_eq!(get_connection_mask(&create_data(&all), &block_info, &cell), 0b1111);
+    }
+
+    #[test]
+    fn connection_mask_ignores_other_blocks() {
+        let block_info = BlockDataInfo::create(1, None);
+        let cell = create_cell(Vector2::new(1.0, 0.0), Vector2::new(0.0, 1.0));
+
+        // Other block and the same block in front of the face
+        let data = create_data(&[([4, 5, 5], 2), ([5, 6, 5], 1)]);
+        assert_eq!(get_connection_mask(&data, &block_info, &cell), 0);
+    }
+
+    #[test]
+    fn connection_mask_turned_texture() {
+        let block_info = BlockDataInfo::create(1, None);
+        // Texture is turned on the face: u goes up in the texture, v goes right
+        let cell = create_cell(Vector2::new(0.0, -1.0), Vector2::new(1.0, 0.0));
+
+        let data = create_data(&[([6, 5, 5], 1), ([5, 5, 4], 1)]);
+        assert_eq!(get_connection_mask(&data, &block_info, &cell), 0b0101);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    utils::textures::texture_mapper::{TextureChoice, TextureMapper},
+    world::{
+        block_storage::BlockStorage,
+        chunks::{
+            chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
+            light::column_light::ChunkLightBordered,
+        },
+    },
+};
+use common::{
+    blocks::{chunk_collider_info::ChunkColliderInfo, voxel_visibility::VoxelVisibility},
+    utils::block_mesh::RIGHT_HANDED_Y_UP_CONFIG,
+    CHUNK_SIZE,
+};
+use godot::prelude::Vector3;
+use ndshape::ConstShape;
+
+use super::{
+    ambient_occlusion::{is_occluder, offset, NO_OCCLUSION},
+    block_shapes::get_box_face,
+    face_textures::{get_color_variant, get_world_position},
+    mesh_generator::{get_layer_uv, MeshData},
+    mesh_settings::ChunkMeshSettings,
+    smooth_lighting::{get_block_light, FULL_LIGHT},
+};
+
+/// Level of detail of the chunk column mesh
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChunkLod {
+    Full = 0,
+    Half = 1,
+    Quarter = 2,
+}
+
+impl ChunkLod {
+    /// Size of the downsampled cell in blocks
+    pub fn get_scale(&self) -> u32 {
+        1 << (*self as u32)
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ChunkLod::Full,
+            1 => ChunkLod::Half,
+            _ => ChunkLod::Quarter,
+        }
+    }
+
+    /// Distance is in chunks from the player chunk
+    pub fn from_distance(distance: f32, settings: &ChunkMeshSettings) -> Self {
+        if settings.lod_distance == 0 {
+            return ChunkLod::Full;
+        }
+        let lod_distance = settings.lod_distance as f32;
+        if distance < lod_distance {
+            ChunkLod::Full
+        } else if distance < lod_distance * 2.0 {
+            ChunkLod::Half
+        } else {
+            ChunkLod::Quarter
+        }
+    }
+}
+
+/// Replaces every `scale` cube of blocks with one block
+///
+/// The grid and the positions stay the same, so greedy meshing merges the cube into big quads.
+/// The cube is solid if at least half of it is solid; the topmost block of the cube is used,
+/// so grass stays on the surface. Holes at the section border are covered by `generate_skirts`.
+pub fn downsample_chunk_data(data: &ChunkColliderDataBordered, lod: ChunkLod) -> ChunkColliderDataBordered {
+    let mut result = *data;
+
+    let scale = lod.get_scale();
+    if scale == 1 {
+        return result;
+    }
+    let size = CHUNK_SIZE as u32;
+    let total = scale * scale * scale;
+
+    for cx in (0..size).step_by(scale as usize) {
+        for cy in (0..size).step_by(scale as usize) {
+            for cz in (0..size).step_by(scale as usize) {
+                let mut solid = 0;
+                let mut top: Option<(u32, usize)> = None;
+                for x in cx..cx + scale {
+                    for y in cy..cy + scale {
+                        for z in cz..cz + scale {
+                            let i = ChunkBordersShape::linearize([x + 1, y + 1, z + 1]) as usize;
+                            if *data[i].get_voxel_visibility() == VoxelVisibility::Empty {
+                                continue;
+                            }
+                            solid += 1;
+                            if top.is_none_or(|(top_y, _)| y > top_y) {
+                                top = Some((y, i));
+                            }
+                        }
+                    }
+                }
+
+                let cell = match (solid * 2 >= total, top) {
+                    (true, Some((_, i))) => data[i],
+                    _ => ChunkColliderInfo::create(VoxelVisibility::Empty, None),
+                };
+
+                for x in cx..cx + scale {
+                    for y in cy..cy + scale {
+                        for z in cz..cz + scale {
+                            let i = ChunkBordersShape::linearize([x + 1, y + 1, z + 1]) as usize;
+                            result[i] = cell;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Faces of the neighbour blocks which are hidden only by the removed blocks of the section
+///
+/// Neighbours are meshed against the full data, so they skip the faces towards
+/// the solid blocks of the section; when the downsampling removes such a block,
+/// the skirt face of the neighbour block is added on the section border instead.
+pub fn generate_skirts(
+    texture_mapper: &TextureMapper,
+    data: &ChunkColliderDataBordered,
+    lod_data: &ChunkColliderDataBordered,
+    chunk_light: Option<&ChunkLightBordered>,
+    block_storage: &BlockStorage,
+    origin: [i32; 3],
+) -> MeshData {
+    let mut mesh = MeshData::default();
+    let size = CHUNK_SIZE as i32;
+
+    // Face of the neighbour block looks inside the section
+    for (side_index, face) in RIGHT_HANDED_Y_UP_CONFIG.faces.iter().enumerate() {
+        let n = face.signed_normal();
+        let normal = [n.x, n.y, n.z];
+        let axis = normal.iter().position(|c| *c != 0).unwrap();
+        let (a_axis, b_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        for a in 1..=size {
+            for b in 1..=size {
+                let mut border = [0; 3];
+                border[axis] = match side_index >= 3 {
+                    true => 0,
+                    false => size + 1,
+                };
+                border[a_axis] = a;
+                border[b_axis] = b;
+                let inner = offset(border, normal, 1);
+
+                if !is_occluder(data, border) || !is_occluder(data, inner) {
+                    continue;
+                }
+                let i = ChunkBordersShape::linearize([inner[0] as u32, inner[1] as u32, inner[2] as u32]);
+                if *lod_data[i as usize].get_voxel_visibility() != VoxelVisibility::Empty {
+                    continue;
+                }
+
+                let i = ChunkBordersShape::linearize([border[0] as u32, border[1] as u32, border[2] as u32]);
+                let Some(block_info) = data[i as usize].get_block_info().as_ref() else {
+                    continue;
+                };
+                let Some(block_type) = block_storage.get(&block_info.get_id()) else {
+                    continue;
+                };
+                let choice = TextureChoice {
+                    color_variant: get_color_variant(block_type, get_world_position(origin, border)),
+                    ..Default::default()
+                };
+                let Some(layer) = texture_mapper.get_texture_layer(block_type, side_index as i8, &choice) else {
+                    continue;
+                };
+
+                let (corners, tex_coords) = get_box_face([0.0; 3], [1.0; 3], side_index);
+                // magic: Offset -1 because of chunk mesh one block boundary
+                let position = Vector3::new(border[0] as f32 - 1.0, border[1] as f32 - 1.0, border[2] as f32 - 1.0);
+                let light = match chunk_light {
+                    Some(chunk_light) => get_block_light(chunk_light, inner),
+                    None => FULL_LIGHT,
+                };
+                mesh.push_quad(
+                    face,
+                    corners.map(|c| position + c),
+                    tex_coords,
+                    get_layer_uv(layer),
+                    NO_OCCLUSION,
+                    light,
+                );
+            }
+        }
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::chunks::chunk_data::BlockDataInfo;
+
+    fn create_data(is_solid: impl Fn([u32; 3]) -> bool) -> ChunkColliderDataBordered {
+        let mut data = [ChunkColliderInfo::create(VoxelVisibility::Empty, None); ChunkBordersShape::SIZE as usize];
+        for i in 0..ChunkBordersShape::SIZE as u32 {
+            if is_solid(ChunkBordersShape::delinearize(i)) {
+                data[i as usize] =
+                    ChunkColliderInfo::create(VoxelVisibility::Opaque, Some(BlockDataInfo::create(1, None)));
+            }
+        }
+        data
+    }
+
+    fn is_empty(data: &ChunkColliderDataBordered, pos: [u32; 3]) -> bool {
+        *data[ChunkBordersShape::linearize(pos) as usize].get_voxel_visibility() == VoxelVisibility::Empty
+    }
+
+    #[test]
+    fn full_lod_keeps_data() {
+        let data = create_data(|[x, y, z]| (x + y + z) % 3 == 0);
+        let result = downsample_chunk_data(&data, ChunkLod::Full);
+        for i in 0..ChunkBordersShape::SIZE as usize {
+            assert!(data[i].get_voxel_visibility() == result[i].get_voxel_visibility());
+        }
+    }
+
+    #[test]
+    fn cubes_by_majority() {
+        // Ground up to the bordered y 4 with one block above it at the border
+        let data = create_data(|[x, y, z]| y <= 4 || (x == 1 && y == 5 && z == 1));
+        let result = downsample_chunk_data(&data, ChunkLod::Half);
+
+        // Cubes of the ground are filled, the cube with the single block is removed
+        assert!(!is_empty(&result, [1, 1, 1]));
+        assert!(!is_empty(&result, [2, 4, 2]));
+        assert!(is_empty(&result, [1, 5, 1]));
+        assert!(is_empty(&result, [2, 6, 2]));
+    }
+
+    #[test]
+    fn shell_is_not_changed() {
+        let data = create_data(|[x, _, _]| x == 0);
+        let result = downsample_chunk_data(&data, ChunkLod::Quarter);
+        assert!(!is_empty(&result, [0, 5, 5]));
+        assert!(is_empty(&result, [1, 5, 5]));
+    }
+}
@@ -1,18 +1,29 @@
 use crate::{
     scenes::main_scene::FloatType,
-    utils::{bridge::IntoNetworkVector, textures::texture_mapper::TextureMapper},
+    utils::{
+        bridge::IntoNetworkVector,
+        textures::texture_mapper::{TextureChoice, TextureMapper},
+    },
     world::{
         block_storage::BlockStorage,
-        chunks::chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
+        chunks::{
+            chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
+            light::column_light::ChunkLightBordered,
+            mesh::{
+                ambient_occlusion::{get_ao_brightness, get_face_ao, need_flip, QuadAo, NO_OCCLUSION},
+                face_textures::{get_face_layer, FaceCell},
+                mesh_settings::ChunkMeshSettings,
+                smooth_lighting::{get_face_light, get_light_color, QuadLight, FULL_LIGHT},
+            },
+        },
     },
 };
 use common::{
     blocks::{chunk_collider_info::ChunkColliderInfo, voxel_visibility::VoxelVisibility},
     chunks::{chunk_data::BlockDataInfo, position::Vector3 as NetworkVector3},
     utils::block_mesh::{
-        buffer::UnitQuadBuffer,
         greedy::{greedy_quads, GreedyQuadsBuffer},
-        visible_block_faces, QuadBuffer, UnorientedQuad, RIGHT_HANDED_Y_UP_CONFIG,
+        OrientedBlockFace, QuadBuffer, RIGHT_HANDED_Y_UP_CONFIG,
     },
     CHUNK_SIZE,
 };
@@ -23,8 +34,8 @@ use godot::{
     },
     obj::{EngineEnum, NewGd},
     prelude::{
-        Array, Gd, PackedInt32Array, PackedVector2Array, PackedVector3Array, Variant, Vector2,
-        Vector3,
+        Array, Color, Gd, PackedColorArray, PackedInt32Array, PackedVector2Array, PackedVector3Array,
+        ToGodot, Variant, Vector2, Vector3,
     },
 };
 use ndshape::ConstShape;
@@ -47,20 +58,8 @@ pub(crate) fn _get_test_sphere(
     b_chunk
 }
 
-pub fn generate_buffer(chunk_collider_data: &ChunkColliderDataBordered) -> UnitQuadBuffer {
-    let mut buffer = UnitQuadBuffer::new();
-    visible_block_faces(
-        chunk_collider_data,
-        &ChunkBordersShape {},
-        [0; 3],
-        [CHUNK_SIZE as u32 + 1; 3],
-        &RIGHT_HANDED_Y_UP_CONFIG.faces,
-        &mut buffer,
-    );
-    buffer
-}
-
-pub fn _generate_buffer_greedy(chunk_collider_data: &ChunkColliderDataBordered) -> QuadBuffer {
+/// Merges faces of the same blocks into quads
+pub fn generate_buffer(chunk_collider_data: &ChunkColliderDataBordered) -> QuadBuffer {
     let mut buffer = GreedyQuadsBuffer::new(chunk_collider_data.len());
     greedy_quads(
         chunk_collider_data,
@@ -73,32 +72,169 @@ pub fn _generate_buffer_greedy(chunk_collider_data: &ChunkColliderDataBordered)
     buffer.quads
 }
 
+/// Which blocks are meshed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MeshPass {
+    Opaque,
+    /// Faces are not merged, so they can be sorted by the distance
+    Translucent,
+}
+
+/// Vertex arrays of the surface
+///
+/// Plain buffers, so they can be built on the worker threads;
+/// the godot mesh is created from them on the main thread
+#[derive(Default)]
+pub struct MeshData {
+    indices: Vec<i32>,
+    verts: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    uvs: Vec<Vector2>,
+    uvs2: Vec<Vector2>,
+    colors: Vec<Color>,
+}
+
+impl MeshData {
+    /// Corners are in the `quad_corners` order
+    pub(crate) fn push_quad(
+        &mut self,
+        face: &OrientedBlockFace,
+        corners: [Vector3; 4],
+        tex_coords: [Vector2; 4],
+        layer_uv: Vector2,
+        ao: QuadAo,
+        light: QuadLight,
+    ) {
+        // Rotating the corners moves the triangles diagonal and keeps the winding
+        let order = match need_flip(&ao, &light) {
+            true => [1, 3, 0, 2],
+            false => [0, 1, 2, 3],
+        };
+
+        self.indices.extend(face.quad_mesh_indices(self.verts.len() as i32));
+
+        let n = face.signed_normal();
+        let normal = Vector3::new(n.x as f32, n.y as f32, n.z as f32);
+        for i in order {
+            self.push_vertex(corners[i], normal, tex_coords[i], layer_uv, ao[i], light[i]);
+        }
+    }
+
+    /// Quad seen from both sides, e.g. plants; corners are in the `quad_corners` order
+    pub(crate) fn push_double_sided_quad(
+        &mut self,
+        corners: [Vector3; 4],
+        tex_coords: [Vector2; 4],
+        layer_uv: Vector2,
+        light: QuadLight,
+    ) {
+        for indices in [[0, 1, 2, 1, 3, 2], [0, 2, 1, 1, 2, 3]] {
+            let start = self.verts.len() as i32;
+            self.indices.extend(indices.map(|i| start + i));
+            for i in 0..4 {
+                // Upward normal lights both sides the same
+                self.push_vertex(corners[i], Vector3::UP, tex_coords[i], layer_uv, NO_OCCLUSION[i], light[i]);
+            }
+        }
+    }
+
+    fn push_vertex(&mut self, vert: Vector3, normal: Vector3, uv: Vector2, layer_uv: Vector2, ao: u8, light: [u8; 2]) {
+        self.verts.push(vert);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        self.uvs2.push(layer_uv);
+
+        // r: ambient occlusion, g: sky light, b: block light
+        self.colors.push(Color::from_rgb(
+            get_ao_brightness(ao),
+            get_light_color(light[0]),
+            get_light_color(light[1]),
+        ));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn append(&mut self, mut other: MeshData) {
+        let offset = self.verts.len() as i32;
+        self.indices.extend(other.indices.iter().map(|i| i + offset));
+        self.verts.append(&mut other.verts);
+        self.normals.append(&mut other.normals);
+        self.uvs.append(&mut other.uvs);
+        self.uvs2.append(&mut other.uvs2);
+        self.colors.append(&mut other.colors);
+    }
+
+    /// Orders quads from the farthest to the nearest for the alpha blending
+    ///
+    /// Every quad has its own 4 vertices and 6 indices, so only the indices are reordered
+    pub fn sort_back_to_front(&mut self, camera_position: Vector3) {
+        let mut quads: Vec<(f32, &[i32])> = self
+            .indices
+            .chunks(6)
+            .map(|quad| {
+                let first = *quad.iter().min().unwrap() as usize;
+                let center = (self.verts[first] + self.verts[first + 1] + self.verts[first + 2] + self.verts[first + 3]) / 4.0;
+                (center.distance_squared_to(camera_position), quad)
+            })
+            .collect();
+        quads.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let indices: Vec<i32> = quads.iter().flat_map(|(_, quad)| quad.iter().cloned()).collect();
+        self.indices = indices;
+    }
+
+    /// Must be called from the main thread
+    pub fn build_mesh(&self) -> Gd<ArrayMesh> {
+        let mut arrays: Array<Variant> = Array::new();
+        arrays.resize(ArrayType::MAX.ord() as usize, &Variant::nil());
+
+        arrays.set(ArrayType::INDEX.ord() as usize, &PackedInt32Array::from(&self.indices[..]).to_variant());
+        arrays.set(ArrayType::VERTEX.ord() as usize, &PackedVector3Array::from(&self.verts[..]).to_variant());
+        arrays.set(ArrayType::NORMAL.ord() as usize, &PackedVector3Array::from(&self.normals[..]).to_variant());
+        arrays.set(ArrayType::TEX_UV.ord() as usize, &PackedVector2Array::from(&self.uvs[..]).to_variant());
+        arrays.set(ArrayType::TEX_UV2.ord() as usize, &PackedVector2Array::from(&self.uvs2[..]).to_variant());
+        arrays.set(ArrayType::COLOR.ord() as usize, &PackedColorArray::from(&self.colors[..]).to_variant());
+
+        let mut mesh_ist = ArrayMesh::new_gd();
+        if !self.is_empty() {
+            mesh_ist.add_surface_from_arrays(PrimitiveType::TRIANGLES, &arrays);
+        }
+        mesh_ist
+    }
+}
+
+/// Texture array layer is passed to the shader in UV2.x
+pub(crate) fn get_layer_uv(layer: usize) -> Vector2 {
+    Vector2::new(layer as FloatType, 0.0)
+}
+
+fn to_ivec(c: &[u32; 3]) -> [i32; 3] {
+    [c[0] as i32, c[1] as i32, c[2] as i32]
+}
+
+/// `origin` is the position of the section in the world; it picks the texture variants
 pub fn generate_mesh(
     texture_mapper: &TextureMapper,
-    buffer: &UnitQuadBuffer,
+    chunk_collider_data: &ChunkColliderDataBordered,
+    chunk_light: Option<&ChunkLightBordered>,
+    buffer: &QuadBuffer,
     block_storage: &BlockStorage,
-    only_translucent: bool,
-) -> Gd<ArrayMesh> {
+    mesh_settings: &ChunkMeshSettings,
+    pass: MeshPass,
+    origin: [i32; 3],
+) -> MeshData {
     // let chunk_collider_data = &_get_test_sphere(8.0, BlockInfo::create(1, None));
 
-    let mut arrays: Array<Variant> = Array::new();
-    arrays.resize(ArrayType::MAX.ord() as usize, &Variant::nil());
-
-    let mut indices = PackedInt32Array::new();
-    let mut verts = PackedVector3Array::new();
-    let mut normals = PackedVector3Array::new();
-    let mut uvs = PackedVector2Array::new();
-
-    let steep = 0.03125;
-    let uv_scale = Vector2::new(steep, steep);
+    let mut arrays = MeshData::default();
 
     let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
 
     let iter = buffer.groups.clone().into_iter();
     for (side_index, (group, face)) in iter.zip(faces.into_iter()).enumerate() {
-        // visible_block_faces_with_voxel_view
         // face is OrientedBlockFace
-        // group Vec<UnorientedUnitQuad>
+        // group Vec<UnorientedQuad>
         for quad in group.into_iter() {
 
             let block_info = quad
@@ -108,62 +244,137 @@ pub fn generate_mesh(
                 .get(&block_info.get_id())
                 .expect("GENERATE_CHUNK_GEOMETRY block type is not found");
 
-            if *block_type.get_voxel_visibility() != VoxelVisibility::Translucent && only_translucent {
+            let is_translucent = *block_type.get_voxel_visibility() == VoxelVisibility::Translucent;
+            if is_translucent != (pass == MeshPass::Translucent) {
                 continue;
             }
 
-            if *block_type.get_voxel_visibility() == VoxelVisibility::Translucent && !only_translucent {
+            // Colors, variants and connected textures are chosen for every block face
+            let per_block = block_storage.has_per_block_texture(&block_info);
+            let layer_uv = match per_block {
+                true => Vector2::ZERO,
+                false => match texture_mapper.get_texture_layer(block_type, side_index as i8, &TextureChoice::default()) {
+                    Some(layer) => get_layer_uv(layer),
+                    None => continue,
+                },
+            };
+
+            // magic: Offset -1 because of chunk mesh one block boundary
+            let corners = face
+                .quad_corners(&quad, true)
+                .map(|c| Vector3::new(c.x as f32, c.y as f32, c.z as f32) - Vector3::new(1.0, 1.0, 1.0));
+
+            // UV is in blocks, so the shader repeats the tile along the merged quad
+            let tex_coords = face
+                .tex_coords_godot(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, false, &quad)
+                .map(|i| Vector2::new(i[0], i[1]));
+
+            // Without light data the mesh is fully lit; used for block icons
+            if !mesh_settings.ambient_occlusion
+                && chunk_light.is_none()
+                && pass == MeshPass::Opaque
+                && !per_block
+            {
+                arrays.push_quad(&face, corners, tex_coords, layer_uv, NO_OCCLUSION, FULL_LIGHT);
                 continue;
             }
 
-            let i = face.quad_mesh_indices(verts.len() as i32);
-            indices.extend(i);
+            // Face axes of the merged quad
+            let (width, height) = (quad.width as i32, quad.height as i32);
+            let u_vec = (corners[1] - corners[0]) / width as f32;
+            let v_vec = (corners[2] - corners[0]) / height as f32;
+            let u = [u_vec.x as i32, u_vec.y as i32, u_vec.z as i32];
+            let v = [v_vec.x as i32, v_vec.y as i32, v_vec.z as i32];
+            let n = face.signed_normal();
+            let normal = [n.x, n.y, n.z];
+            let minimum = to_ivec(&quad.minimum);
 
-            let voxel_size = 1.0;
-            let v = face.quad_corners(&quad.clone().into(), true).map(|c| {
-                // magic: Offset -1 because of chunk mesh one block boundary
-                let vert_pos =
-                    Vector3::new(c.x as f32, c.y as f32, c.z as f32) - Vector3::new(1.0, 1.0, 1.0);
-                vert_pos * voxel_size
-            });
-            verts.extend(v);
+            let mut cells: Vec<([i32; 3], QuadAo, QuadLight)> = Vec::with_capacity((width * height) as usize);
+            for j in 0..height {
+                for i in 0..width {
+                    let voxel = [
+                        minimum[0] + u[0] * i + v[0] * j,
+                        minimum[1] + u[1] * i + v[1] * j,
+                        minimum[2] + u[2] * i + v[2] * j,
+                    ];
+                    let ao = match mesh_settings.ambient_occlusion {
+                        true => get_face_ao(chunk_collider_data, voxel, normal, u, v),
+                        false => NO_OCCLUSION,
+                    };
+                    let light = match chunk_light {
+                        Some(chunk_light) => get_face_light(chunk_collider_data, chunk_light, voxel, normal, u, v),
+                        None => FULL_LIGHT,
+                    };
+                    cells.push((voxel, ao, light));
+                }
+            }
 
-            let n = face.signed_normal();
-            normals.extend([Vector3::new(n.x as f32, n.y as f32, n.z as f32); 4]);
-
-            let unoriented_quad = UnorientedQuad::from(quad);
-            for i in &face.tex_coords_godot(
-                RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
-                false,
-                &unoriented_quad,
-            ) {
-                let Some(offset) = texture_mapper.get_uv_offset(block_type, side_index as i8)
-                else {
-                    continue;
-                };
-                let ui_offset = Vector2::new(
-                    steep * ((offset % 32) as i32) as FloatType,
-                    steep * ((offset / 32) as f32).floor() as FloatType,
-                );
-                uvs.push(Vector2::new(i[0], i[1]) * uv_scale + ui_offset)
+            // Merged quad keeps the occlusion and light only if they are the same everywhere
+            let (first_ao, first_light) = (cells[0].1[0], cells[0].2[0]);
+            let is_uniform = cells
+                .iter()
+                .all(|(_, ao, light)| ao.iter().all(|a| *a == first_ao) && light.iter().all(|l| *l == first_light));
+            if is_uniform && pass == MeshPass::Opaque && !per_block {
+                arrays.push_quad(&face, corners, tex_coords, layer_uv, [first_ao; 4], [first_light; 4]);
+                continue;
+            }
+
+            // Otherwise it's splitted back into the single block faces
+            let tex_u = (tex_coords[1] - tex_coords[0]) / width as f32;
+            let tex_v = (tex_coords[2] - tex_coords[0]) / height as f32;
+            for j in 0..height {
+                for i in 0..width {
+                    let (fi, fj) = (i as f32, j as f32);
+                    let cell_corners = [
+                        corners[0] + u_vec * fi + v_vec * fj,
+                        corners[0] + u_vec * (fi + 1.0) + v_vec * fj,
+                        corners[0] + u_vec * fi + v_vec * (fj + 1.0),
+                        corners[0] + u_vec * (fi + 1.0) + v_vec * (fj + 1.0),
+                    ];
+                    let cell_tex_coords = [
+                        tex_coords[0] + tex_u * fi + tex_v * fj,
+                        tex_coords[0] + tex_u * (fi + 1.0) + tex_v * fj,
+                        tex_coords[0] + tex_u * fi + tex_v * (fj + 1.0),
+                        tex_coords[0] + tex_u * (fi + 1.0) + tex_v * (fj + 1.0),
+                    ];
+                    let (voxel, ao, light) = cells[(j * width + i) as usize];
+                    let cell_layer_uv = match per_block {
+                        true => {
+                            let cell = FaceCell {
+                                voxel,
+                                u,
+                                v,
+                                tex_u,
+                                tex_v,
+                            };
+                            let layer = get_face_layer(
+                                texture_mapper,
+                                block_storage,
+                                chunk_collider_data,
+                                &block_info,
+                                side_index as i8,
+                                &cell,
+                                origin,
+                            );
+                            match layer {
+                                Some(layer) => get_layer_uv(layer),
+                                None => continue,
+                            }
+                        }
+                        false => layer_uv,
+                    };
+                    arrays.push_quad(&face, cell_corners, cell_tex_coords, cell_layer_uv, ao, light);
+                }
             }
         }
     }
 
-    let mesh_len = indices.len();
-    arrays.set(ArrayType::INDEX.ord() as usize, &Variant::from(indices));
-    arrays.set(ArrayType::VERTEX.ord() as usize, &Variant::from(verts));
-    arrays.set(ArrayType::NORMAL.ord() as usize, &Variant::from(normals));
-    arrays.set(ArrayType::TEX_UV.ord() as usize, &Variant::from(uvs));
-
-    let mut mesh_ist = ArrayMesh::new_gd();
-    if mesh_len > 0 {
-        mesh_ist.add_surface_from_arrays(PrimitiveType::TRIANGLES, &arrays);
-    }
-    mesh_ist
+    arrays
 }
 
-pub fn build_collider(buffer: &UnitQuadBuffer) -> PhysicsColliderBuilder {
+/// Collider is built from the merged quads too;
+/// `shape_quads` are the faces of the block shapes in the `quad_corners` order
+pub fn build_collider(buffer: &QuadBuffer, shape_quads: &[[Vector3; 4]]) -> PhysicsColliderBuilder {
     let mut collider_indices: Vec<[u32; 3]> = Default::default();
     let mut collider_verts: Vec<NetworkVector3> = Default::default();
 
@@ -181,7 +392,7 @@ pub fn build_collider(buffer: &UnitQuadBuffer) -> PhysicsColliderBuilder {
             collider_indices.push([i[0] as u32, i[1] as u32, i[2] as u32]);
             collider_indices.push([i[3] as u32, i[4] as u32, i[5] as u32]);
 
-            for c in face.quad_corners(&quad.clone().into(), true).iter() {
+            for c in face.quad_corners(&quad, true).iter() {
                 // magic: Offset -1 because of chunk mesh one block boundary
                 let vert_pos =
                     Vector3::new(c.x as f32, c.y as f32, c.z as f32) - Vector3::new(1.0, 1.0, 1.0);
@@ -190,5 +401,15 @@ pub fn build_collider(buffer: &UnitQuadBuffer) -> PhysicsColliderBuilder {
             };
         }
     }
+
+    for corners in shape_quads.iter() {
+        let i = vert_index as u32;
+        vert_index += 4;
+        collider_indices.push([i, i + 1, i + 2]);
+        collider_indices.push([i + 1, i + 3, i + 2]);
+        for c in corners.iter() {
+            collider_verts.push(c.to_network());
+        }
+    }
     PhysicsColliderBuilder::trimesh(collider_verts, collider_indices)
 }
@@ -0,0 +1,38 @@
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub type ChunkMeshSettingsType = Arc<RwLock<ChunkMeshSettings>>;
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_lod_distance() -> u16 {
+    DEFAULT_LOD_DISTANCE
+}
+
+/// Columns further than this many chunks from the player are meshed with less detail
+pub const DEFAULT_LOD_DISTANCE: u16 = 8;
+
+/// Settings of the chunks mesher; changing them requires remeshing loaded chunks
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ChunkMeshSettings {
+    /// Per-vertex voxel ambient occlusion
+    #[serde(default = "default_true")]
+    pub ambient_occlusion: bool,
+
+    /// Distance in chunks where the 2x downsampled meshes start; 4x starts at the double distance.
+    /// 0 disables the level of detail
+    #[serde(default = "default_lod_distance")]
+    pub lod_distance: u16,
+}
+
+impl Default for ChunkMeshSettings {
+    fn default() -> Self {
+        Self {
+            ambient_occlusion: true,
+            lod_distance: DEFAULT_LOD_DISTANCE,
+        }
+    }
+}
@@ -1 +1,8 @@
+pub mod ambient_occlusion;
+pub mod block_shapes;
+pub mod face_textures;
+pub mod lod;
 pub mod mesh_generator;
+pub mod mesh_settings;
+pub mod smooth_lighting;
+pub mod translucent;
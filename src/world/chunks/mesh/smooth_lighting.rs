@@ -0,0 +1,94 @@
+use super::ambient_occlusion::{is_occluder, offset};
+use crate::world::chunks::{
+    chunk_section::{ChunkBordersShape, ChunkColliderDataBordered},
+    light::column_light::{ChunkLightBordered, LightChannel, MAX_LIGHT},
+};
+use common::CHUNK_SIZE;
+use ndshape::ConstShape;
+
+/// Sky and block light of the quad corners
+///
+/// Averaged levels are multiplied by 4 to keep them precise
+pub type QuadLight = [[u8; 2]; 4];
+
+pub const LIGHT_SCALE: u8 = 4;
+
+pub const FULL_LIGHT: QuadLight = [[MAX_LIGHT * LIGHT_SCALE, 0]; 4];
+
+/// Light of the vertex is the average of the open blocks around it
+fn get_vertex_light(
+    data: &ChunkColliderDataBordered,
+    light: &ChunkLightBordered,
+    front: [i32; 3],
+    side_u: [i32; 3],
+    side_v: [i32; 3],
+) -> [u8; 2] {
+    let side_1 = offset(front, side_u, 1);
+    let side_2 = offset(front, side_v, 1);
+    let corner = offset(side_1, side_v, 1);
+
+    let mut cells = vec![front];
+    let (side_1_open, side_2_open) = (!is_occluder(data, side_1), !is_occluder(data, side_2));
+    if side_1_open {
+        cells.push(side_1);
+    }
+    if side_2_open {
+        cells.push(side_2);
+    }
+    // Corner light can't come through two blocks
+    if (side_1_open || side_2_open) && !is_occluder(data, corner) {
+        cells.push(corner);
+    }
+
+    let (mut sky, mut block, mut count) = (0_u16, 0_u16, 0_u16);
+    for pos in cells {
+        let Some(packed) = get_light(light, pos) else {
+            continue;
+        };
+        sky += LightChannel::Sky.get(packed) as u16;
+        block += LightChannel::Block.get(packed) as u16;
+        count += 1;
+    }
+    if count == 0 {
+        return [0, 0];
+    }
+    let average = |sum: u16| ((sum * LIGHT_SCALE as u16 + count / 2) / count) as u8;
+    [average(sky), average(block)]
+}
+
+fn get_light(light: &ChunkLightBordered, pos: [i32; 3]) -> Option<u8> {
+    let max = CHUNK_SIZE as i32 + 1;
+    if pos.iter().any(|p| *p < 0 || *p > max) {
+        return None;
+    }
+    Some(light[ChunkBordersShape::linearize([pos[0] as u32, pos[1] as u32, pos[2] as u32]) as usize])
+}
+
+/// Smooth light of the single block face; arguments are the same as for `get_face_ao`
+pub fn get_face_light(
+    data: &ChunkColliderDataBordered,
+    light: &ChunkLightBordered,
+    voxel: [i32; 3],
+    normal: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+) -> QuadLight {
+    let front = offset(voxel, normal, 1);
+    let (neg_u, neg_v) = (offset([0; 3], u, -1), offset([0; 3], v, -1));
+    [
+        get_vertex_light(data, light, front, neg_u, neg_v),
+        get_vertex_light(data, light, front, u, neg_v),
+        get_vertex_light(data, light, front, neg_u, v),
+        get_vertex_light(data, light, front, u, v),
+    ]
+}
+
+/// Flat light of the block itself; used for the faces inside the block
+pub fn get_block_light(light: &ChunkLightBordered, voxel: [i32; 3]) -> QuadLight {
+    let packed = get_light(light, voxel).unwrap_or(0);
+    [[LightChannel::Sky.get(packed) * LIGHT_SCALE, LightChannel::Block.get(packed) * LIGHT_SCALE]; 4]
+}
+
+pub fn get_light_color(level: u8) -> f32 {
+    level as f32 / (MAX_LIGHT * LIGHT_SCALE) as f32
+}
@@ -0,0 +1,80 @@
+use crate::{
+    utils::textures::texture_mapper::TextureMapper,
+    world::{
+        block_storage::BlockStorage,
+        chunks::{chunk_section::ChunkColliderDataBordered, light::column_light::ChunkLightBordered},
+    },
+};
+use common::{
+    blocks::{chunk_collider_info::ChunkColliderInfo, voxel_visibility::VoxelVisibility},
+    chunks::chunk_data::BlockDataInfo,
+};
+
+use super::{
+    mesh_generator::{generate_buffer, generate_mesh, MeshData, MeshPass},
+    mesh_settings::ChunkMeshSettings,
+};
+
+/// Translucent geometry of the section
+#[derive(Default)]
+pub struct TranslucentMeshes {
+    pub transparent: MeshData,
+    pub liquid: MeshData,
+}
+
+/// Each translucent block type is meshed by a separate pass
+///
+/// Other translucent blocks are empty for the pass, so the faces between different
+/// translucent blocks (water and glass) are kept, while the faces between
+/// the same blocks are culled like the opaque ones.
+pub fn generate_translucent_meshes(
+    texture_mapper: &TextureMapper,
+    chunk_collider_data: &ChunkColliderDataBordered,
+    chunk_light: Option<&ChunkLightBordered>,
+    block_storage: &BlockStorage,
+    mesh_settings: &ChunkMeshSettings,
+    origin: [i32; 3],
+) -> TranslucentMeshes {
+    let mut meshes = TranslucentMeshes::default();
+
+    let mut block_infos: Vec<BlockDataInfo> = Vec::new();
+    for collider in chunk_collider_data.iter() {
+        if *collider.get_voxel_visibility() != VoxelVisibility::Translucent {
+            continue;
+        }
+        let Some(block_info) = collider.get_block_info().as_ref().map(|b| (*b).clone()) else {
+            continue;
+        };
+        if !block_infos.iter().any(|b| b.get_id() == block_info.get_id()) {
+            block_infos.push(block_info);
+        }
+    }
+
+    for block_info in block_infos.iter() {
+        let block_data = chunk_collider_data.map(|collider| {
+            let is_other = *collider.get_voxel_visibility() == VoxelVisibility::Translucent
+                && collider.get_block_info().as_ref().map(|b| b.get_id()) != Some(block_info.get_id());
+            match is_other {
+                true => ChunkColliderInfo::create(VoxelVisibility::Empty, None),
+                false => collider,
+            }
+        });
+
+        let buffer = generate_buffer(&block_data);
+        let mesh = generate_mesh(
+            texture_mapper,
+            &block_data,
+            chunk_light,
+            &buffer,
+            block_storage,
+            mesh_settings,
+            MeshPass::Translucent,
+            origin,
+        );
+        match block_storage.is_liquid(block_info) {
+            true => meshes.liquid.append(mesh),
+            false => meshes.transparent.append(mesh),
+        }
+    }
+    meshes
+}
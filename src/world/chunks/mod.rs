@@ -1,3 +1,4 @@
+pub mod light;
 pub mod mesh;
 pub mod chunk_column;
 pub mod chunks_map;
@@ -6,3 +7,5 @@ pub mod chunk_data_formatter;
 pub mod near_chunk_data;
 pub mod chunk_generator;
 pub mod objects_container;
+pub mod section_visibility;
+pub mod remesh_queue;
@@ -8,6 +8,12 @@ pub struct NearChunksData {
     pub behind: Option<ColumnDataLockType>,
     pub left: Option<ColumnDataLockType>,
     pub right: Option<ColumnDataLockType>,
+
+    // Diagonal neighbours are needed only for the edges of the bordered data
+    pub forward_left: Option<ColumnDataLockType>,
+    pub forward_right: Option<ColumnDataLockType>,
+    pub behind_left: Option<ColumnDataLockType>,
+    pub behind_right: Option<ColumnDataLockType>,
 }
 
 impl NearChunksData {
@@ -17,9 +23,30 @@ impl NearChunksData {
             behind: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x + 1, pos.z)),
             left: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x, pos.z - 1)),
             right: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x, pos.z + 1)),
+            forward_left: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x - 1, pos.z - 1)),
+            forward_right: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x - 1, pos.z + 1)),
+            behind_left: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x + 1, pos.z - 1)),
+            behind_right: NearChunksData::get_data(chunks, &ChunkPosition::new(pos.x + 1, pos.z + 1)),
         }
     }
 
+    /// Returns neighbour column by the offset; None for the center
+    pub fn get(&self, x: i32, z: i32) -> Option<&ColumnDataLockType> {
+        let column = match (x, z) {
+            (-1, 0) => &self.forward,
+            (1, 0) => &self.behind,
+            (0, -1) => &self.left,
+            (0, 1) => &self.right,
+            (-1, -1) => &self.forward_left,
+            (-1, 1) => &self.forward_right,
+            (1, -1) => &self.behind_left,
+            (1, 1) => &self.behind_right,
+            _ => return None,
+        };
+        column.as_ref()
+    }
+
+    /// Only direct neighbours are required for meshing
     pub fn is_full(&self) -> bool {
         self.forward.is_some() && self.behind.is_some() && self.left.is_some() && self.right.is_some()
     }
@@ -1,6 +1,6 @@
 use crate::{
     client_scripts::resource_manager::ResourceStorage,
-    utils::bridge::IntoGodotVector,
+    utils::bridge::{IntoGodotVector, IntoNetworkVector},
     world::{
         block_storage::BlockStorage,
         physics::{PhysicsProxy, PhysicsType},
@@ -18,82 +18,126 @@ use common::{
     blocks::{block_info::BlockFace, block_type::ColliderType},
     chunks::{chunk_data::ChunkSectionData, position::Vector3 as NetworkVector3},
 };
-use godot::prelude::*;
+use godot::{
+    classes::{
+        mesh::PrimitiveType, multi_mesh::TransformFormat, ArrayMesh, MeshInstance3D, MultiMesh,
+        MultiMeshInstance3D,
+    },
+    prelude::*,
+};
 use physics::{
     PhysicsCollider, PhysicsColliderBuilder,
     physics::{IPhysicsCollider, IPhysicsColliderBuilder},
 };
 
-#[derive(GodotClass)]
-#[class(no_init, base=Node3D)]
-pub struct CustomObject {
-    base: Base<Node3D>,
-    collider: Option<PhysicsCollider>,
-}
-
-impl CustomObject {
-    pub fn create(base: Base<Node3D>) -> Self {
-        Self { base, collider: None }
-    }
+/// Creates the model node turned by the block face; used for icons and previews
+pub fn create_model_node(glb: &Gd<Node3D>, block_face: Option<&BlockFace>) -> Gd<Node3D> {
+    let mut obj = glb.duplicate().unwrap().cast::<Node3D>();
 
-    pub fn attach_glb(&mut self, glb: &Gd<Node3D>, block_face: Option<&BlockFace>) {
-        let mut obj = glb.duplicate().unwrap().cast::<Node3D>();
+    // magic: locate it on center of block
+    obj.set_position(Vector3::new(-0.5, -0.5, -0.5));
 
-        // magic: locate it on center of block
-        obj.set_position(Vector3::new(-0.5, -0.5, -0.5));
+    let mut obj_holder = Node3D::new_alloc();
+    obj_holder.add_child(&obj);
+    obj_holder.set_basis(get_face_basis(block_face));
+    obj_holder
+}
 
-        let mut obj_holder = Node3D::new_alloc();
-        obj_holder.add_child(&obj);
+fn get_face_basis(block_face: Option<&BlockFace>) -> Basis {
+    let block_face = match block_face {
+        Some(f) => f.clone(),
+        None => BlockFace::default(),
+    };
+    let rotation = block_face.get_rotation();
+    Basis::from_euler(
+        EulerOrder::YXZ,
+        Vector3::new(
+            (rotation.yaw % 360.0).to_radians(),
+            (rotation.pitch % 360.0).to_radians(),
+            0.0,
+        ),
+    )
+}
 
-        let block_face = match block_face {
-            Some(f) => f.clone(),
-            None => BlockFace::default(),
+/// Mesh surfaces of the model with their transforms inside the model
+fn get_model_surfaces(node: &Gd<Node3D>, parent_transform: Transform3D, surfaces: &mut Vec<(Gd<ArrayMesh>, Transform3D)>) {
+    for child in node.get_children().iter_shared() {
+        let Ok(child) = child.try_cast::<Node3D>() else {
+            continue;
         };
-        let rotation = block_face.get_rotation();
-        let mut r = obj.get_rotation_degrees();
-        r.x = rotation.yaw % 360.0;
-        r.y = rotation.pitch % 360.0;
-        obj_holder.set_rotation_degrees(r);
-
-        self.base_mut().add_child(&obj_holder);
-    }
+        let transform = parent_transform * child.get_transform();
 
-    pub fn create_collider(&mut self, position: &BlockPosition, physics: &PhysicsProxy, is_sensor: bool) {
-        let physics_type = PhysicsType::ChunkMeshCollider(position.get_chunk_position());
-        let collider_builder = PhysicsColliderBuilder::cuboid(1.0, 1.0, 1.0);
-        let mut collider = physics.create_collider(collider_builder, Some(physics_type));
-        collider.set_position(position.get_position() + NetworkVector3::new(0.5, 0.5, 0.5));
-        if is_sensor {
-            collider.set_sensor(is_sensor);
+        if let Ok(mesh_instance) = child.clone().try_cast::<MeshInstance3D>() {
+            if let Some(mesh) = mesh_instance.get_mesh() {
+                for i in 0..mesh.get_surface_count() {
+                    let mut surface = ArrayMesh::new_gd();
+                    surface.add_surface_from_arrays(PrimitiveType::TRIANGLES, &mesh.surface_get_arrays(i));
+                    if let Some(material) = mesh_instance.get_active_material(i) {
+                        surface.surface_set_material(0, &material);
+                    }
+                    surfaces.push((surface, transform));
+                }
+            }
         }
-
-        self.collider = Some(collider);
+        get_model_surfaces(&child, transform, surfaces);
     }
+}
 
-    pub fn remove(&mut self) {
-        if let Some(collider) = self.collider.as_mut() {
-            collider.remove();
-        }
-    }
+/// All blocks of one model in the section
+///
+/// Every surface of the model is drawn by one multimesh for all the blocks
+struct ModelBatch {
+    surfaces: Vec<(Gd<MultiMeshInstance3D>, Transform3D)>,
+    blocks: AHashMap<ChunkBlockPosition, Option<BlockFace>>,
+    is_sensor: bool,
+}
 
-    pub fn get_content(&self) -> Gd<Node3D> {
-        let obj = self.base().get_children().iter_shared().next().unwrap();
-        obj.cast::<Node3D>()
+impl ModelBatch {
+    fn update_instances(&mut self) {
+        for (instance, surface_transform) in self.surfaces.iter_mut() {
+            let Some(mut multimesh) = instance.get_multimesh() else {
+                continue;
+            };
+            multimesh.set_instance_count(self.blocks.len() as i32);
+            for (i, (block_position, block_face)) in self.blocks.iter().enumerate() {
+                // +0.5 to place it on center of the block
+                let center = Transform3D::new(
+                    get_face_basis(block_face.as_ref()),
+                    block_position.to_godot() + Vector3::new(0.5, 0.5, 0.5),
+                );
+                let offset = Transform3D::new(Basis::IDENTITY, Vector3::new(-0.5, -0.5, -0.5));
+                multimesh.set_instance_transform(i as i32, center * offset * *surface_transform);
+            }
+        }
     }
 }
 
 /// Container for custom objects of map per chunk section
+///
+/// Model blocks are batched by the model into multimeshes,
+/// their colliders are merged into one shape per section
 #[derive(GodotClass)]
 #[class(init, base=Node3D)]
 pub struct ObjectsContainer {
     base: Base<Node3D>,
-    blocks: AHashMap<ChunkBlockPosition, Gd<CustomObject>>,
+    models: AHashMap<String, ModelBatch>,
+
+    // Model of each block
+    blocks: AHashMap<ChunkBlockPosition, String>,
+
+    chunk_position: Option<ChunkPosition>,
+    section_origin: Option<NetworkVector3>,
+    collider: Option<PhysicsCollider>,
+    sensor_collider: Option<PhysicsCollider>,
 }
 
 impl ObjectsContainer {
-    // Specifically for icons
-    pub fn get_first(&self) -> &Gd<CustomObject> {
-        self.blocks.values().next().unwrap()
+    /// Is there any block which is rendered as a model
+    pub fn contains_models(chunk_data: &ChunkSectionData, block_storage: &BlockStorage) -> bool {
+        chunk_data.iter().any(|(_block_index, block_info)| match block_storage.get(&block_info.get_id()) {
+            Some(block_type) => !block_type.get_block_content().is_texture(),
+            None => false,
+        })
     }
 
     pub fn setup(
@@ -118,35 +162,40 @@ impl ObjectsContainer {
                     icon_size: _,
                     collider_type,
                 } => {
-                    self.create_block_model(
-                        &position,
-                        model,
-                        collider_type,
-                        Some(physics),
-                        resource_storage,
-                        block_info.get_face(),
-                    )?;
+                    self.add_block_model(&position, model, collider_type, resource_storage, block_info.get_face())?;
                 }
                 _ => continue,
             }
         }
+
+        // Batches are filled once for the whole section
+        for batch in self.models.values_mut() {
+            batch.update_instances();
+        }
+        self.update_colliders(physics);
         Ok(())
     }
 
-    pub fn remove(&mut self, chunk_block_position: &ChunkBlockPosition) -> Option<()> {
-        if let Some(mut object) = self.blocks.remove(chunk_block_position) {
-            object.bind_mut().remove();
-            object.queue_free();
-            return Some(());
-        }
-        return None;
+    pub fn remove(&mut self, chunk_block_position: &ChunkBlockPosition, physics: &PhysicsProxy) -> Option<()> {
+        let model = self.blocks.remove(chunk_block_position)?;
+        let batch = self.models.get_mut(&model)?;
+        batch.blocks.remove(chunk_block_position);
+        batch.update_instances();
+
+        self.update_colliders(physics);
+        Some(())
     }
 
     pub fn destory(&mut self) {
-        for (_position, mut object) in self.blocks.drain() {
-            object.bind_mut().remove();
-            object.queue_free();
+        for mut collider in [self.collider.take(), self.sensor_collider.take()].into_iter().flatten() {
+            collider.remove();
+        }
+        for (_model, batch) in self.models.drain() {
+            for (mut instance, _transform) in batch.surfaces {
+                instance.queue_free();
+            }
         }
+        self.blocks.clear();
     }
 
     pub fn create_block_model(
@@ -154,32 +203,123 @@ impl ObjectsContainer {
         position: &BlockPosition,
         model: &String,
         collider_type: &ColliderType,
-        physics: Option<&PhysicsProxy>,
+        physics: &PhysicsProxy,
+        resource_storage: &ResourceStorage,
+        block_face: Option<&BlockFace>,
+    ) -> Result<(), String> {
+        self.add_block_model(position, model, collider_type, resource_storage, block_face)?;
+        if let Some(batch) = self.models.get_mut(model) {
+            batch.update_instances();
+        }
+        self.update_colliders(physics);
+        Ok(())
+    }
+
+    fn add_block_model(
+        &mut self,
+        position: &BlockPosition,
+        model: &String,
+        collider_type: &ColliderType,
         resource_storage: &ResourceStorage,
         block_face: Option<&BlockFace>,
     ) -> Result<(), String> {
+        let (section, block_position) = position.get_block_position();
+        if self.section_origin.is_none() {
+            let origin =
+                BlockPosition::from_chunk_position(&position.get_chunk_position(), &section, &ChunkBlockPosition::new(0, 0, 0));
+            self.section_origin = Some(origin.get_position());
+            self.chunk_position = Some(position.get_chunk_position());
+        }
+
+        if !self.models.contains_key(model) {
+            let batch = self.create_batch(model, collider_type, resource_storage)?;
+            self.models.insert(model.clone(), batch);
+        }
+        let batch = self.models.get_mut(model).unwrap();
+        batch.blocks.insert(block_position.clone(), block_face.cloned());
+        self.blocks.insert(block_position, model.clone());
+        Ok(())
+    }
+
+    fn create_batch(
+        &mut self,
+        model: &String,
+        collider_type: &ColliderType,
+        resource_storage: &ResourceStorage,
+    ) -> Result<ModelBatch, String> {
         let Some(media) = resource_storage.get_media(model) else {
             return Err(format!("model:{} is not found", model));
         };
         let Some(glb) = media.get_glb() else {
             return Err(format!("model:{} is not glb", model));
         };
-        let mut object = Gd::<CustomObject>::from_init_fn(|base| CustomObject::create(base));
 
-        if let Some(physics) = physics {
-            object
-                .bind_mut()
-                .create_collider(&position, physics, collider_type.is_sensor());
+        let mut model_surfaces = Default::default();
+        get_model_surfaces(glb, Transform3D::IDENTITY, &mut model_surfaces);
+
+        let mut surfaces = Vec::with_capacity(model_surfaces.len());
+        for (i, (mesh, transform)) in model_surfaces.into_iter().enumerate() {
+            let mut multimesh = MultiMesh::new_gd();
+            multimesh.set_transform_format(TransformFormat::TRANSFORM_3D);
+            multimesh.set_mesh(&mesh);
+
+            let mut instance = MultiMeshInstance3D::new_alloc();
+            instance.set_name(&format!("{} #{}", model, i));
+            instance.set_multimesh(&multimesh);
+            self.base_mut().add_child(&instance);
+            surfaces.push((instance, transform));
         }
 
-        object.bind_mut().attach_glb(glb, block_face);
-        let (_section, block_position) = position.get_block_position();
+        Ok(ModelBatch {
+            surfaces,
+            blocks: Default::default(),
+            is_sensor: collider_type.is_sensor(),
+        })
+    }
 
-        // +0.5 to place it on center of the block
-        object.set_position(block_position.to_godot() + Vector3::new(0.5, 0.5, 0.5));
+    /// Solid and sensor model blocks are merged into two colliders
+    fn update_colliders(&mut self, physics: &PhysicsProxy) {
+        for (is_sensor, collider) in [(false, &mut self.collider), (true, &mut self.sensor_collider)] {
+            if let Some(mut old) = collider.take() {
+                old.remove();
+            }
 
-        self.base_mut().add_child(&object);
-        self.blocks.insert(block_position, object);
-        Ok(())
+            let blocks: Vec<&ChunkBlockPosition> = self
+                .models
+                .values()
+                .filter(|batch| batch.is_sensor == is_sensor)
+                .flat_map(|batch| batch.blocks.keys())
+                .collect();
+            if blocks.is_empty() {
+                continue;
+            }
+            let (Some(chunk_position), Some(section_origin)) = (self.chunk_position.as_ref(), self.section_origin.as_ref())
+            else {
+                continue;
+            };
+
+            let physics_type = PhysicsType::ChunkMeshCollider(chunk_position.clone());
+            let mut new_collider = physics.create_collider(build_blocks_collider(&blocks), Some(physics_type));
+            new_collider.set_position(section_origin.clone());
+            if is_sensor {
+                new_collider.set_sensor(is_sensor);
+            }
+            *collider = Some(new_collider);
+        }
     }
 }
+
+/// Cuboid of every block in one compound shape; positions are inside the section
+///
+/// Solid shapes, so sensors detect bodies fully inside the block too
+fn build_blocks_collider(blocks: &[&ChunkBlockPosition]) -> PhysicsColliderBuilder {
+    let shapes: Vec<(NetworkVector3, PhysicsColliderBuilder)> = blocks
+        .iter()
+        .map(|block_position| {
+            // +0.5 to place it on center of the block
+            let center = block_position.to_godot() + Vector3::new(0.5, 0.5, 0.5);
+            (center.to_network(), PhysicsColliderBuilder::cuboid(1.0, 1.0, 1.0))
+        })
+        .collect();
+    PhysicsColliderBuilder::compound(shapes)
+}
@@ -0,0 +1,122 @@
+use ahash::AHashMap;
+use common::{chunks::chunk_position::ChunkPosition, CHUNK_SIZE};
+use godot::prelude::Vector3;
+
+use super::chunk_generator::RemeshResult;
+use crate::utils::bridge::GodotPositionConverter;
+
+/// Chunk column position and the section index
+pub type SectionKey = (ChunkPosition, usize);
+
+/// Lower is more urgent
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum RemeshPriority {
+    /// Sections around the block edited by the player
+    PlayerEdit,
+    Edit,
+    /// Level of detail swaps and settings changes
+    Background,
+}
+
+/// Sections waiting for the remesh; repeated requests are merged into one
+#[derive(Default)]
+pub struct RemeshQueue {
+    sections: AHashMap<SectionKey, RemeshPriority>,
+}
+
+impl RemeshQueue {
+    /// Keeps the most urgent priority of the section
+    pub fn insert(&mut self, key: SectionKey, priority: RemeshPriority) {
+        self.sections
+            .entry(key)
+            .and_modify(|p| *p = (*p).min(priority))
+            .or_insert(priority);
+    }
+
+    /// Raises the priority only if the section is already queued
+    pub fn raise(&mut self, key: &SectionKey, priority: RemeshPriority) {
+        if let Some(p) = self.sections.get_mut(key) {
+            *p = (*p).min(priority);
+        }
+    }
+
+    pub fn retain(&mut self, f: impl Fn(&SectionKey) -> bool) {
+        self.sections.retain(|key, _| f(key));
+    }
+
+    pub fn len(&self) -> usize {
+        self.sections.len()
+    }
+
+    /// Takes the most urgent ready sections; the nearest to the camera are first
+    pub fn take_batch(
+        &mut self,
+        camera_position: Option<Vector3>,
+        limit: usize,
+        is_ready: impl Fn(&SectionKey) -> bool,
+    ) -> Vec<SectionKey> {
+        let mut ready: Vec<(RemeshPriority, f32, SectionKey)> = self
+            .sections
+            .iter()
+            .filter(|(key, _)| is_ready(key))
+            .map(|(key, priority)| (*priority, get_section_distance(key, camera_position), key.clone()))
+            .collect();
+        ready.sort_by(|a, b| {
+            a.0.cmp(&b.0)
+                .then(a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        });
+        ready.truncate(limit);
+
+        ready
+            .into_iter()
+            .map(|(_, _, key)| {
+                self.sections.remove(&key);
+                key
+            })
+            .collect()
+    }
+}
+
+fn get_section_distance(key: &SectionKey, camera_position: Option<Vector3>) -> f32 {
+    let Some(camera_position) = camera_position else {
+        return 0.0;
+    };
+    let half = CHUNK_SIZE as f32 / 2.0;
+    let center = Vector3::new(
+        key.0.x as f32 * CHUNK_SIZE as f32 + half,
+        GodotPositionConverter::get_chunk_y_local(key.1 as u8) + half,
+        key.0.z as f32 * CHUNK_SIZE as f32 + half,
+    );
+    center.distance_squared_to(camera_position)
+}
+
+/// Sections remeshed on the workers together
+///
+/// They are applied in one frame when all of them are ready,
+/// so neighbour sections never show the different states of the edit
+pub struct RemeshBatch {
+    pending: usize,
+    results: Vec<RemeshResult>,
+}
+
+impl RemeshBatch {
+    pub fn create(size: usize) -> Self {
+        Self {
+            pending: size,
+            results: Vec::with_capacity(size),
+        }
+    }
+
+    pub fn push(&mut self, result: RemeshResult) {
+        self.pending -= 1;
+        self.results.push(result);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending == 0
+    }
+
+    pub fn take_results(self) -> Vec<RemeshResult> {
+        self.results
+    }
+}
@@ -0,0 +1,105 @@
+use common::{blocks::voxel_visibility::VoxelVisibility, CHUNK_SIZE};
+use ndshape::ConstShape;
+
+use super::chunk_section::{ChunkBordersShape, ChunkColliderDataBordered};
+
+/// Section faces: -x, +x, -y, +y, -z, +z
+pub const SECTION_FACES: [[i32; 3]; 6] = [[-1, 0, 0], [1, 0, 0], [0, -1, 0], [0, 1, 0], [0, 0, -1], [0, 0, 1]];
+
+pub fn opposite_face(face: usize) -> usize {
+    face ^ 1
+}
+
+/// Which faces of the section are connected through non-opaque blocks
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SectionVisibility(u64);
+
+impl SectionVisibility {
+    /// Empty section: everything is seen through it
+    pub const ALL: Self = Self(u64::MAX);
+
+    pub const NONE: Self = Self(0);
+
+    fn connect(&mut self, a: usize, b: usize) {
+        self.0 |= 1 << (a * 6 + b);
+        self.0 |= 1 << (b * 6 + a);
+    }
+
+    pub fn is_connected(&self, a: usize, b: usize) -> bool {
+        self.0 & (1 << (a * 6 + b)) != 0
+    }
+}
+
+/// Faces touched by the block
+fn get_block_faces(pos: [u32; 3]) -> u8 {
+    let max = CHUNK_SIZE as u32 - 1;
+    let mut faces = 0;
+    for axis in 0..3 {
+        if pos[axis] == 0 {
+            faces |= 1 << (axis * 2);
+        }
+        if pos[axis] == max {
+            faces |= 1 << (axis * 2 + 1);
+        }
+    }
+    faces
+}
+
+/// Flood fill of the non-opaque blocks of the section;
+/// faces touched by one filled area are connected
+pub fn compute_section_visibility(data: &ChunkColliderDataBordered) -> SectionVisibility {
+    let size = CHUNK_SIZE as u32;
+    let index = |pos: [u32; 3]| ((pos[1] * size + pos[2]) * size + pos[0]) as usize;
+    let is_open = |pos: [u32; 3]| {
+        let i = ChunkBordersShape::linearize([pos[0] + 1, pos[1] + 1, pos[2] + 1]);
+        *data[i as usize].get_voxel_visibility() != VoxelVisibility::Opaque
+    };
+
+    let mut visibility = SectionVisibility::NONE;
+    let mut visited = vec![false; (size * size * size) as usize];
+    let mut stack: Vec<[u32; 3]> = Default::default();
+
+    for x in 0..size {
+        for y in 0..size {
+            for z in 0..size {
+                let start = [x, y, z];
+                if visited[index(start)] || !is_open(start) {
+                    continue;
+                }
+                visited[index(start)] = true;
+                stack.push(start);
+
+                let mut faces = 0_u8;
+                while let Some(pos) = stack.pop() {
+                    faces |= get_block_faces(pos);
+
+                    for offset in SECTION_FACES.iter() {
+                        let next = [
+                            pos[0] as i32 + offset[0],
+                            pos[1] as i32 + offset[1],
+                            pos[2] as i32 + offset[2],
+                        ];
+                        if next.iter().any(|p| *p < 0 || *p >= size as i32) {
+                            continue;
+                        }
+                        let next = [next[0] as u32, next[1] as u32, next[2] as u32];
+                        if visited[index(next)] || !is_open(next) {
+                            continue;
+                        }
+                        visited[index(next)] = true;
+                        stack.push(next);
+                    }
+                }
+
+                for a in 0..6 {
+                    for b in 0..6 {
+                        if faces & (1 << a) != 0 && faces & (1 << b) != 0 {
+                            visibility.connect(a, b);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    visibility
+}
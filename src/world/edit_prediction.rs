@@ -0,0 +1,230 @@
+use ahash::AHashMap;
+use common::chunks::{
+    block_position::{BlockPosition, BlockPositionTrait},
+    chunk_data::BlockDataInfo,
+    chunk_position::ChunkPosition,
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Local edits are rolled back if the server is silent for this long
+pub const EDIT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct PendingEdit {
+    // The last state confirmed by the server
+    confirmed: Option<BlockDataInfo>,
+    predicted: Option<BlockDataInfo>,
+
+    last_sent_at: Instant,
+
+    // Requests sent for this position and not answered yet
+    pending: u32,
+}
+
+/// What must be done with the server edit
+#[derive(Debug, PartialEq)]
+pub enum ServerEditResult {
+    /// There are no local edits at this position
+    Apply,
+    /// The server agrees with the prediction or other requests are still in flight
+    Skip,
+    /// The server disagrees; local state must be replaced
+    Correct,
+}
+
+/// Journal of the block edits applied locally before server confirmation
+#[derive(Default)]
+pub struct PendingEdits {
+    edits: AHashMap<BlockPosition, PendingEdit>,
+
+    // Send times in the order of sending;
+    // the entry is outdated if the edit was sent again or answered
+    expiry: VecDeque<(Instant, BlockPosition)>,
+}
+
+impl PendingEdits {
+    /// `previous` is the block before the local edit
+    pub fn add(&mut self, position: BlockPosition, previous: Option<BlockDataInfo>, predicted: Option<BlockDataInfo>) {
+        self.add_at(position, previous, predicted, Instant::now());
+    }
+
+    fn add_at(
+        &mut self,
+        position: BlockPosition,
+        previous: Option<BlockDataInfo>,
+        predicted: Option<BlockDataInfo>,
+        now: Instant,
+    ) {
+        self.expiry.push_back((now, position.clone()));
+        match self.edits.get_mut(&position) {
+            Some(edit) => {
+                // Keep the confirmed state of the first edit for the rollback
+                edit.predicted = predicted;
+                edit.last_sent_at = now;
+                edit.pending += 1;
+            }
+            None => {
+                self.edits.insert(
+                    position,
+                    PendingEdit {
+                        confirmed: previous,
+                        predicted,
+                        last_sent_at: now,
+                        pending: 1,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn reconcile(&mut self, position: &BlockPosition, server_block: &Option<BlockDataInfo>) -> ServerEditResult {
+        let Some(edit) = self.edits.get_mut(position) else {
+            return ServerEditResult::Apply;
+        };
+
+        edit.confirmed = server_block.clone();
+        edit.pending = edit.pending.saturating_sub(1);
+        if edit.pending > 0 {
+            return ServerEditResult::Skip;
+        }
+
+        let edit = self.edits.remove(position).unwrap();
+        if edit.predicted == *server_block {
+            ServerEditResult::Skip
+        } else {
+            ServerEditResult::Correct
+        }
+    }
+
+    /// Returns positions and confirmed blocks of edits the server never answered
+    pub fn take_expired(&mut self) -> Vec<(BlockPosition, Option<BlockDataInfo>)> {
+        self.take_expired_at(Instant::now())
+    }
+
+    fn take_expired_at(&mut self, now: Instant) -> Vec<(BlockPosition, Option<BlockDataInfo>)> {
+        let mut expired: Vec<(BlockPosition, Option<BlockDataInfo>)> = Default::default();
+        while let Some((sent_at, _)) = self.expiry.front() {
+            if now.duration_since(*sent_at) < EDIT_CONFIRM_TIMEOUT {
+                break;
+            }
+            let (sent_at, position) = self.expiry.pop_front().unwrap();
+            let is_actual = match self.edits.get(&position) {
+                Some(edit) => edit.last_sent_at == sent_at,
+                None => false,
+            };
+            if is_actual {
+                let edit = self.edits.remove(&position).unwrap();
+                expired.push((position, edit.confirmed));
+            }
+        }
+        expired
+    }
+
+    /// Chunk data from the server already contains the actual blocks
+    pub fn remove_chunk(&mut self, chunk_position: &ChunkPosition) {
+        self.edits
+            .retain(|position, _edit| position.get_chunk_position() != *chunk_position);
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> Option<BlockDataInfo> {
+        Some(BlockDataInfo::create(id, None))
+    }
+
+    fn expire_all(edits: &mut PendingEdits) -> Vec<(BlockPosition, Option<BlockDataInfo>)> {
+        edits.take_expired_at(Instant::now() + EDIT_CONFIRM_TIMEOUT)
+    }
+
+    #[test]
+    fn apply_without_prediction() {
+        let mut edits = PendingEdits::default();
+        let result = edits.reconcile(&BlockPosition::new(1, 2, 3), &block(1));
+        assert_eq!(result, ServerEditResult::Apply);
+    }
+
+    #[test]
+    fn confirm() {
+        let mut edits = PendingEdits::default();
+        edits.add(BlockPosition::new(1, 2, 3), None, block(1));
+        assert_eq!(edits.len(), 1);
+
+        let result = edits.reconcile(&BlockPosition::new(1, 2, 3), &block(1));
+        assert_eq!(result, ServerEditResult::Skip);
+        assert!(edits.is_empty());
+        assert!(expire_all(&mut edits).is_empty());
+    }
+
+    #[test]
+    fn reject() {
+        let mut edits = PendingEdits::default();
+        edits.add(BlockPosition::new(1, 2, 3), None, block(1));
+
+        let result = edits.reconcile(&BlockPosition::new(1, 2, 3), &None);
+        assert_eq!(result, ServerEditResult::Correct);
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn answer_of_the_first_request_is_skipped() {
+        let mut edits = PendingEdits::default();
+        edits.add(BlockPosition::new(1, 2, 3), None, block(1));
+        edits.add(BlockPosition::new(1, 2, 3), block(1), None);
+
+        let result = edits.reconcile(&BlockPosition::new(1, 2, 3), &block(1));
+        assert_eq!(result, ServerEditResult::Skip);
+        assert_eq!(edits.len(), 1);
+
+        let result = edits.reconcile(&BlockPosition::new(1, 2, 3), &None);
+        assert_eq!(result, ServerEditResult::Skip);
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn expiry() {
+        let mut edits = PendingEdits::default();
+        edits.add(BlockPosition::new(1, 2, 3), block(5), block(1));
+        edits.add(BlockPosition::new(4, 5, 6), None, block(2));
+        edits.reconcile(&BlockPosition::new(4, 5, 6), &block(2));
+
+        assert!(edits.take_expired().is_empty());
+
+        let expired = expire_all(&mut edits);
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].0 == BlockPosition::new(1, 2, 3));
+        assert!(expired[0].1 == block(5));
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn expiry_of_the_resent_edit() {
+        let mut edits = PendingEdits::default();
+        let first_sent_at = Instant::now();
+        edits.add_at(BlockPosition::new(1, 2, 3), None, block(1), first_sent_at);
+        edits.add_at(
+            BlockPosition::new(1, 2, 3),
+            block(1),
+            None,
+            first_sent_at + Duration::from_secs(1),
+        );
+
+        // Only the first request is timed out
+        assert!(edits.take_expired_at(first_sent_at + EDIT_CONFIRM_TIMEOUT).is_empty());
+        assert_eq!(edits.len(), 1);
+
+        let expired = expire_all(&mut edits);
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].1.is_none());
+    }
+}
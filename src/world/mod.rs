@@ -3,3 +3,5 @@ pub mod world_manager;
 pub mod chunks;
 pub mod physics;
 pub mod block_storage;
+pub mod block_render;
+pub mod edit_prediction;
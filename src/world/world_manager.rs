@@ -1,6 +1,10 @@
 use super::{
     block_storage::BlockStorage,
-    chunks::chunks_map::ChunkMap,
+    chunks::{
+        chunks_map::ChunkMap,
+        mesh::{lod::ChunkLod, mesh_settings::ChunkMeshSettingsType},
+    },
+    edit_prediction::{PendingEdits, ServerEditResult},
     physics::PhysicsProxy,
     worlds_manager::{BlockStorageType, TextureMapperType, WorldMaterials},
 };
@@ -9,7 +13,7 @@ use crate::{
     scenes::main_scene::ResourceManagerType, utils::bridge::ChunkPositionGd,
 };
 use common::chunks::{
-    block_position::BlockPosition,
+    block_position::{BlockPosition, BlockPositionTrait},
     chunk_data::{BlockDataInfo, ChunkData},
     chunk_position::ChunkPosition,
 };
@@ -42,8 +46,12 @@ pub struct WorldManager {
     texture_mapper: TextureMapperType,
     materials: WorldMaterials,
     block_storage: BlockStorageType,
+    mesh_settings: ChunkMeshSettingsType,
 
     resource_manager: ResourceManagerType,
+
+    // Block edits applied locally and not confirmed by the server yet
+    pending_edits: PendingEdits,
 }
 
 impl WorldManager {
@@ -53,6 +61,7 @@ impl WorldManager {
         texture_mapper: TextureMapperType,
         materials: WorldMaterials,
         block_storage: BlockStorageType,
+        mesh_settings: ChunkMeshSettingsType,
         resource_manager: ResourceManagerType,
     ) -> Self {
         let physics = PhysicsProxy::default();
@@ -71,8 +80,11 @@ impl WorldManager {
             texture_mapper,
             materials,
             block_storage,
+            mesh_settings,
 
             resource_manager,
+
+            pending_edits: Default::default(),
         }
     }
 
@@ -92,6 +104,16 @@ impl WorldManager {
         &self.slug
     }
 
+    /// Mesh settings are changed; all chunks are meshed again
+    pub fn remesh_chunks(&self) {
+        self.chunk_map.bind().update_all_chunks_geometry();
+    }
+
+    /// Swaps the level of detail of columns around the player chunk
+    pub fn update_chunks_lod(&self, center: &ChunkPosition) {
+        self.chunk_map.bind().update_chunks_lod(center, &self.mesh_settings.read());
+    }
+
     pub fn get_chunk_map(&self) -> GdRef<'_, ChunkMap> {
         self.chunk_map.bind()
     }
@@ -102,13 +124,16 @@ impl WorldManager {
 
     /// Recieve chunk data from network
     pub fn recieve_chunk(&mut self, center: ChunkPosition, chunk_position: ChunkPosition, data: ChunkData) {
+        self.pending_edits.remove_chunk(&chunk_position);
+        let lod = ChunkLod::from_distance(chunk_position.get_distance(&center), &self.mesh_settings.read());
         self.chunk_map
             .bind_mut()
-            .create_chunk_column(center, chunk_position, data);
+            .create_chunk_column(center, chunk_position, data, lod);
     }
 
     /// Recieve chunk unloaded from network
     pub fn unload_chunk(&mut self, chunk_position: ChunkPosition) {
+        self.pending_edits.remove_chunk(&chunk_position);
         self.chunk_map.bind_mut().unload_chunk(chunk_position)
     }
 
@@ -123,6 +148,84 @@ impl WorldManager {
             .bind()
             .edit_block(position, block_storage, new_block_info, &self.physics, resource_storage)
     }
+
+    /// Applies player's edit before the server confirms it
+    ///
+    /// Returns false if the block can't be edited locally: the chunk is not loaded or the block is unknown
+    pub fn predict_edit_block(
+        &mut self,
+        position: BlockPosition,
+        block_storage: &BlockStorage,
+        new_block_info: Option<BlockDataInfo>,
+        resource_storage: &ResourceStorage,
+    ) -> bool {
+        let previous = {
+            let chunk_map = self.chunk_map.bind();
+            let Some(chunk_column) = chunk_map.get_chunk(&position.get_chunk_position()) else {
+                return false;
+            };
+            let c = chunk_column.read();
+            if !c.is_loaded() {
+                return false;
+            }
+            c.get_block_info(&position)
+        };
+
+        if let Some(info) = new_block_info.as_ref() {
+            if block_storage.get(&info.get_id()).is_none() {
+                return false;
+            }
+        }
+
+        if let Err(e) = self.edit_block(position.clone(), block_storage, new_block_info.clone(), resource_storage) {
+            log::error!(target: "world", "Block edit prediction error: {}", e);
+            return false;
+        }
+        self.chunk_map.bind().prioritize_player_edit(&position);
+        self.pending_edits.add(position, previous, new_block_info);
+        true
+    }
+
+    /// Authoritative block edit from the server
+    pub fn recieve_edit_block(
+        &mut self,
+        position: BlockPosition,
+        block_storage: &BlockStorage,
+        new_block_info: Option<BlockDataInfo>,
+        resource_storage: &ResourceStorage,
+    ) -> Result<(), String> {
+        match self.pending_edits.reconcile(&position, &new_block_info) {
+            ServerEditResult::Skip => Ok(()),
+            ServerEditResult::Apply | ServerEditResult::Correct => {
+                self.edit_block(position, block_storage, new_block_info, resource_storage)
+            }
+        }
+    }
+
+    /// Rolls back local edits which were not confirmed in time
+    fn rollback_expired_edits(&mut self) {
+        if self.pending_edits.is_empty() {
+            return;
+        }
+
+        let expired = self.pending_edits.take_expired();
+        if expired.is_empty() {
+            return;
+        }
+
+        let block_storage = self.block_storage.read();
+        let resource_manager = self.resource_manager.borrow();
+        let resource_storage = resource_manager.get_resources_storage();
+        for (position, confirmed) in expired {
+            log::warn!(target: "world", "Block edit at {:?} was not confirmed by the server; rollback", position);
+            if self.chunk_map.bind().get_chunk(&position.get_chunk_position()).is_none() {
+                continue;
+            }
+            if let Err(e) = self.edit_block(position, &block_storage, confirmed, &*resource_storage) {
+                log::error!(target: "world", "Block edit rollback error: {}", e);
+            }
+        }
+    }
 }
 
 #[godot_api]
@@ -170,20 +273,21 @@ impl INode for WorldManager {
             let _span = crate::span!("world_manager.custom_process::send_chunks_to_load");
 
             let map = self.chunk_map.bind();
-            map.send_chunks_to_load(
-                &self.materials,
-                self.texture_mapper.clone(),
-                self.block_storage.clone(),
-                &self.physics,
-                &*self.resource_manager.borrow(),
-            );
+            map.send_chunks_to_load(self.texture_mapper.clone(), self.block_storage.clone(), &self.mesh_settings);
+        }
+
+        {
+            let _span = crate::span!("world_manager.custom_process::rollback_expired_edits");
+            self.rollback_expired_edits();
         }
 
         let mut loaded_chunks = {
             let _span = crate::span!("world_manager.custom_process::spawn_loaded_chunks");
 
+            let resource_manager = self.resource_manager.borrow();
+            let resources_storage = resource_manager.get_resources_storage();
             let mut map = self.chunk_map.bind_mut();
-            map.spawn_loaded_chunks(&self.physics)
+            map.spawn_loaded_chunks(&self.materials, &self.block_storage.read(), &self.physics, &resources_storage)
         };
         let loaded_chunks_gd: Vec<Gd<ChunkPositionGd>> = loaded_chunks
             .drain(..)
@@ -191,13 +295,30 @@ impl INode for WorldManager {
             .collect();
         self.signals().chunk_loeded().emit(loaded_chunks_gd);
 
+        let camera = self.base().get_viewport().and_then(|v| v.get_camera_3d());
+
         {
             let _span = crate::span!("world_manager.custom_process::update_chunks_geometry");
 
-            let bs = self.block_storage.read();
-            let tm = self.texture_mapper.read();
             let map = self.chunk_map.bind();
-            map.update_chunks_geometry(&self.physics, &bs, &tm);
+            map.update_chunks_geometry(
+                &self.physics,
+                self.texture_mapper.clone(),
+                self.block_storage.clone(),
+                &self.mesh_settings,
+                camera.as_ref().map(|c| c.get_global_position()),
+            );
+        }
+
+        {
+            let _span = crate::span!("world_manager.custom_process::update_sections_culling");
+
+            if let Some(camera) = camera {
+                let frustum: Vec<Plane> = camera.get_frustum().iter_shared().collect();
+                let map = self.chunk_map.bind();
+                map.update_sections_culling(camera.get_global_position(), &frustum);
+                map.update_translucent_sorting(camera.get_global_position());
+            }
         }
     }
 }
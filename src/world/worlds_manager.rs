@@ -1,12 +1,13 @@
-use godot::classes::base_material_3d::TextureParam;
-use godot::classes::StandardMaterial3D;
+use godot::classes::{Shader, ShaderMaterial};
 use godot::prelude::*;
 use godot::{classes::Material, prelude::Gd};
 use parking_lot::lock_api::{RwLockReadGuard, RwLockWriteGuard};
+use ahash::AHashMap;
 use parking_lot::RwLock;
 use std::sync::Arc;
 
 use super::block_storage::BlockStorage;
+use super::chunks::mesh::mesh_settings::{ChunkMeshSettings, ChunkMeshSettingsType};
 use super::world_manager::{WorldManager, NEAR_DISTANCE};
 use crate::client_scripts::resource_manager::ResourceStorage;
 use crate::controller::entity_movement::EntityMovement;
@@ -14,22 +15,36 @@ use crate::controller::player_controller::PlayerController;
 use crate::scenes::components::block_mesh_storage::BlockMeshStorage;
 use crate::scenes::main_scene::ResourceManagerType;
 use crate::utils::bridge::{ChunkPositionGd, IntoChunkPositionVector};
+use crate::utils::textures::terrain_material::{create_liquid_material, create_terrain_material, set_terrain_texture};
 use crate::utils::textures::texture_mapper::TextureMapper;
+use common::chunks::chunk_position::ChunkPosition;
 
 pub type TextureMapperType = Arc<RwLock<TextureMapper>>;
 pub type BlockStorageType = Arc<RwLock<BlockStorage>>;
 
+/// Chunks around the spawn which must be meshed before the player is released
+const SPAWN_AREA_RADIUS: i64 = 2;
+
+/// How many chunk columns paused worlds can keep in total
+const DEFAULT_PAUSED_CHUNKS_BUDGET: u32 = 2048;
+
 #[derive(Clone)]
 pub struct WorldMaterials {
     material_3d_id: InstanceId,
     material_3d_transparent_id: InstanceId,
+    material_3d_liquid_id: InstanceId,
 }
 
 impl WorldMaterials {
-    pub fn create(material_3d: Gd<Material>, material_3d_transparent: Gd<Material>) -> Self {
+    pub fn create(
+        material_3d: Gd<Material>,
+        material_3d_transparent: Gd<Material>,
+        material_3d_liquid: Gd<Material>,
+    ) -> Self {
         Self {
             material_3d_id: material_3d.instance_id(),
             material_3d_transparent_id: material_3d_transparent.instance_id(),
+            material_3d_liquid_id: material_3d_liquid.instance_id(),
         }
     }
 
@@ -42,6 +57,11 @@ impl WorldMaterials {
         let material: Gd<Material> = Gd::from_instance_id(self.material_3d_transparent_id);
         material
     }
+
+    pub fn get_material_3d_liquid(&self) -> Gd<Material> {
+        let material: Gd<Material> = Gd::from_instance_id(self.material_3d_liquid_id);
+        material
+    }
 }
 
 #[derive(GodotClass)]
@@ -49,9 +69,23 @@ impl WorldMaterials {
 pub struct WorldsManager {
     base: Base<Node>,
 
-    world: Option<Gd<WorldManager>>,
+    worlds: AHashMap<String, Gd<WorldManager>>,
+
+    // Active world is in the tree, so it's rendered and its physics is stepped
+    active_world: Option<String>,
+
+    // Paused worlds keep their chunks; the most recently used is the last
+    paused_worlds: Vec<String>,
+
+    #[init(val = DEFAULT_PAUSED_CHUNKS_BUDGET)]
+    #[export]
+    paused_chunks_budget: u32,
+
     player_controller: Option<Gd<PlayerController>>,
 
+    // Player stays frozen until the spawn area is loaded
+    spawn_locked: bool,
+
     pub(crate) resource_manager: Option<ResourceManagerType>,
 
     #[init(val = Arc::new(RwLock::new(Default::default())))]
@@ -60,11 +94,25 @@ pub struct WorldsManager {
     #[init(val = Arc::new(RwLock::new(Default::default())))]
     block_storage: BlockStorageType,
 
+    #[init(val = Arc::new(RwLock::new(Default::default())))]
+    mesh_settings: ChunkMeshSettingsType,
+
+    /// Replaces the built-in terrain shader; must have the same uniforms
     #[export]
-    terrain_material: Option<Gd<StandardMaterial3D>>,
+    terrain_shader: Option<Gd<Shader>>,
 
     #[export]
-    terrain_material_transparent: Option<Gd<StandardMaterial3D>>,
+    terrain_shader_transparent: Option<Gd<Shader>>,
+
+    // Shared by all worlds; the atlas texture is replaced on textures build
+    #[init(val = create_terrain_material(false))]
+    terrain_material: Gd<ShaderMaterial>,
+
+    #[init(val = create_terrain_material(true))]
+    terrain_material_transparent: Gd<ShaderMaterial>,
+
+    #[init(val = create_liquid_material())]
+    terrain_material_liquid: Gd<ShaderMaterial>,
 
     block_mesh_storage: Option<Gd<BlockMeshStorage>>,
 }
@@ -78,13 +126,14 @@ impl WorldsManager {
 
         texture_mapper.clear();
 
-        let image_texture = match texture_mapper.build(&*block_storage, resources_storage) {
+        let terrain_textures = match texture_mapper.build(&*block_storage, resources_storage) {
             Ok(i) => i,
             Err(e) => return Err(e),
         };
 
-        let material_3d = self.terrain_material.as_mut().expect("terrain_material is not set");
-        material_3d.set_texture(TextureParam::ALBEDO, &image_texture);
+        set_terrain_texture(&mut self.terrain_material, &terrain_textures);
+        set_terrain_texture(&mut self.terrain_material_transparent, &terrain_textures);
+        set_terrain_texture(&mut self.terrain_material_liquid, &terrain_textures);
 
         log::info!(target: "main", "Textures builded successfily; texture blocks:&7{}&r textures loaded:&7{}&r &8(executed:{:.2?})", block_storage.textures_blocks_count(), texture_mapper.len(), now.elapsed());
         return Ok(());
@@ -99,6 +148,7 @@ impl WorldsManager {
             BlockMeshStorage::init(
                 &*self.get_block_storage(),
                 &self.get_materials(),
+                &self.mesh_settings.read(),
                 &self.get_resource_manager(),
                 &*self.get_texture_mapper(),
             )
@@ -122,24 +172,58 @@ impl WorldsManager {
         self.block_storage.write()
     }
 
+    pub fn set_mesh_settings(&mut self, mesh_settings: ChunkMeshSettings) {
+        if *self.mesh_settings.read() == mesh_settings {
+            return;
+        }
+        *self.mesh_settings.write() = mesh_settings;
+
+        let player_chunk = self
+            .player_controller
+            .as_ref()
+            .map(|p| p.bind().get_position().to_chunk_position());
+        for world in self.worlds.values() {
+            let w = world.bind();
+            if let Some(player_chunk) = player_chunk.as_ref() {
+                w.update_chunks_lod(player_chunk);
+            }
+            w.remesh_chunks();
+        }
+    }
+
     pub fn get_texture_mapper(&self) -> RwLockReadGuard<'_, parking_lot::RawRwLock, TextureMapper> {
         self.texture_mapper.read()
     }
 
+    /// Returns the active world
     pub fn get_world(&self) -> Option<&Gd<WorldManager>> {
-        match self.world.as_ref() {
-            Some(w) => Some(&w),
+        match self.active_world.as_ref() {
+            Some(slug) => self.worlds.get(slug),
             None => None,
         }
     }
 
+    /// Returns the active world
     pub fn get_world_mut(&mut self) -> Option<&mut Gd<WorldManager>> {
-        match self.world.as_mut() {
-            Some(w) => Some(w),
+        match self.active_world.as_ref() {
+            Some(slug) => self.worlds.get_mut(slug),
             None => None,
         }
     }
 
+    /// Returns active or paused world
+    pub fn get_world_by_slug(&self, world_slug: &String) -> Option<&Gd<WorldManager>> {
+        self.worlds.get(world_slug)
+    }
+
+    pub fn get_world_by_slug_mut(&mut self, world_slug: &String) -> Option<&mut Gd<WorldManager>> {
+        self.worlds.get_mut(world_slug)
+    }
+
+    pub fn is_active_world(&self, world_slug: &String) -> bool {
+        self.active_world.as_ref() == Some(world_slug)
+    }
+
     pub fn get_player_controller(&self) -> &Option<Gd<PlayerController>> {
         &self.player_controller
     }
@@ -154,29 +238,63 @@ impl WorldsManager {
         });
 
         self.base_mut().add_child(&player_controller.clone());
+        player_controller.bind().set_frozen(true);
+        self.spawn_locked = true;
 
         self.player_controller = Some(player_controller.clone());
         player_controller
     }
 
+    /// Returns count of loaded and total chunks around the player
+    pub fn get_spawn_area_progress(&self) -> Option<(usize, usize)> {
+        let world = self.get_world()?;
+        let player_controller = self.player_controller.as_ref()?;
+        let center = player_controller.bind().get_position().to_chunk_position();
+
+        let world = world.bind();
+        let chunk_map = world.get_chunk_map();
+        let (mut loaded, mut total) = (0, 0);
+        for x in -SPAWN_AREA_RADIUS..=SPAWN_AREA_RADIUS {
+            for z in -SPAWN_AREA_RADIUS..=SPAWN_AREA_RADIUS {
+                let chunk_position = ChunkPosition::new(center.x + x, center.z + z);
+                if chunk_position.get_distance(&center) > SPAWN_AREA_RADIUS as f32 {
+                    continue;
+                }
+                total += 1;
+                if let Some(c) = chunk_map.get_chunk(&chunk_position) {
+                    if c.read().is_loaded() {
+                        loaded += 1;
+                    }
+                }
+            }
+        }
+        Some((loaded, total))
+    }
+
+    /// Spawn area is loaded; player can move
+    pub fn unlock_spawn(&mut self) {
+        self.spawn_locked = false;
+        if let Some(player_controller) = self.player_controller.as_ref() {
+            player_controller.bind().set_frozen(false);
+        }
+    }
+
     pub fn get_materials(&self) -> WorldMaterials {
-        let material_3d = self
-            .terrain_material
-            .as_ref()
-            .expect("Terrain StandardMaterial3D is not set")
-            .clone();
-        let material_3d_transparent = self
-            .terrain_material_transparent
-            .as_ref()
-            .expect("Terrain StandardMaterial3D is not set")
-            .clone();
         WorldMaterials::create(
-            material_3d.upcast::<Material>(),
-            material_3d_transparent.upcast::<Material>(),
+            self.terrain_material.clone().upcast::<Material>(),
+            self.terrain_material_transparent.clone().upcast::<Material>(),
+            self.terrain_material_liquid.clone().upcast::<Material>(),
         )
     }
 
     pub fn create_world(&mut self, world_slug: String) -> Gd<WorldManager> {
+        if self.active_world.is_some() {
+            self.pause_active_world();
+        }
+        if self.worlds.contains_key(&world_slug) {
+            self.destroy_paused_world(&world_slug);
+        }
+
         let now = std::time::Instant::now();
 
         let mut world = Gd::<WorldManager>::from_init_fn(|base| {
@@ -189,6 +307,7 @@ impl WorldsManager {
                 self.texture_mapper.clone(),
                 self.get_materials(),
                 self.block_storage.clone(),
+                self.mesh_settings.clone(),
                 resource_manager.clone(),
             )
         });
@@ -208,29 +327,107 @@ impl WorldsManager {
                 .connect_other(&self.to_gd(), Self::chunk_loeded);
         }
 
-        self.world = Some(world.clone());
+        self.worlds.insert(world_slug.clone(), world.clone());
+        self.active_world = Some(world_slug.clone());
 
-        log::info!(target: "world", "World &a\"{}\"&r created; &8(executed:{:.2?})", self.world.as_ref().unwrap().bind().get_slug(), now.elapsed());
+        log::info!(target: "world", "World &a\"{}\"&r created; &8(executed:{:.2?})", world_slug, now.elapsed());
 
         world
     }
 
+    /// Makes the paused world active again
+    ///
+    /// Returns None if there is no such paused world
+    pub fn resume_world(&mut self, world_slug: &String) -> Option<Gd<WorldManager>> {
+        let index = self.paused_worlds.iter().position(|s| s == world_slug)?;
+        if self.active_world.is_some() {
+            self.pause_active_world();
+        }
+        self.paused_worlds.remove(index);
+
+        let world = self.worlds.get(world_slug).expect("paused world is not found").clone();
+        self.base_mut().add_child(&world);
+        self.active_world = Some(world_slug.clone());
+
+        log::info!(target: "world", "World &a\"{}\"&r resumed; chunks:{}", world_slug, world.bind().get_chunk_map().get_loaded_chunks_count());
+        Some(world)
+    }
+
+    /// Removes the active world from the tree keeping its chunks
+    fn pause_active_world(&mut self) {
+        let Some(world_slug) = self.active_world.take() else {
+            return;
+        };
+        let mut world = self.worlds.get(&world_slug).expect("active world is not found").clone();
+
+        // Entities are streamed again when the player returns
+        world.bind_mut().get_entities_manager_mut().despawn_all();
+        self.base_mut().remove_child(&world);
+        self.destroy_player();
+
+        log::info!(target: "world", "World &a\"{}\"&r paused", world_slug);
+        self.paused_worlds.push(world_slug);
+        self.evict_paused_worlds();
+    }
+
+    /// Destroys least recently used paused worlds which don't fit the budget
+    fn evict_paused_worlds(&mut self) {
+        let mut paused_chunks: u32 = self
+            .paused_worlds
+            .iter()
+            .map(|slug| self.worlds[slug].bind().get_chunk_map().get_loaded_chunks_count() as u32)
+            .sum();
+
+        while paused_chunks > self.paused_chunks_budget && !self.paused_worlds.is_empty() {
+            let world_slug = self.paused_worlds[0].clone();
+            paused_chunks -= self.worlds[&world_slug].bind().get_chunk_map().get_loaded_chunks_count() as u32;
+            log::info!(target: "world", "Paused world &a\"{}\"&r doesn't fit the chunks budget {}", world_slug, self.paused_chunks_budget);
+            self.destroy_paused_world(&world_slug);
+        }
+    }
+
+    fn destroy_paused_world(&mut self, world_slug: &String) {
+        self.paused_worlds.retain(|s| s != world_slug);
+        if let Some(mut world) = self.worlds.remove(world_slug) {
+            world.queue_free();
+            log::info!(target: "world", "World &a\"{}\"&r destroyed", world_slug);
+        }
+    }
+
+    fn destroy_player(&mut self) {
+        if let Some(mut player_controller) = self.player_controller.take() {
+            player_controller.bind_mut().remove_collider();
+            self.base_mut().remove_child(&player_controller);
+            player_controller.queue_free();
+        }
+    }
+
+    /// Destroys the active world
     pub fn destroy_world(&mut self) {
         let now = std::time::Instant::now();
 
-        let mut base = self.base_mut().clone();
-
-        let Some(world) = self.world.as_mut().take() else {
+        let Some(world_slug) = self.active_world.take() else {
             panic!("destroy_world: world is not exists");
         };
-        let world_slug = world.bind().get_slug().clone();
-        base.remove_child(&world.clone());
+        let Some(mut world) = self.worlds.remove(&world_slug) else {
+            panic!("destroy_world: world is not exists");
+        };
+        self.base_mut().remove_child(&world);
+        world.queue_free();
 
-        if let Some(player_controller) = self.player_controller.as_mut().take() {
-            base.remove_child(&player_controller.clone());
-        }
+        self.destroy_player();
         log::info!(target: "world", "World &a\"{}\"&r destroyed; &8(executed:{:.2?})", world_slug, now.elapsed());
     }
+
+    /// Destroys active and paused worlds
+    pub fn destroy_all_worlds(&mut self) {
+        if self.active_world.is_some() {
+            self.destroy_world();
+        }
+        for world_slug in self.paused_worlds.clone() {
+            self.destroy_paused_world(&world_slug);
+        }
+    }
 }
 
 #[godot_api]
@@ -242,7 +439,10 @@ impl WorldsManager {
 
         let _span = crate::span!("worlds_manager.handler_player_move");
 
-        let world = self.world.as_ref().unwrap().bind();
+        let Some(world) = self.get_world() else {
+            return;
+        };
+        let world = world.bind();
 
         let chunk_map = world.get_chunk_map();
 
@@ -253,7 +453,7 @@ impl WorldsManager {
                 Some(c) => c.read().is_loaded(),
                 None => false,
             };
-            player_controller.bind().set_frozen(!chunk_loaded);
+            player_controller.bind().set_frozen(!chunk_loaded || self.spawn_locked);
         }
 
         if new_chunk {
@@ -264,11 +464,15 @@ impl WorldsManager {
 
                 chunk_column.update_collider_group(is_near);
             }
+            world.update_chunks_lod(&new_chunk);
         }
     }
 
     #[func]
     pub fn chunk_loeded(&mut self, chunks: Vec<Gd<ChunkPositionGd>>) {
+        if self.spawn_locked {
+            return;
+        }
         if let Some(player_controller) = self.player_controller.as_ref() {
             let player_chunk_position = {
                 let pc = player_controller.bind();
@@ -288,4 +492,13 @@ impl WorldsManager {
 }
 
 #[godot_api]
-impl INode for WorldsManager {}
+impl INode for WorldsManager {
+    fn ready(&mut self) {
+        if let Some(shader) = self.terrain_shader.as_ref() {
+            self.terrain_material.set_shader(shader);
+        }
+        if let Some(shader) = self.terrain_shader_transparent.as_ref() {
+            self.terrain_material_transparent.set_shader(shader);
+        }
+    }
+}